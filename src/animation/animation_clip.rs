@@ -0,0 +1,24 @@
+use super::keyframe_track::KeyframeTrack;
+
+/// A named set of tracks played together, e.g. a walk cycle.
+#[derive(Clone)]
+pub struct AnimationClip {
+    pub name: String,
+    /// seconds, the time of the last keyframe of any track
+    pub duration: f32,
+    pub tracks: Vec<KeyframeTrack>,
+}
+
+impl AnimationClip {
+    pub fn new(name: &str, tracks: Vec<KeyframeTrack>) -> AnimationClip {
+        let duration = tracks
+            .iter()
+            .map(|track| track.duration())
+            .fold(0.0, f32::max);
+        AnimationClip {
+            name: name.to_string(),
+            duration,
+            tracks,
+        }
+    }
+}
@@ -0,0 +1,673 @@
+use super::animation_clip::AnimationClip;
+use super::keyframe_track::TrackProperty;
+use crate::object3d::Object3D;
+use std::collections::HashMap;
+use std::rc::Rc;
+extern crate gmath;
+use gmath::quat;
+
+/// What happens when an action reaches the end of its clip.
+#[derive(Clone, Copy, PartialEq)]
+pub enum LoopMode {
+    /// plays the clip once and stops on its last frame
+    Once,
+    /// starts over from the beginning
+    Repeat,
+    /// plays the clip backwards and forwards in turn
+    PingPong,
+}
+
+/// How an action combines with the other actions animating the same properties.
+#[derive(Clone, Copy, PartialEq)]
+pub enum BlendMode {
+    /// weighted average with the other normal actions
+    Normal,
+    /// adds the difference between the current frame and the clip's first frame on
+    /// top of the normal actions, e.g. a head turn over a walk cycle
+    Additive,
+}
+
+/// Notifications sent to the mixer's listeners during `update`.
+pub enum AnimationEvent {
+    /// an action started another pass through its clip
+    Loop {
+        clip: Rc<AnimationClip>,
+        /// index of the pass that just started
+        loop_count: i32,
+    },
+    /// an action made all its passes and stopped
+    Finished { clip: Rc<AnimationClip> },
+}
+
+type Listener = Box<dyn FnMut(&AnimationEvent)>;
+
+/// A weight ramp, run on mixer time.
+struct Fade {
+    from: f32,
+    to: f32,
+    elapsed: f32,
+    duration: f32,
+}
+
+impl Fade {
+    fn factor(&self) -> f32 {
+        if self.duration <= 0.0 {
+            return self.to;
+        }
+        let t = (self.elapsed / self.duration).min(1.0);
+        self.from + (self.to - self.from) * t
+    }
+}
+
+/// The playback state of one clip in a mixer.
+pub struct AnimationAction {
+    clip: Rc<AnimationClip>,
+    /// seconds of clip time played since the action started, across loops
+    pub time: f32,
+    /// speed of the action, negative values play it backwards
+    pub time_scale: f32,
+    pub loop_mode: LoopMode,
+    /// passes through the clip before the action stops, `None` for endless loops.
+    /// Ignored by `LoopMode::Once`
+    pub repetitions: Option<u32>,
+    pub paused: bool,
+    /// influence of the action, 0.0 to 1.0, before fades are applied
+    pub weight: f32,
+    pub blend_mode: BlendMode,
+    /// keep showing the last frame once finished instead of letting go of the nodes
+    pub clamp_when_finished: bool,
+    running: bool,
+    finished: bool,
+    fade: Option<Fade>,
+}
+
+impl AnimationAction {
+    pub fn new(clip: Rc<AnimationClip>) -> AnimationAction {
+        AnimationAction {
+            clip,
+            time: 0.0,
+            time_scale: 1.0,
+            loop_mode: LoopMode::Repeat,
+            repetitions: None,
+            paused: false,
+            weight: 1.0,
+            blend_mode: BlendMode::Normal,
+            clamp_when_finished: false,
+            running: false,
+            finished: false,
+            fade: None,
+        }
+    }
+
+    pub fn clip(&self) -> &Rc<AnimationClip> {
+        &self.clip
+    }
+
+    pub fn play(&mut self) -> &mut Self {
+        self.running = true;
+        self.finished = false;
+        self
+    }
+
+    /// Stops the action and rewinds it.
+    pub fn stop(&mut self) -> &mut Self {
+        self.running = false;
+        self.fade = None;
+        self.reset()
+    }
+
+    pub fn reset(&mut self) -> &mut Self {
+        self.time = 0.0;
+        self.paused = false;
+        self.finished = false;
+        self
+    }
+
+    /// Whether the action is playing, i.e. started, not paused and not finished.
+    pub fn is_running(&self) -> bool {
+        self.running && !self.paused
+    }
+
+    /// Ramps the weight up from zero over `duration` seconds.
+    pub fn fade_in(&mut self, duration: f32) -> &mut Self {
+        self.fade = Some(Fade {
+            from: 0.0,
+            to: 1.0,
+            elapsed: 0.0,
+            duration,
+        });
+        self
+    }
+
+    /// Ramps the weight down to zero over `duration` seconds, then stops the action.
+    pub fn fade_out(&mut self, duration: f32) -> &mut Self {
+        let from = self.fade.as_ref().map(Fade::factor).unwrap_or(1.0);
+        self.fade = Some(Fade {
+            from,
+            to: 0.0,
+            elapsed: 0.0,
+            duration,
+        });
+        self
+    }
+
+    /// `weight` scaled by the current fade.
+    pub fn effective_weight(&self) -> f32 {
+        self.weight * self.fade.as_ref().map(Fade::factor).unwrap_or(1.0)
+    }
+
+    /// Whether the action poses its nodes this frame.
+    fn is_active(&self) -> bool {
+        (self.is_running() || (self.finished && self.clamp_when_finished))
+            && self.effective_weight() > 0.0
+    }
+
+    /// Number of passes the action makes before it stops.
+    fn passes(&self) -> Option<u32> {
+        match self.loop_mode {
+            LoopMode::Once => Some(1),
+            _ => self.repetitions,
+        }
+    }
+
+    /// Index of the pass `time` falls in.
+    fn pass(&self, time: f32) -> i32 {
+        let duration = self.clip.duration;
+        if duration <= 0.0 {
+            return 0;
+        }
+        // the end of the last pass belongs to that pass, not the next one
+        let end = self.passes().map(|passes| passes as f32 * duration);
+        if Some(time) == end {
+            return (time / duration).floor() as i32 - 1;
+        }
+        (time / duration).floor() as i32
+    }
+
+    /// Advances the action by `delta` seconds of mixer time, stopping it once it has
+    /// made all its passes or faded out.
+    fn advance(&mut self, delta: f32, events: &mut Vec<AnimationEvent>) {
+        if let Some(fade) = &mut self.fade {
+            fade.elapsed += delta;
+            if fade.elapsed >= fade.duration {
+                let to = fade.to;
+                self.fade = None;
+                if to <= 0.0 {
+                    self.stop();
+                    return;
+                }
+            }
+        }
+
+        let previous_pass = self.pass(self.time);
+        self.time += delta * self.time_scale;
+        let duration = self.clip.duration;
+        if let Some(passes) = self.passes() {
+            let end = passes as f32 * duration;
+            if self.time >= end || self.time < 0.0 {
+                self.time = self.time.max(0.0).min(end);
+                self.running = false;
+                self.finished = true;
+                events.push(AnimationEvent::Finished {
+                    clip: self.clip.clone(),
+                });
+                return;
+            }
+        }
+        let pass = self.pass(self.time);
+        if pass != previous_pass {
+            events.push(AnimationEvent::Loop {
+                clip: self.clip.clone(),
+                loop_count: pass,
+            });
+        }
+    }
+
+    /// Position in the clip matching `time`.
+    pub fn clip_time(&self) -> f32 {
+        let duration = self.clip.duration;
+        if duration <= 0.0 {
+            return 0.0;
+        }
+        let pass = self.pass(self.time);
+        let offset = self.time - pass as f32 * duration;
+        match self.loop_mode {
+            LoopMode::PingPong if pass.rem_euclid(2) != 0 => duration - offset,
+            _ => offset,
+        }
+    }
+}
+
+/// Weighted sum of the values every action gives one property of one node.
+struct Accumulator {
+    /// weighted average of the normal actions
+    value: Vec<f32>,
+    weight: f32,
+    /// combined offset of the additive actions
+    additive: Vec<f32>,
+}
+
+impl Accumulator {
+    fn new(property: TrackProperty, size: usize) -> Accumulator {
+        let additive = match property {
+            TrackProperty::Rotation => quat::new_identity::<f32>().to_vec(),
+            _ => vec![0.0; size],
+        };
+        Accumulator {
+            value: vec![0.0; size],
+            weight: 0.0,
+            additive,
+        }
+    }
+
+    fn accumulate(&mut self, property: TrackProperty, value: &[f32], weight: f32) {
+        // running average, each new value is mixed in by its share of the total weight
+        let t = weight / (self.weight + weight);
+        mix(property, &mut self.value, value, t);
+        self.weight += weight;
+    }
+
+    fn accumulate_additive(
+        &mut self,
+        property: TrackProperty,
+        value: &[f32],
+        reference: &[f32],
+        weight: f32,
+    ) {
+        match property {
+            TrackProperty::Rotation => {
+                // the rotation from the reference frame to the current one
+                let mut inverse = quat::new_identity();
+                quat::inv(&mut inverse, &as_quat(reference));
+                let mut delta = quat::new_identity();
+                quat::mul(&mut delta, &inverse, &as_quat(value));
+                let mut scaled = quat::new_identity();
+                quat::slerp(&mut scaled, &quat::new_identity(), &delta, &weight);
+                let mut additive = quat::new_identity();
+                quat::mul(&mut additive, &as_quat(&self.additive), &scaled);
+                self.additive.copy_from_slice(&additive);
+            }
+            _ => {
+                for i in 0..self.additive.len().min(value.len()) {
+                    self.additive[i] += (value[i] - reference[i]) * weight;
+                }
+            }
+        }
+    }
+
+    /// The blended value, falling back on `original` for the weight the normal
+    /// actions leave unused.
+    fn result(&self, property: TrackProperty, original: &[f32]) -> Vec<f32> {
+        let mut value = original.to_vec();
+        value.resize(self.value.len(), 0.0);
+        if self.weight > 0.0 {
+            mix(property, &mut value, &self.value, self.weight.min(1.0));
+        }
+        match property {
+            TrackProperty::Rotation => {
+                let mut rotated = quat::new_identity();
+                quat::mul(&mut rotated, &as_quat(&value), &as_quat(&self.additive));
+                value.copy_from_slice(&rotated);
+            }
+            _ => {
+                for (v, offset) in value.iter_mut().zip(self.additive.iter()) {
+                    *v += offset;
+                }
+            }
+        }
+        value
+    }
+}
+
+fn as_quat(values: &[f32]) -> [f32; 4] {
+    [values[0], values[1], values[2], values[3]]
+}
+
+/// Moves `out` towards `value` by `t`, slerping rotations.
+fn mix(property: TrackProperty, out: &mut [f32], value: &[f32], t: f32) {
+    if property == TrackProperty::Rotation {
+        let mut mixed = quat::new_identity();
+        quat::slerp(&mut mixed, &as_quat(out), &as_quat(value), &t);
+        out.copy_from_slice(&mixed);
+    } else {
+        for (out, value) in out.iter_mut().zip(value.iter()) {
+            *out += (value - *out) * t;
+        }
+    }
+}
+
+/// Plays animation clips on a scene graph. Several actions can animate the same
+/// nodes at once, their poses are blended by weight.
+pub struct AnimationMixer {
+    actions: Vec<AnimationAction>,
+    /// seconds of mixer time elapsed
+    pub time: f32,
+    /// speed of every action of the mixer
+    pub time_scale: f32,
+    /// the values nodes had before the mixer first animated them, blended in
+    /// where the actions' weights add up to less than one
+    original: HashMap<(usize, TrackProperty), Vec<f32>>,
+    listeners: Vec<Listener>,
+}
+
+impl Default for AnimationMixer {
+    fn default() -> AnimationMixer {
+        AnimationMixer::new()
+    }
+}
+
+impl AnimationMixer {
+    pub fn new() -> AnimationMixer {
+        AnimationMixer {
+            actions: Vec::new(),
+            time: 0.0,
+            time_scale: 1.0,
+            original: HashMap::new(),
+            listeners: Vec::new(),
+        }
+    }
+
+    /// The action playing `clip`, created the first time the clip is asked for.
+    pub fn clip_action(&mut self, clip: &Rc<AnimationClip>) -> &mut AnimationAction {
+        let index = match self
+            .actions
+            .iter()
+            .position(|action| Rc::ptr_eq(&action.clip, clip))
+        {
+            Some(index) => index,
+            None => {
+                self.actions.push(AnimationAction::new(clip.clone()));
+                self.actions.len() - 1
+            }
+        };
+        &mut self.actions[index]
+    }
+
+    /// The action of the clip called `name`, if one was created.
+    pub fn existing_action(&mut self, name: &str) -> Option<&mut AnimationAction> {
+        self.actions
+            .iter_mut()
+            .find(|action| action.clip.name == name)
+    }
+
+    pub fn stop_all_action(&mut self) {
+        for action in self.actions.iter_mut() {
+            action.stop();
+        }
+    }
+
+    /// Fades `from` out and `to` in over `duration` seconds, `to` starts from its
+    /// beginning.
+    pub fn cross_fade(&mut self, from: &Rc<AnimationClip>, to: &Rc<AnimationClip>, duration: f32) {
+        self.clip_action(from).fade_out(duration);
+        self.clip_action(to).reset().play().fade_in(duration);
+    }
+
+    /// Calls `listener` for every loop and finish of the mixer's actions.
+    pub fn add_event_listener<F: FnMut(&AnimationEvent) + 'static>(&mut self, listener: F) {
+        self.listeners.push(Box::new(listener));
+    }
+
+    /// Advances every running action by `delta` seconds and poses the nodes of
+    /// `root` they animate.
+    pub fn update(&mut self, delta: f32, root: &mut Object3D) {
+        let delta = delta * self.time_scale;
+        self.time += delta;
+
+        let mut events = Vec::new();
+        let mut accumulators: HashMap<(usize, TrackProperty), Accumulator> = HashMap::new();
+        for action in self.actions.iter_mut() {
+            if action.is_running() {
+                // the frame an action finishes on still shows its last pose
+                action.advance(delta, &mut events);
+            }
+            if !action.is_active() && !action.finished {
+                continue;
+            }
+            let weight = action.effective_weight();
+            if weight <= 0.0 {
+                continue;
+            }
+            let time = action.clip_time();
+            for track in action.clip.tracks.iter() {
+                let size = track.value_size();
+                let mut value = vec![0.0; size];
+                track.sample(time, &mut value);
+                let accumulator = accumulators
+                    .entry((track.node, track.property))
+                    .or_insert_with(|| Accumulator::new(track.property, size));
+                match action.blend_mode {
+                    BlendMode::Normal => accumulator.accumulate(track.property, &value, weight),
+                    BlendMode::Additive => {
+                        let mut reference = vec![0.0; size];
+                        track.sample(0.0, &mut reference);
+                        accumulator.accumulate_additive(track.property, &value, &reference, weight);
+                    }
+                }
+            }
+            // a finished action only shows its last pose once unless clamped
+            if action.finished && !action.clamp_when_finished {
+                action.finished = false;
+            }
+        }
+
+        if !accumulators.is_empty() {
+            let original = &mut self.original;
+            root.traverse_mut(&mut |object| {
+                for &property in &[
+                    TrackProperty::Translation,
+                    TrackProperty::Rotation,
+                    TrackProperty::Scale,
+                    TrackProperty::MorphWeights,
+                ] {
+                    let key = (object.id(), property);
+                    if let Some(accumulator) = accumulators.get(&key) {
+                        let original = original
+                            .entry(key)
+                            .or_insert_with(|| read(object, property));
+                        let value = accumulator.result(property, original);
+                        apply(object, property, &value);
+                    }
+                }
+            });
+        }
+
+        for event in events.iter() {
+            for listener in self.listeners.iter_mut() {
+                listener(event);
+            }
+        }
+    }
+}
+
+fn read(object: &mut Object3D, property: TrackProperty) -> Vec<f32> {
+    match property {
+        TrackProperty::Translation => object.position.to_vec(),
+        TrackProperty::Rotation => object.rotation.to_vec(),
+        TrackProperty::Scale => object.scale.to_vec(),
+        TrackProperty::MorphWeights => object
+            .mesh()
+            .map(|mesh| mesh.morph_target_influences.clone())
+            .unwrap_or_default(),
+    }
+}
+fn apply(object: &mut Object3D, property: TrackProperty, value: &[f32]) {
+    match property {
+        TrackProperty::Translation => object.position.copy_from_slice(&value[..3]),
+        TrackProperty::Rotation => object.rotation.copy_from_slice(&value[..4]),
+        TrackProperty::Scale => object.scale.copy_from_slice(&value[..3]),
+        TrackProperty::MorphWeights => {
+            if let Some(mesh) = object.mesh_mut() {
+                mesh.morph_target_influences.clear();
+                mesh.morph_target_influences.extend_from_slice(value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::keyframe_track::{Interpolation, KeyframeTrack};
+    use std::cell::RefCell;
+
+    /// A one second clip moving `node` linearly from `from` to `to`.
+    fn translation(node: &Object3D, from: [f32; 3], to: [f32; 3]) -> Rc<AnimationClip> {
+        let mut values = from.to_vec();
+        values.extend_from_slice(&to);
+        let track = KeyframeTrack::new(
+            node.id(),
+            TrackProperty::Translation,
+            vec![0.0, 1.0],
+            values,
+            Interpolation::Linear,
+        );
+        Rc::new(AnimationClip::new("translation", vec![track]))
+    }
+
+    fn assert_near(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-5, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn blends_actions_by_weight() {
+        let mut node = Object3D::group();
+        let a = translation(&node, [4.0, 0.0, 0.0], [4.0, 0.0, 0.0]);
+        let b = translation(&node, [0.0, 8.0, 0.0], [0.0, 8.0, 0.0]);
+        let mut mixer = AnimationMixer::new();
+        mixer.clip_action(&a).play().weight = 0.25;
+        mixer.clip_action(&b).play().weight = 0.75;
+        mixer.update(0.0, &mut node);
+        assert_near(&node.position, &[1.0, 6.0, 0.0]);
+    }
+
+    #[test]
+    fn keeps_original_value_for_unused_weight() {
+        let mut node = Object3D::group();
+        node.position = [2.0, 2.0, 2.0];
+        let clip = translation(&node, [4.0, 0.0, 0.0], [4.0, 0.0, 0.0]);
+        let mut mixer = AnimationMixer::new();
+        mixer.clip_action(&clip).play().weight = 0.5;
+        mixer.update(0.0, &mut node);
+        assert_near(&node.position, &[3.0, 1.0, 1.0]);
+        // the original is remembered, not the blended pose of the last frame
+        mixer.update(0.0, &mut node);
+        assert_near(&node.position, &[3.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn cross_fades_between_actions() {
+        let mut node = Object3D::group();
+        let walk = translation(&node, [10.0, 0.0, 0.0], [10.0, 0.0, 0.0]);
+        let run = translation(&node, [0.0, 10.0, 0.0], [0.0, 10.0, 0.0]);
+        let mut mixer = AnimationMixer::new();
+        mixer.clip_action(&walk).play();
+        mixer.update(0.0, &mut node);
+        assert_near(&node.position, &[10.0, 0.0, 0.0]);
+
+        mixer.cross_fade(&walk, &run, 1.0);
+        mixer.update(0.25, &mut node);
+        assert_near(&[mixer.clip_action(&walk).effective_weight()], &[0.75]);
+        assert_near(&node.position, &[7.5, 2.5, 0.0]);
+
+        mixer.update(1.0, &mut node);
+        assert!(!mixer.clip_action(&walk).is_running());
+        assert!(mixer.clip_action(&run).is_running());
+        assert_near(&node.position, &[0.0, 10.0, 0.0]);
+    }
+
+    #[test]
+    fn adds_additive_actions_on_top() {
+        let mut node = Object3D::group();
+        let base = translation(&node, [1.0, 0.0, 0.0], [1.0, 0.0, 0.0]);
+        let bob = translation(&node, [0.0, 0.0, 0.0], [0.0, 2.0, 0.0]);
+        let mut mixer = AnimationMixer::new();
+        mixer.clip_action(&base).play();
+        let action = mixer.clip_action(&bob);
+        action.blend_mode = BlendMode::Additive;
+        action.weight = 0.5;
+        action.play();
+        // half way the additive clip is 1 above its first frame, at half weight
+        mixer.update(0.5, &mut node);
+        assert_near(&node.position, &[1.0, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn adds_additive_rotations() {
+        let mut node = Object3D::group();
+        let quarter_turn = std::f32::consts::FRAC_PI_4;
+        let track = KeyframeTrack::new(
+            node.id(),
+            TrackProperty::Rotation,
+            vec![0.0, 1.0],
+            vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, quarter_turn.sin(), quarter_turn.cos()],
+            Interpolation::Linear,
+        );
+        let clip = Rc::new(AnimationClip::new("turn", vec![track]));
+        let mut mixer = AnimationMixer::new();
+        let action = mixer.clip_action(&clip);
+        action.blend_mode = BlendMode::Additive;
+        action.play();
+        mixer.update(0.5, &mut node);
+        let eighth_turn = std::f32::consts::PI / 8.0;
+        assert_near(&node.rotation, &[0.0, 0.0, eighth_turn.sin(), eighth_turn.cos()]);
+    }
+
+    #[test]
+    fn sends_loop_and_finish_events() {
+        let mut node = Object3D::group();
+        let clip = translation(&node, [0.0, 0.0, 0.0], [10.0, 0.0, 0.0]);
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut mixer = AnimationMixer::new();
+        let log = events.clone();
+        mixer.add_event_listener(move |event| {
+            log.borrow_mut().push(match event {
+                AnimationEvent::Loop { loop_count, .. } => format!("loop {}", loop_count),
+                AnimationEvent::Finished { clip } => format!("finished {}", clip.name),
+            })
+        });
+        let action = mixer.clip_action(&clip);
+        action.repetitions = Some(2);
+        action.play();
+
+        mixer.update(0.6, &mut node);
+        assert!(events.borrow().is_empty());
+        mixer.update(0.6, &mut node);
+        assert_eq!(*events.borrow(), vec!["loop 1"]);
+        assert_near(&node.position, &[2.0, 0.0, 0.0]);
+        mixer.update(0.9, &mut node);
+        assert_eq!(*events.borrow(), vec!["loop 1", "finished translation"]);
+        // the frame it finishes on shows the end of the last pass
+        assert_near(&node.position, &[10.0, 0.0, 0.0]);
+        assert!(!mixer.clip_action(&clip).is_running());
+    }
+
+    #[test]
+    fn once_clamps_to_last_frame() {
+        let mut node = Object3D::group();
+        let clip = translation(&node, [0.0, 0.0, 0.0], [10.0, 0.0, 0.0]);
+        let mut mixer = AnimationMixer::new();
+        let action = mixer.clip_action(&clip);
+        action.loop_mode = LoopMode::Once;
+        action.clamp_when_finished = true;
+        action.play();
+        mixer.update(1.5, &mut node);
+        assert_near(&node.position, &[10.0, 0.0, 0.0]);
+        node.position = [0.0, 0.0, 0.0];
+        mixer.update(0.1, &mut node);
+        assert_near(&node.position, &[10.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn ping_pong_plays_backwards() {
+        let mut node = Object3D::group();
+        let clip = translation(&node, [0.0, 0.0, 0.0], [10.0, 0.0, 0.0]);
+        let mut mixer = AnimationMixer::new();
+        mixer.clip_action(&clip).play().loop_mode = LoopMode::PingPong;
+        mixer.update(1.25, &mut node);
+        assert_near(&node.position, &[7.5, 0.0, 0.0]);
+    }
+}
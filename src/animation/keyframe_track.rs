@@ -0,0 +1,282 @@
+extern crate gmath;
+use gmath::quat;
+
+/// How values are computed between two keyframes.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    /// holds the value of the previous keyframe
+    Step,
+    /// lerp, slerp for rotations
+    Linear,
+    /// cubic Hermite spline, every keyframe stores an in-tangent, a value and an
+    /// out-tangent
+    CubicSpline,
+}
+
+/// The node field a track animates.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TrackProperty {
+    /// `Object3D::position`
+    Translation,
+    /// `Object3D::rotation`, an xyzw quaternion
+    Rotation,
+    /// `Object3D::scale`
+    Scale,
+    /// `Mesh::morph_target_influences` of the node's mesh
+    MorphWeights,
+}
+
+/// Keyframes of one property of one node.
+#[derive(Clone)]
+pub struct KeyframeTrack {
+    /// `Object3D::id` of the animated node
+    pub node: usize,
+    pub property: TrackProperty,
+    /// keyframe times in seconds, increasing
+    pub times: Vec<f32>,
+    /// `value_size` floats per keyframe, three times that for cubic splines
+    pub values: Vec<f32>,
+    pub interpolation: Interpolation,
+}
+
+impl KeyframeTrack {
+    pub fn new(
+        node: usize,
+        property: TrackProperty,
+        times: Vec<f32>,
+        values: Vec<f32>,
+        interpolation: Interpolation,
+    ) -> KeyframeTrack {
+        KeyframeTrack {
+            node,
+            property,
+            times,
+            values,
+            interpolation,
+        }
+    }
+
+    /// Number of floats in one sampled value.
+    pub fn value_size(&self) -> usize {
+        if self.times.is_empty() {
+            return 0;
+        }
+        let per_keyframe = self.values.len() / self.times.len();
+        match self.interpolation {
+            Interpolation::CubicSpline => per_keyframe / 3,
+            _ => per_keyframe,
+        }
+    }
+
+    /// Time of the last keyframe.
+    pub fn duration(&self) -> f32 {
+        self.times.last().cloned().unwrap_or(0.0)
+    }
+
+    /// The value of keyframe `index`, skipping the tangents of cubic splines.
+    fn keyframe(&self, index: usize) -> &[f32] {
+        let size = self.value_size();
+        let start = match self.interpolation {
+            Interpolation::CubicSpline => (index * 3 + 1) * size,
+            _ => index * size,
+        };
+        &self.values[start..start + size]
+    }
+
+    /// Writes the value at `time` to `out`, which holds `value_size` floats. Times
+    /// outside the keyframes clamp to the first or last one.
+    pub fn sample(&self, time: f32, out: &mut [f32]) {
+        let size = self.value_size();
+        let last = match self.times.len() {
+            0 => return,
+            count => count - 1,
+        };
+        if time <= self.times[0] {
+            out.copy_from_slice(self.keyframe(0));
+            return;
+        }
+        if time >= self.times[last] {
+            out.copy_from_slice(self.keyframe(last));
+            return;
+        }
+
+        // first keyframe after `time`
+        let next = self.times.iter().position(|&t| t > time).unwrap_or(last);
+        let previous = next - 1;
+        let delta = self.times[next] - self.times[previous];
+        let t = (time - self.times[previous]) / delta;
+
+        match self.interpolation {
+            Interpolation::Step => out.copy_from_slice(self.keyframe(previous)),
+            Interpolation::Linear => {
+                let (a, b) = (self.keyframe(previous), self.keyframe(next));
+                if self.property == TrackProperty::Rotation {
+                    let a = [a[0], a[1], a[2], a[3]];
+                    let b = [b[0], b[1], b[2], b[3]];
+                    let mut rotation = quat::new_identity();
+                    quat::slerp(&mut rotation, &a, &b, &t);
+                    out.copy_from_slice(&rotation);
+                } else {
+                    for i in 0..size {
+                        out[i] = a[i] + (b[i] - a[i]) * t;
+                    }
+                }
+            }
+            Interpolation::CubicSpline => {
+                let t2 = t * t;
+                let t3 = t2 * t;
+                let s0 = 2.0 * t3 - 3.0 * t2 + 1.0;
+                let s1 = t3 - 2.0 * t2 + t;
+                let s2 = -2.0 * t3 + 3.0 * t2;
+                let s3 = t3 - t2;
+
+                let p0 = previous * 3 * size;
+                let p1 = next * 3 * size;
+                for (i, out) in out.iter_mut().enumerate().take(size) {
+                    let value0 = self.values[p0 + size + i];
+                    let out_tangent0 = self.values[p0 + 2 * size + i];
+                    let in_tangent1 = self.values[p1 + i];
+                    let value1 = self.values[p1 + size + i];
+                    *out = s0 * value0
+                        + s1 * out_tangent0 * delta
+                        + s2 * value1
+                        + s3 * in_tangent1 * delta;
+                }
+                if self.property == TrackProperty::Rotation {
+                    normalize(out);
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn normalize(values: &mut [f32]) {
+    let length = values.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if length > 0.0 {
+        for value in values.iter_mut() {
+            *value /= length;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(track: &KeyframeTrack, time: f32) -> Vec<f32> {
+        let mut out = vec![0.0; track.value_size()];
+        track.sample(time, &mut out);
+        out
+    }
+
+    fn assert_near(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-5, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn step_holds_previous_keyframe() {
+        let track = KeyframeTrack::new(
+            0,
+            TrackProperty::Translation,
+            vec![0.0, 1.0, 2.0],
+            vec![0.0, 0.0, 0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0],
+            Interpolation::Step,
+        );
+        assert_eq!(track.value_size(), 3);
+        assert_eq!(track.duration(), 2.0);
+        assert_near(&sample(&track, 0.99), &[0.0, 0.0, 0.0]);
+        assert_near(&sample(&track, 1.0), &[1.0, 2.0, 3.0]);
+        assert_near(&sample(&track, 1.5), &[1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn clamps_outside_keyframes() {
+        let track = KeyframeTrack::new(
+            0,
+            TrackProperty::Scale,
+            vec![1.0, 2.0],
+            vec![1.0, 1.0, 1.0, 3.0, 3.0, 3.0],
+            Interpolation::Linear,
+        );
+        assert_near(&sample(&track, -1.0), &[1.0, 1.0, 1.0]);
+        assert_near(&sample(&track, 5.0), &[3.0, 3.0, 3.0]);
+    }
+
+    #[test]
+    fn linear_lerps_vectors() {
+        let track = KeyframeTrack::new(
+            0,
+            TrackProperty::Translation,
+            vec![0.0, 2.0],
+            vec![0.0, 0.0, 0.0, 2.0, 4.0, -6.0],
+            Interpolation::Linear,
+        );
+        assert_near(&sample(&track, 0.5), &[0.5, 1.0, -1.5]);
+    }
+
+    #[test]
+    fn linear_slerps_rotations() {
+        // from no rotation to a quarter turn around z, quaternions hold the
+        // sine and cosine of half the angle
+        let quarter_turn = std::f32::consts::FRAC_PI_4;
+        let track = KeyframeTrack::new(
+            0,
+            TrackProperty::Rotation,
+            vec![0.0, 1.0],
+            vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, quarter_turn.sin(), quarter_turn.cos()],
+            Interpolation::Linear,
+        );
+        // an eighth turn, not the normalized average of the two
+        let eighth_turn = std::f32::consts::PI / 8.0;
+        assert_near(&sample(&track, 0.5), &[0.0, 0.0, eighth_turn.sin(), eighth_turn.cos()]);
+        let twelfth_turn = std::f32::consts::PI / 12.0;
+        assert_near(
+            &sample(&track, 1.0 / 3.0),
+            &[0.0, 0.0, twelfth_turn.sin(), twelfth_turn.cos()],
+        );
+    }
+
+    #[test]
+    fn cubic_spline_uses_tangents() {
+        // in-tangent, value, out-tangent per keyframe
+        let track = KeyframeTrack::new(
+            0,
+            TrackProperty::MorphWeights,
+            vec![0.0, 2.0],
+            vec![0.0, 0.0, 3.0, 1.0, 2.0, 0.0],
+            Interpolation::CubicSpline,
+        );
+        assert_eq!(track.value_size(), 1);
+        // at t = 0.5 the Hermite basis is 0.5, 0.125, 0.5, -0.125 and the
+        // tangents are scaled by the 2 seconds between the keyframes
+        assert_near(&sample(&track, 1.0), &[0.125 * 3.0 * 2.0 + 0.5 * 2.0 - 0.125 * 2.0]);
+        // t = 0.25: 0.84375, 0.140625, 0.15625, -0.046875
+        assert_near(
+            &sample(&track, 0.5),
+            &[0.140625 * 3.0 * 2.0 + 0.15625 * 2.0 - 0.046875 * 2.0],
+        );
+        // the ends are the values, not the tangents
+        assert_near(&sample(&track, 0.0), &[0.0]);
+        assert_near(&sample(&track, 2.0), &[2.0]);
+    }
+
+    #[test]
+    fn cubic_spline_normalizes_rotations() {
+        let track = KeyframeTrack::new(
+            0,
+            TrackProperty::Rotation,
+            vec![0.0, 1.0],
+            vec![
+                0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, //
+                0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+            ],
+            Interpolation::CubicSpline,
+        );
+        let rotation = sample(&track, 0.5);
+        let length = rotation.iter().map(|v| v * v).sum::<f32>().sqrt();
+        assert!((length - 1.0).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,3 @@
+pub mod animation_clip;
+pub mod animation_mixer;
+pub mod keyframe_track;
@@ -12,6 +12,7 @@ use self::cgmath::{
      Point3, Vector3,Matrix4
 };
 
+#[derive(Clone)]
 pub struct Camera {
     pub position: [f32; 3],
     pub up: [f32; 3],
@@ -46,9 +47,9 @@ impl Camera {
         }
 
         //1
-        let proj_matrix: [f32; 16] = get_projection(
+        let _proj_matrix: [f32; 16] = get_projection(
             40.0,
-            (dom_element.width() as f32 / dom_element.height() as f32) as f32,
+            dom_element.width() as f32 / dom_element.height() as f32,
             1.0,
             100.0,
         );
@@ -95,7 +96,7 @@ impl Camera {
         for x in mat4.iter() {
             for y in x.iter() {
                 new_mat[i] = *y;
-                i = i + 1;
+                i += 1;
             }
         }
 
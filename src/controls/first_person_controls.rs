@@ -23,7 +23,7 @@ pub struct FirstPersonControls {
 impl FirstPersonControls {
     pub fn new(dom_element: web_sys::HtmlCanvasElement) -> FirstPersonControls {
         let control = FirstPersonControls {
-            dom_element: dom_element,
+            dom_element,
             drag: Rc::new(RefCell::new(false)),
             key_code: Rc::new(RefCell::new(0)),
         };
@@ -75,7 +75,7 @@ impl FirstPersonControls {
         }
         {
             let key_code = control.key_code.clone();
-            let keyup_cb = Closure::wrap(Box::new(move |event: KeyboardEvent| {
+            let keyup_cb = Closure::wrap(Box::new(move |_event: KeyboardEvent| {
                 *key_code.borrow_mut() = 0;
             }) as Box<dyn FnMut(KeyboardEvent)>);
             web_sys::window()
@@ -110,7 +110,7 @@ impl FirstPersonControls {
             _ => (0, 0.0),
         };
         if *self.key_code.borrow() != 0 {
-            camera.position[move_info.0] = camera.position[move_info.0] + move_info.1;
+            camera.position[move_info.0] += move_info.1;
         }
 
         mat4::set_position(&mut camera.matrix, &camera.position);
@@ -33,7 +33,7 @@ pub struct OrbitControls {
 impl OrbitControls {
     pub fn new(dom_element: web_sys::HtmlCanvasElement) -> OrbitControls {
         let control = OrbitControls {
-            dom_element: dom_element,
+            dom_element,
             target: vec3::new_zero(),
             rotate_start: Rc::new(RefCell::new(vec2::new_zero())),
             rotate_end: Rc::new(RefCell::new(vec2::new_zero())),
@@ -56,7 +56,7 @@ impl OrbitControls {
                 *state.borrow_mut() = button;
 
                 vec2::set(
-                    &mut *rotate_start.borrow_mut(),
+                    &mut rotate_start.borrow_mut(),
                     event.client_x() as f32,
                     event.client_y() as f32,
                 );
@@ -84,14 +84,14 @@ impl OrbitControls {
             let mousemove_cb = Closure::wrap(Box::new(move |event: MouseEvent| {
                 if *state.borrow() == 0 {
                     vec2::set(
-                        &mut *rotate_end.borrow_mut(),
+                        &mut rotate_end.borrow_mut(),
                         event.client_x() as f32,
                         event.client_y() as f32,
                     );
                     vec2::sub(
-                        &mut *rotate_delta.borrow_mut(),
-                        &mut *rotate_end.borrow_mut(),
-                        &mut *rotate_start.borrow_mut(),
+                        &mut rotate_delta.borrow_mut(),
+                        &rotate_end.borrow_mut(),
+                        &rotate_start.borrow_mut(),
                     );
 
                     let r_delta = *rotate_delta.borrow();
@@ -105,7 +105,7 @@ impl OrbitControls {
 
                     let v_rotate_end = *rotate_end.borrow();
                     vec2::set(
-                        &mut *rotate_start.borrow_mut(),
+                        &mut rotate_start.borrow_mut(),
                         v_rotate_end[0],
                         v_rotate_end[1],
                     );
@@ -168,7 +168,7 @@ impl OrbitControls {
         control
     }
     pub fn update(&self, camera: &mut Camera) {
-        let LOG_NUM = "xxx";
+        const LOG_NUM: &str = "xxx";
 
         if LOG_NUM == "position" {
             log_num(camera.position[0] as f64);
@@ -209,10 +209,10 @@ impl OrbitControls {
         }
 
         let theta_delta = self.theta_delta.clone();
-        theta = theta + *theta_delta.borrow() as f64;
+        theta += *theta_delta.borrow() as f64;
 
         let phi_delta = self.phi_delta.clone();
-        phi = phi + *phi_delta.borrow() as f64;
+        phi += *phi_delta.borrow() as f64;
         if LOG_NUM == "theta_1" {
             log_num(theta);
         }
@@ -222,9 +222,9 @@ impl OrbitControls {
 
         // theta = theta + 0.005;
         // log_num(theta as f64);
-        let EPS: f32 = 0.0001;
+        const EPS: f32 = 0.0001;
 
-        phi = Math::max(EPS as f64, Math::min(((PI - EPS) as f64).into(), phi));
+        phi = Math::max(EPS as f64, Math::min((PI - EPS) as f64, phi));
         if LOG_NUM == "phi_2" {
             log_num(phi);
         }
@@ -236,7 +236,7 @@ impl OrbitControls {
         }
         // log_num(length as f64);
 
-        let radius = length * (*scala.borrow() as f32);
+        let radius = length * *scala.borrow();
         // radius = 11.0;
         if LOG_NUM == "radius" {
             log_num(radius as f64);
@@ -334,6 +334,6 @@ impl OrbitControls {
         *phi_delta.borrow_mut() = 0.0;
         *scala.borrow_mut() = 1.0;
 
-        let state = self.state.clone();
+        let _state = self.state.clone();
     }
 }
@@ -0,0 +1,87 @@
+use crate::loaders::resolver::ResourceError;
+use std::fmt;
+use wasm_bindgen::JsValue;
+
+/// Everything that can go wrong loading or drawing a scene.
+#[derive(Debug)]
+pub enum Error {
+    /// a model or material file is malformed
+    Parse(String),
+    /// a file the model refers to could not be fetched or read
+    MissingResource(ResourceError),
+    /// valid input the crate doesn't handle, e.g. a glTF triangle strip
+    Unsupported(String),
+    /// no WebGL context, e.g. the canvas is missing or WebGL is disabled
+    Context(String),
+    /// a shader failed to compile, with the driver's info log
+    Shader(String),
+    /// a program failed to link, or lacks an input the renderer sets
+    Link(String),
+    /// a buffer or texture could not be created
+    Allocation(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Parse(message) => write!(f, "parse error: {}", message),
+            Error::MissingResource(err) => err.fmt(f),
+            Error::Unsupported(message) => write!(f, "unsupported: {}", message),
+            Error::Context(message) => write!(f, "WebGL context: {}", message),
+            Error::Shader(log) => write!(f, "shader compilation failed: {}", log),
+            Error::Link(log) => write!(f, "program linking failed: {}", log),
+            Error::Allocation(what) => write!(f, "cannot create {}", what),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::MissingResource(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ResourceError> for Error {
+    fn from(err: ResourceError) -> Error {
+        Error::MissingResource(err)
+    }
+}
+
+impl From<wavefront_obj::ParseError> for Error {
+    fn from(err: wavefront_obj::ParseError) -> Error {
+        Error::Parse(format!("line {}: {}", err.line_number, err.message))
+    }
+}
+
+impl From<gltf::Error> for Error {
+    fn from(err: gltf::Error) -> Error {
+        match err {
+            gltf::Error::Io(err) => Error::MissingResource(ResourceError {
+                url: String::new(),
+                reason: err.to_string(),
+            }),
+            gltf::Error::UnsupportedImageEncoding | gltf::Error::UnsupportedScheme => {
+                Error::Unsupported(err.to_string())
+            }
+            gltf::Error::Validation(errors) => Error::Parse(
+                errors
+                    .iter()
+                    .map(|(path, err)| format!("{}: {}", path, err))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            err => Error::Parse(err.to_string()),
+        }
+    }
+}
+
+impl From<Error> for JsValue {
+    fn from(err: Error) -> JsValue {
+        js_sys::Error::new(&err.to_string()).into()
+    }
+}
@@ -1,5 +1,610 @@
+use crate::error::{Error, Result};
+use std::collections::HashMap;
+use web_sys::WebGlRenderingContext;
+
+/// Largest number of vertices a chunk can address with u16 indices.
+pub const MAX_U16_VERTICES: usize = 65536;
+
+#[derive(Clone)]
+pub enum Indices {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl Indices {
+    /// Narrows to u16 when every index fits, otherwise keeps u32.
+    pub fn from_u32(indices: Vec<u32>) -> Indices {
+        if indices.iter().all(|&index| (index as usize) < MAX_U16_VERTICES) {
+            Indices::U16(indices.into_iter().map(|index| index as u16).collect())
+        } else {
+            Indices::U32(indices)
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Indices::U16(indices) => indices.len(),
+            Indices::U32(indices) => indices.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, i: usize) -> usize {
+        match self {
+            Indices::U16(indices) => indices[i] as usize,
+            Indices::U32(indices) => indices[i] as usize,
+        }
+    }
+}
+
+impl From<Vec<u16>> for Indices {
+    fn from(indices: Vec<u16>) -> Self {
+        Indices::U16(indices)
+    }
+}
+
+impl From<Vec<u32>> for Indices {
+    fn from(indices: Vec<u32>) -> Self {
+        Indices::U32(indices)
+    }
+}
+
+/// Attribute names the built-in shaders read, loaders store their data under these.
+pub const POSITION: &str = "position";
+pub const NORMAL: &str = "normal";
+pub const TANGENT: &str = "tangent";
+pub const UV: &str = "uv";
+/// `COLOR_0` in glTF, named so it doesn't clash with the material's `color` uniform
+pub const COLOR: &str = "vertexColor";
+pub const SKIN_INDEX: &str = "skinIndex";
+pub const SKIN_WEIGHT: &str = "skinWeight";
+
+/// Per-vertex values of one attribute, in any type WebGL can feed to a float
+/// attribute.
+#[derive(Clone)]
+pub enum AttributeData {
+    F32(Vec<f32>),
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    I8(Vec<i8>),
+    I16(Vec<i16>),
+}
+
+impl AttributeData {
+    pub fn len(&self) -> usize {
+        match self {
+            AttributeData::F32(data) => data.len(),
+            AttributeData::U8(data) => data.len(),
+            AttributeData::U16(data) => data.len(),
+            AttributeData::I8(data) => data.len(),
+            AttributeData::I16(data) => data.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// `FLOAT`, `UNSIGNED_BYTE`, ... as passed to `vertexAttribPointer`.
+    pub fn component_type(&self) -> u32 {
+        match self {
+            AttributeData::F32(_) => WebGlRenderingContext::FLOAT,
+            AttributeData::U8(_) => WebGlRenderingContext::UNSIGNED_BYTE,
+            AttributeData::U16(_) => WebGlRenderingContext::UNSIGNED_SHORT,
+            AttributeData::I8(_) => WebGlRenderingContext::BYTE,
+            AttributeData::I16(_) => WebGlRenderingContext::SHORT,
+        }
+    }
+
+    /// Component `i` converted to a float, normalized like the GPU would.
+    pub fn get(&self, i: usize, normalized: bool) -> f32 {
+        let (value, max) = match self {
+            AttributeData::F32(data) => return data[i],
+            AttributeData::U8(data) => (data[i] as f32, 255.0),
+            AttributeData::U16(data) => (data[i] as f32, 65535.0),
+            AttributeData::I8(data) => (data[i] as f32, 127.0),
+            AttributeData::I16(data) => (data[i] as f32, 32767.0),
+        };
+        if normalized {
+            (value / max).max(-1.0)
+        } else {
+            value
+        }
+    }
+
+    fn empty_like(&self) -> AttributeData {
+        match self {
+            AttributeData::F32(_) => AttributeData::F32(Vec::new()),
+            AttributeData::U8(_) => AttributeData::U8(Vec::new()),
+            AttributeData::U16(_) => AttributeData::U16(Vec::new()),
+            AttributeData::I8(_) => AttributeData::I8(Vec::new()),
+            AttributeData::I16(_) => AttributeData::I16(Vec::new()),
+        }
+    }
+
+    /// Appends components `start..end` of `other`, which must hold the same type.
+    fn extend_from(&mut self, other: &AttributeData, start: usize, end: usize) -> Result<()> {
+        fn extend<T: Copy>(data: &mut Vec<T>, other: &[T], start: usize, end: usize) -> Result<()> {
+            let values = other.get(start..end).ok_or_else(|| {
+                Error::Parse(format!(
+                    "vertex data {}..{} is out of range of {} values",
+                    start,
+                    end,
+                    other.len()
+                ))
+            })?;
+            data.extend_from_slice(values);
+            Ok(())
+        }
+        match (self, other) {
+            (AttributeData::F32(data), AttributeData::F32(other)) => {
+                extend(data, other, start, end)
+            }
+            (AttributeData::U8(data), AttributeData::U8(other)) => extend(data, other, start, end),
+            (AttributeData::U16(data), AttributeData::U16(other)) => {
+                extend(data, other, start, end)
+            }
+            (AttributeData::I8(data), AttributeData::I8(other)) => extend(data, other, start, end),
+            (AttributeData::I16(data), AttributeData::I16(other)) => {
+                extend(data, other, start, end)
+            }
+            _ => Err(Error::Parse(String::from("mismatched attribute types"))),
+        }
+    }
+}
+
+impl From<Vec<f32>> for AttributeData {
+    fn from(data: Vec<f32>) -> Self {
+        AttributeData::F32(data)
+    }
+}
+
+impl From<Vec<u8>> for AttributeData {
+    fn from(data: Vec<u8>) -> Self {
+        AttributeData::U8(data)
+    }
+}
+
+impl From<Vec<u16>> for AttributeData {
+    fn from(data: Vec<u16>) -> Self {
+        AttributeData::U16(data)
+    }
+}
+
+impl From<Vec<i8>> for AttributeData {
+    fn from(data: Vec<i8>) -> Self {
+        AttributeData::I8(data)
+    }
+}
+
+impl From<Vec<i16>> for AttributeData {
+    fn from(data: Vec<i16>) -> Self {
+        AttributeData::I16(data)
+    }
+}
+
+#[derive(Clone)]
+pub struct Attribute {
+    pub data: AttributeData,
+    /// components per vertex, 1 to 4
+    pub item_size: usize,
+    /// integer data is mapped to 0..1 (or -1..1) instead of converted as is
+    pub normalized: bool,
+}
+
+impl Attribute {
+    pub fn new<D: Into<AttributeData>>(data: D, item_size: usize) -> Attribute {
+        Attribute {
+            data: data.into(),
+            item_size,
+            normalized: false,
+        }
+    }
+
+    pub fn normalized<D: Into<AttributeData>>(data: D, item_size: usize) -> Attribute {
+        Attribute {
+            normalized: true,
+            ..Attribute::new(data, item_size)
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.data.len() / self.item_size
+    }
+
+    /// Components of vertex `index` as floats.
+    pub fn get_item(&self, index: usize) -> Vec<f32> {
+        (index * self.item_size..(index + 1) * self.item_size)
+            .map(|i| self.data.get(i, self.normalized))
+            .collect()
+    }
+}
+
+/// Indexed triangles with any number of named vertex attributes. The renderer binds
+/// each attribute to the shader input of the same name.
+#[derive(Clone)]
 pub struct Geometry {
-    pub vertices: Vec<f32>,
-    pub indices: Vec<u16>, 
-    pub normals: Vec<f32>,
+    pub attributes: HashMap<String, Attribute>,
+    /// morph targets of `position` and `normal`, stored as offsets from the base
+    /// attribute, one entry per target
+    pub morph_attributes: HashMap<String, Vec<Attribute>>,
+    pub indices: Indices,
+}
+
+impl Geometry {
+    pub fn new(indices: Indices) -> Geometry {
+        Geometry {
+            attributes: HashMap::new(),
+            morph_attributes: HashMap::new(),
+            indices,
+        }
+    }
+
+    pub fn morph_target_count(&self) -> usize {
+        self.morph_attributes
+            .get(POSITION)
+            .map(|targets| targets.len())
+            .unwrap_or(0)
+    }
+
+    /// `name` with every morph target added in by its influence, as floats.
+    pub fn morphed_attribute(&self, name: &str, influences: &[f32]) -> Option<Attribute> {
+        let base = self.attribute(name)?;
+        let mut data: Vec<f32> = (0..base.data.len())
+            .map(|i| base.data.get(i, base.normalized))
+            .collect();
+        if let Some(targets) = self.morph_attributes.get(name) {
+            for (target, &influence) in targets.iter().zip(influences.iter()) {
+                if influence == 0.0 || target.data.len() != data.len() {
+                    continue;
+                }
+                for (i, value) in data.iter_mut().enumerate() {
+                    *value += target.data.get(i, target.normalized) * influence;
+                }
+            }
+        }
+        Some(Attribute::new(data, base.item_size))
+    }
+
+    pub fn set_attribute(&mut self, name: &str, attribute: Attribute) {
+        self.attributes.insert(name.to_string(), attribute);
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&Attribute> {
+        self.attributes.get(name)
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.attribute(POSITION)
+            .map(|position| position.count())
+            .unwrap_or(0)
+    }
+
+    /// Checks that every index refers to a vertex and that every attribute and
+    /// morph target has a value for each vertex. Loaders call it before handing
+    /// the geometry to `compute_vertex_normals`, `split` or the renderer.
+    pub fn validate(&self) -> Result<()> {
+        let vertex_count = self.vertex_count();
+        let check = |name: &str, attribute: &Attribute| {
+            if attribute.item_size == 0
+                || attribute.data.len() != vertex_count * attribute.item_size
+            {
+                return Err(Error::Parse(format!(
+                    "attribute `{}` has {} values for {} vertices of {} components",
+                    name,
+                    attribute.data.len(),
+                    vertex_count,
+                    attribute.item_size
+                )));
+            }
+            Ok(())
+        };
+        for (name, attribute) in self.attributes.iter() {
+            check(name, attribute)?;
+        }
+        for (name, targets) in self.morph_attributes.iter() {
+            for target in targets.iter() {
+                check(name, target)?;
+            }
+        }
+        if let Some(index) = (0..self.indices.len())
+            .map(|i| self.indices.get(i))
+            .find(|&index| index >= vertex_count)
+        {
+            return Err(Error::Parse(format!(
+                "index {} is out of range of {} vertices",
+                index, vertex_count
+            )));
+        }
+        Ok(())
+    }
+
+    /// Smooth normals averaged from the faces around each vertex, weighted by area.
+    /// The geometry must be valid, see `validate`.
+    pub fn compute_vertex_normals(&mut self) {
+        let position = match self.attribute(POSITION) {
+            Some(position) => position,
+            None => return,
+        };
+        let mut normals = vec![0.0f32; self.vertex_count() * 3];
+
+        let mut i = 0;
+        while i + 3 <= self.indices.len() {
+            let triangle = [
+                self.indices.get(i),
+                self.indices.get(i + 1),
+                self.indices.get(i + 2),
+            ];
+            let a = position.get_item(triangle[0]);
+            let b = position.get_item(triangle[1]);
+            let c = position.get_item(triangle[2]);
+            let ab = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+            let ac = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+            let face_normal = [
+                ab[1] * ac[2] - ab[2] * ac[1],
+                ab[2] * ac[0] - ab[0] * ac[2],
+                ab[0] * ac[1] - ab[1] * ac[0],
+            ];
+            for &index in triangle.iter() {
+                for axis in 0..3 {
+                    normals[index * 3 + axis] += face_normal[axis];
+                }
+            }
+            i += 3;
+        }
+
+        for normal in normals.chunks_mut(3) {
+            let length = (normal[0] * normal[0] + normal[1] * normal[1] + normal[2] * normal[2]).sqrt();
+            if length > 0.0 {
+                for component in normal.iter_mut() {
+                    *component /= length;
+                }
+            }
+        }
+        self.set_attribute(NORMAL, Attribute::new(normals, 3));
+    }
+
+    /// Partitions the primitives, `primitive_size` indices each, into chunks that
+    /// each reference at most `max_vertices` vertices, so every chunk can be drawn
+    /// with u16 indices. Fails on indices out of range of the attributes and with
+    /// `Error::Unsupported` when a primitive alone has more than `max_vertices`.
+    pub fn split(&self, max_vertices: usize, primitive_size: usize) -> Result<Vec<Geometry>> {
+        let vertex_count = self.vertex_count();
+        // attributes without a value for every vertex can't be remapped
+        let attributes: Vec<(&String, &Attribute)> = self
+            .attributes
+            .iter()
+            .filter(|(_, attribute)| attribute.count() == vertex_count)
+            .collect();
+        let morph_attributes: Vec<(&String, &Vec<Attribute>)> = self
+            .morph_attributes
+            .iter()
+            .filter(|(_, targets)| targets.iter().all(|target| target.count() == vertex_count))
+            .collect();
+        let empty_like = |attribute: &Attribute| Attribute {
+            data: attribute.data.empty_like(),
+            ..attribute.clone()
+        };
+        let new_chunk = || Geometry {
+            attributes: attributes
+                .iter()
+                .map(|(name, attribute)| (name.to_string(), empty_like(attribute)))
+                .collect(),
+            morph_attributes: morph_attributes
+                .iter()
+                .map(|(name, targets)| {
+                    (name.to_string(), targets.iter().map(empty_like).collect())
+                })
+                .collect(),
+            indices: Indices::U16(Vec::new()),
+        };
+        let mut chunks = Vec::new();
+
+        let mut remap: HashMap<usize, u16> = HashMap::new();
+        let mut chunk = new_chunk();
+        let mut indices = Vec::new();
+
+        let mut i = 0;
+        while i + primitive_size <= self.indices.len() {
+            let primitive: Vec<usize> = (i..i + primitive_size)
+                .map(|i| self.indices.get(i))
+                .collect();
+            // degenerate primitives repeat a vertex, count it once
+            let new_vertices = primitive
+                .iter()
+                .enumerate()
+                .filter(|&(n, index)| {
+                    !remap.contains_key(index) && !primitive[..n].contains(index)
+                })
+                .count();
+
+            if new_vertices > max_vertices {
+                return Err(Error::Unsupported(format!(
+                    "a primitive of {} vertices doesn't fit in chunks of {}",
+                    new_vertices, max_vertices
+                )));
+            }
+            if remap.len() + new_vertices > max_vertices {
+                let mut full = std::mem::replace(&mut chunk, new_chunk());
+                full.indices = Indices::U16(std::mem::take(&mut indices));
+                chunks.push(full);
+                remap.clear();
+            }
+
+            for &index in primitive.iter() {
+                let new_index = match remap.get(&index) {
+                    Some(&new_index) => new_index,
+                    None => {
+                        for (name, attribute) in attributes.iter() {
+                            let size = attribute.item_size;
+                            chunk.attributes.get_mut(*name).unwrap().data.extend_from(
+                                &attribute.data,
+                                index * size,
+                                index * size + size,
+                            )?;
+                        }
+                        for (name, targets) in morph_attributes.iter() {
+                            let chunk_targets = chunk.morph_attributes.get_mut(*name).unwrap();
+                            for (target, chunk_target) in
+                                targets.iter().zip(chunk_targets.iter_mut())
+                            {
+                                let size = target.item_size;
+                                chunk_target.data.extend_from(
+                                    &target.data,
+                                    index * size,
+                                    index * size + size,
+                                )?;
+                            }
+                        }
+                        let new_index = remap.len() as u16;
+                        remap.insert(index, new_index);
+                        new_index
+                    }
+                };
+                indices.push(new_index);
+            }
+            i += primitive_size;
+        }
+
+        if !indices.is_empty() {
+            chunk.indices = Indices::U16(indices);
+            chunks.push(chunk);
+        }
+
+        Ok(chunks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A geometry whose vertex `i` sits at `x = i`, so chunks can be mapped back.
+    fn numbered(vertex_count: usize, indices: Vec<u32>) -> Geometry {
+        let positions = (0..vertex_count)
+            .flat_map(|i| vec![i as f32, 0.0, 0.0])
+            .collect::<Vec<f32>>();
+        let mut geometry = Geometry::new(Indices::U32(indices));
+        geometry.set_attribute(POSITION, Attribute::new(positions, 3));
+        geometry
+    }
+
+    /// The primitives of every chunk, in the original vertex numbers.
+    fn primitives(chunks: &[Geometry], primitive_size: usize) -> Vec<Vec<usize>> {
+        let mut primitives = Vec::new();
+        for chunk in chunks {
+            let position = chunk.attribute(POSITION).unwrap();
+            let indices: Vec<usize> = (0..chunk.indices.len())
+                .map(|i| position.get_item(chunk.indices.get(i))[0] as usize)
+                .collect();
+            primitives.extend(
+                indices
+                    .chunks(primitive_size)
+                    .map(|primitive| primitive.to_vec()),
+            );
+        }
+        primitives
+    }
+
+    #[test]
+    fn splits_large_geometry_into_u16_chunks() {
+        let vertex_count = 70000;
+        // neighbouring triangles share vertices, some of them across chunks
+        let indices: Vec<u32> = (0..vertex_count as u32 - 2)
+            .flat_map(|i| vec![i, i + 1, i + 2])
+            .collect();
+        let mut geometry = numbered(vertex_count, indices.clone());
+        let uvs: Vec<u16> = (0..vertex_count * 2).map(|i| i as u16).collect();
+        geometry.set_attribute(UV, Attribute::normalized(uvs, 2));
+        geometry.morph_attributes.insert(
+            POSITION.to_string(),
+            vec![Attribute::new(vec![1.0f32; vertex_count * 3], 3)],
+        );
+
+        let chunks = geometry.split(MAX_U16_VERTICES, 3).unwrap();
+        assert!(chunks.len() > 1);
+        for chunk in chunks.iter() {
+            assert!(chunk.vertex_count() <= MAX_U16_VERTICES);
+            match chunk.indices {
+                Indices::U16(_) => {}
+                Indices::U32(_) => panic!("chunk with u32 indices"),
+            }
+            let uv = chunk.attribute(UV).unwrap();
+            assert!(uv.normalized);
+            assert_eq!(uv.count(), chunk.vertex_count());
+            assert_eq!(
+                chunk.morph_attributes[POSITION][0].count(),
+                chunk.vertex_count()
+            );
+        }
+
+        let expected: Vec<Vec<usize>> = indices
+            .chunks(3)
+            .map(|triangle| triangle.iter().map(|&index| index as usize).collect())
+            .collect();
+        assert_eq!(primitives(&chunks, 3), expected);
+    }
+
+    #[test]
+    fn splits_lines_without_breaking_segments() {
+        let indices = vec![0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 0];
+        let chunks = numbered(6, indices.clone()).split(3, 2).unwrap();
+        assert!(chunks.len() > 1);
+        for chunk in chunks.iter() {
+            assert!(chunk.vertex_count() <= 3);
+        }
+        let expected: Vec<Vec<usize>> = indices
+            .chunks(2)
+            .map(|line| line.iter().map(|&index| index as usize).collect())
+            .collect();
+        assert_eq!(primitives(&chunks, 2), expected);
+    }
+
+    #[test]
+    fn splits_points() {
+        let chunks = numbered(7, (0..7).collect()).split(3, 1).unwrap();
+        let sizes: Vec<usize> = chunks.iter().map(|chunk| chunk.vertex_count()).collect();
+        assert_eq!(sizes, vec![3, 3, 1]);
+        let expected: Vec<Vec<usize>> = (0..7).map(|index| vec![index]).collect();
+        assert_eq!(primitives(&chunks, 1), expected);
+    }
+
+    #[test]
+    fn validates_indices_and_attribute_counts() {
+        assert!(numbered(3, vec![0, 1, 2]).validate().is_ok());
+        assert!(numbered(3, vec![0, 1, 3]).validate().is_err());
+
+        let mut geometry = numbered(3, vec![0, 1, 2]);
+        geometry.set_attribute(NORMAL, Attribute::new(vec![0.0f32; 6], 3));
+        assert!(geometry.validate().is_err());
+
+        let mut geometry = numbered(3, vec![0, 1, 2]);
+        geometry.morph_attributes.insert(
+            POSITION.to_string(),
+            vec![Attribute::new(vec![0.0f32; 12], 3)],
+        );
+        assert!(geometry.validate().is_err());
+    }
+
+    #[test]
+    fn split_fails_on_index_out_of_range() {
+        let geometry = numbered(3, vec![0, 1, 5]);
+        assert!(geometry.split(MAX_U16_VERTICES, 3).is_err());
+    }
+
+    #[test]
+    fn split_refuses_primitives_larger_than_a_chunk() {
+        match numbered(3, vec![0, 1, 2]).split(2, 3) {
+            Err(Error::Unsupported(_)) => {}
+            Err(err) => panic!("expected unsupported, got {}", err),
+            Ok(chunks) => panic!("expected unsupported, got {} chunks", chunks.len()),
+        }
+        // a degenerate triangle only needs the vertices it references
+        let chunks = numbered(3, vec![0, 0, 1, 1, 2, 2]).split(2, 3).unwrap();
+        assert!(chunks.iter().all(|chunk| chunk.vertex_count() > 0));
+        assert_eq!(primitives(&chunks, 3), vec![vec![0, 0, 1], vec![1, 2, 2]]);
+    }
 }
@@ -0,0 +1,79 @@
+use crate::geometry::Geometry;
+use crate::material::Material;
+use crate::webgl_renderer::GeometryBuffers;
+extern crate gmath;
+use gmath::mat4;
+
+/// Many copies of one geometry/material pair, each with its own transform
+/// and (optionally) its own color, drawn with a single instanced draw call.
+/// Instance matrices are relative to the `Object3D` node holding the mesh.
+#[allow(non_snake_case)]
+pub struct InstancedMesh {
+    pub geometry: Geometry,
+    pub material: Material,
+    /// column-major instance matrices, 16 floats per instance
+    pub instance_matrices: Vec<f32>,
+    /// rgba instance colors, 4 floats per instance, multiplied with `material.color`
+    pub instance_colors: Option<Vec<f32>>,
+    pub needs_update: bool,
+    pub __webGLBuffers: Vec<GeometryBuffers>,
+    pub __webGLInstanceMatrixBuffer: Option<web_sys::WebGlBuffer>,
+    pub __webGLInstanceColorBuffer: Option<web_sys::WebGlBuffer>,
+}
+
+impl InstancedMesh {
+    pub fn new(geometry: Geometry, material: Material, count: usize) -> InstancedMesh {
+        let mut instance_matrices = Vec::with_capacity(count * 16);
+        for _ in 0..count {
+            instance_matrices.extend_from_slice(&mat4::new_identity());
+        }
+
+        InstancedMesh {
+            geometry,
+            material,
+            instance_matrices,
+            instance_colors: None,
+            needs_update: true,
+            __webGLBuffers: Vec::new(),
+            __webGLInstanceMatrixBuffer: None,
+            __webGLInstanceColorBuffer: None,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.instance_matrices.len() / 16
+    }
+
+    pub fn get_matrix_at(&self, index: usize) -> [f32; 16] {
+        let mut matrix = [0.0; 16];
+        matrix.copy_from_slice(&self.instance_matrices[index * 16..index * 16 + 16]);
+        matrix
+    }
+
+    pub fn set_matrix_at(&mut self, index: usize, matrix: &[f32; 16]) {
+        self.instance_matrices[index * 16..index * 16 + 16].copy_from_slice(matrix);
+        self.needs_update = true;
+    }
+
+    /// Sets the instance transform from a translation/scale/rotation triple.
+    pub fn compose_at(
+        &mut self,
+        index: usize,
+        position: &[f32; 3],
+        scale: &[f32; 3],
+        rotation: &[f32; 4],
+    ) {
+        let mut matrix = mat4::new_identity();
+        mat4::compose(&mut matrix, position, scale, rotation);
+        self.set_matrix_at(index, &matrix);
+    }
+
+    pub fn set_color_at(&mut self, index: usize, color: &[f32; 4]) {
+        let count = self.count();
+        let colors = self
+            .instance_colors
+            .get_or_insert_with(|| vec![1.0; count * 4]);
+        colors[index * 4..index * 4 + 4].copy_from_slice(color);
+        self.needs_update = true;
+    }
+}
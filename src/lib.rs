@@ -5,91 +5,137 @@ extern crate num_traits;
 extern crate wasm_bindgen;
 extern crate wavefront_obj;
 extern crate web_sys;
-use futures::{future, Future};
-use gmath::{mat4, quat, vec2, vec3};
-use js_sys::{Math,Promise};
+use futures::Future;
+use gmath::{quat, vec3};
+use js_sys::Math;
 use std::cell::RefCell;
-use std::f32::consts::PI;
 use std::rc::Rc;
 use wasm_bindgen::prelude::*;
-use wasm_bindgen_futures::{JsFuture,future_to_promise};
+use wasm_bindgen_futures::future_to_promise;
 
 #[allow(dead_code)]
 mod utils;
-use utils::{get_string_from_xhr,request_animation_frame};
+use utils::request_animation_frame;
 
-mod geometry;
-use geometry::Geometry;
+pub mod error;
 
-mod material;
-use material::Material;
+pub mod geometry;
+use geometry::{Attribute, Geometry};
 
-mod mesh;
+pub mod texture;
+
+pub mod material;
+use material::PhongMaterial;
+
+pub mod mesh;
 use mesh::Mesh;
 
-mod scene;
+pub mod instanced_mesh;
+use instanced_mesh::InstancedMesh;
+
+pub mod skeleton;
+
+pub mod skinned_mesh;
+
+pub mod light;
+use light::{AmbientLight, DirectionalLight};
+
+pub mod object3d;
+use object3d::Object3D;
+
+pub mod scene;
 use scene::Scene;
 
-mod camera;
+pub mod camera;
 use camera::Camera;
 
-mod webgl_renderer;
+mod program_info;
+
+mod shaders;
+
+pub mod webgl_renderer;
 use webgl_renderer::WebGLRenderer;
 
-mod controls;
+pub mod controls;
 use controls::orbit_controls::OrbitControls;
 
-mod loaders;
+pub mod loaders;
+use loaders::loading_manager::LoadingManager;
+use loaders::registry::LoaderRegistry;
+use loaders::resolver::ResourceResolver;
+
+pub mod animation;
+use animation::animation_mixer::AnimationMixer;
 
 
 #[allow(non_snake_case)]
 #[wasm_bindgen(start)]
 pub fn start() -> Result<(), JsValue> {
     //scene
-    let mut mesh = get_mesh();
+    let mut mesh = Object3D::from(get_mesh());
     // let angele = 0.71;
     // quat::rotate_x_mut(&mut mesh.rotation, &angele);
     // mesh.position[0] = 1.5;
     // mesh.position[1] = 1.5;
     // mesh.position[2] = 1.5;
 
-    let mut mesh2 = get_mesh_2();
+    let mesh2 = Object3D::from(get_mesh_2());
     // mesh2.scale=[3.0,3.0,3.0];
 
-    let mut mesh3 = get_mesh();
+    let mesh3 = Object3D::from(get_mesh());
     mesh.position[0] = 1.5;
 
-    let mut mesh4 = get_mesh();
+    let mut mesh4 = Object3D::from(get_mesh());
     mesh4.position[0] = -1.5;
 
-    let mut mesh5 = get_mesh();
+    let mut mesh5 = Object3D::from(get_mesh());
     mesh5.position[2] = 1.5;
 
-    let mut scene = Scene {
-        objects: vec![mesh2, mesh, mesh3, mesh4, mesh5], //,
-    };
+    // the test meshes move as one unit
+    let mut meshes = Object3D::group().with_name("meshes");
+    for object in [mesh2, mesh, mesh3, mesh4, mesh5] {
+        meshes.add(object);
+    }
+
+    let mut scene = Scene::new();
+    scene.add(meshes);
+
+    scene.add_light(AmbientLight::new([1.0, 1.0, 1.0], 0.2));
+    scene.add_light(DirectionalLight::new([1.0, 1.0, 1.0], 1.0, [-0.5, -3.0, -4.0]));
 
     // let mut mesh7 = get_mesh();
     // mesh7.position[0] = -1.5;
     // mesh7.position[2] = -1.5;
     // scene.add_object(mesh7);
 
+    // all the cubes share one geometry and are drawn with a single instanced call
+    let mut cubes = InstancedMesh::new(
+        get_cube_geometry(),
+        PhongMaterial {
+            color: [1.0, 1.0, 1.0, 1.0],
+            ..Default::default()
+        }
+        .into(),
+        730,
+    );
     for x in 0..700 {
-        let mut mesh = get_mesh();
-        mesh.position[0] = (Math::random() * 160.0 - 80.0) as f32;
-        mesh.position[1] = 0.0;
-        mesh.position[2] = (Math::random() * 160.0 - 80.0) as f32;
-        scene.add_object(mesh);
+        let position = [
+            (Math::random() * 160.0 - 80.0) as f32,
+            0.0,
+            (Math::random() * 160.0 - 80.0) as f32,
+        ];
+        cubes.compose_at(x, &position, &vec3::new_one(), &quat::new_identity());
+        cubes.set_color_at(x, &[1.0, 0.0, 0.0, 1.0]);
     }
     for x in 0..30 {
-        let mut mesh = get_mesh();
-        mesh.position[1] = (x as f32) * 5.0;
-        mesh.material.color = [0.0, 0.0, 1.0, 1.0];
-        scene.add_object(mesh);
+        let position = [0.0, (x as f32) * 5.0, 0.0];
+        cubes.compose_at(700 + x, &position, &vec3::new_one(), &quat::new_identity());
+        cubes.set_color_at(700 + x, &[0.0, 0.0, 1.0, 1.0]);
     }
+    scene.add_instanced_object(cubes);
 
     //renderer
-    let webGLRenderer = WebGLRenderer::new();
+    let mut webGLRenderer = WebGLRenderer::new()?;
 
     //camera
     let mut camera = Camera::new(&webGLRenderer.dom_element);
@@ -115,34 +161,48 @@ pub fn start() -> Result<(), JsValue> {
     //     &[0_f32, 1_f32, 0_f32],
     // );
     //control
-    let mut control = OrbitControls::new(webGLRenderer.dom_element.clone());
+    let control = OrbitControls::new(webGLRenderer.dom_element.clone());
+
+    let start_render = |model: Object3D| {
+        scene.remove("meshes");
 
-    let start_render = |meshs: Vec<Mesh>| {
-        scene.objects = Vec::new();
+        let mut model = model.with_name("model");
+        model.scale = [3.0, 3.0, 3.0];
 
-        for mesh in meshs {
-            scene.add_object(mesh);
+        // play every clip that came with the model
+        let mut mixer = AnimationMixer::new();
+        for clip in model.animations.iter() {
+            mixer.clip_action(clip).play();
         }
+        scene.add(model);
 
         //render 1 request_animation_frame start
         let f = Rc::new(RefCell::new(None));
         let g = f.clone();
 
-        let cb = move |dt: f32| {
+        // requestAnimationFrame passes a timestamp in milliseconds
+        let mut last_time = None;
+        let cb = move |time: f32| {
             // let angele = 0.01;
             // quat::rotate_z_mut(&mut scene.objects[0].rotation, &angele);
 
+            let delta = last_time.map(|last| (time - last) / 1000.0).unwrap_or(0.0);
+            last_time = Some(time);
+            mixer.update(delta, &mut scene.root);
+
             control.update(&mut camera);
 
-            webGLRenderer
-                .render(&mut scene, &mut camera)
-                .expect("render err");
+            // a frame that fails would fail again, stop the loop
+            if let Err(err) = webGLRenderer.render(&mut scene, &mut camera) {
+                web_sys::console::error_1(&err.into());
+                return;
+            }
 
             // Schedule ourself for another requestAnimationFrame callback.
             request_animation_frame(f.borrow().as_ref().unwrap());
         };
 
-        *g.borrow_mut() = Some(Closure::wrap(Box::new(cb) as Box<FnMut(f32)>));
+        *g.borrow_mut() = Some(Closure::wrap(Box::new(cb) as Box<dyn FnMut(f32)>));
 
         request_animation_frame(g.borrow().as_ref().unwrap());
         //render 1 request_animation_frame end
@@ -156,28 +216,34 @@ pub fn start() -> Result<(), JsValue> {
 
     //model/Duck.gltf model/rust_logo2.obj model/SimpleSkinning.obj model/SimpleSkinning.obj
     //model/CesiumMilkTruck.gltf model/BoxVertexColors.gltf
-    let future =JsFuture::from(get_string_from_xhr("model/WaltHead.obj"))
-    .and_then(|js_str|{
-            let meshs = loaders::obj::load_obj_string(&js_str.as_string().unwrap()).unwrap();
-            start_render(meshs);
-            JsFuture::from(Promise::resolve(&JsValue::NULL))
-
-    }); 
-    future_to_promise(future);
+    let url = "model/WaltHead.obj";
+    let registry = LoaderRegistry::default();
+    let manager = LoadingManager::new();
+    manager.on_progress(|progress| {
+        log!(
+            "{}/{} files, {}/{} bytes",
+            progress.items_loaded,
+            progress.items_total,
+            progress.bytes_loaded,
+            progress.bytes_total
+        );
+    });
+    manager.on_error(|err| web_sys::console::warn_1(&err.to_string().into()));
+    let future = manager
+        .fetch(url)
+        .map_err(error::Error::from)
+        .and_then(move |bytes| registry.load(&bytes, url, None, Rc::new(manager)))
+        .map_err(move |err| JsValue::from_str(&format!("{}: {}", url, err)))
+        .map(|model| {
+            start_render(model);
+            JsValue::NULL
+        });
+    // the model renders when it loads, nothing waits on the promise
+    let _ = future_to_promise(future);
 
     Ok(())
 }
 
-fn cal(num: i64, sum: f64) -> f64 {
-    let sum1 = sum * 1.1012345678;
-
-    if num == 0 {
-        return sum1 + 1.0;
-    } else {
-        return cal(num - 1, sum1);
-    }
-}
-
 fn get_mesh_2() -> Mesh {
     let vertices: [f32; 9] = [12.0, 0.0, 0.0, 0.0, 0.0, 12.0, -12.0, 0.0, 0.0];
 
@@ -185,38 +251,19 @@ fn get_mesh_2() -> Mesh {
 
     let normals: [f32; 9] = [0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0];
 
-    let geometry = Geometry {
-        vertices: vertices.to_vec(),
-        indices: indices.to_vec(),
-        normals: normals.to_vec(),
-    };
+    let mut geometry = Geometry::new(indices.to_vec().into());
+    geometry.set_attribute(geometry::POSITION, Attribute::new(vertices.to_vec(), 3));
+    geometry.set_attribute(geometry::NORMAL, Attribute::new(normals.to_vec(), 3));
 
-    let material = Material {
+    let material = PhongMaterial {
         color: [1.0, 0.0, 1.0, 1.0],
+        ..Default::default()
     };
 
-    let mesh = Mesh {
-        geometry: geometry,
-        material: material,
-        position: vec3::new_zero(),
-        scale: vec3::new_one(),
-        rotation: quat::new_identity(),
-        matrix: [
-            1.0, 0.0, 0.0, 0.0, //
-            0.0, 1.0, 0.0, 0.0, //
-            0.0, 0.0, 1.0, 0.0, //
-            0.0, 0.0, 0.0, 1.0, //
-        ],
-        __webGLVertexBuffer: None,
-        __webGLFaceBuffer: None,
-        __webGLNormalBuffer: None,
-        __webGLColorBuffer: None,
-    };
-
-    mesh
+    Mesh::new(geometry, material.into())
 }
 
-fn get_mesh() -> Mesh {
+fn get_cube_geometry() -> Geometry {
     let vertices: [f32; 72] = [
         1.0, 1.0, 1.0, -1.0, 1.0, 1.0, -1.0, -1.0, 1.0, 1.0, -1.0, 1.0, // v0-v1-v2-v3 front
         1.0, 1.0, 1.0, 1.0, -1.0, 1.0, 1.0, -1.0, -1.0, 1.0, 1.0, -1.0, // v0-v3-v4-v5 right
@@ -247,33 +294,19 @@ fn get_mesh() -> Mesh {
         0.0, 0.0, -1.0, 0.0, 0.0, -1.0, 0.0, 0.0, -1.0, 0.0, 0.0, -1.0, // v4-v7-v6-v5 back
     ];
 
-    let geometry = Geometry {
-        vertices: vertices.to_vec(),
-        indices: indices.to_vec(),
-        normals: normals.to_vec(),
-    };
+    let mut geometry = Geometry::new(indices.to_vec().into());
+    geometry.set_attribute(geometry::POSITION, Attribute::new(vertices.to_vec(), 3));
+    geometry.set_attribute(geometry::NORMAL, Attribute::new(normals.to_vec(), 3));
+    geometry
+}
 
-    let material = Material {
-        color: [1.0, 0.0, 0.0, 1.0],
-    };
+fn get_mesh() -> Mesh {
+    let geometry = get_cube_geometry();
 
-    let mesh = Mesh {
-        geometry: geometry,
-        material: material,
-        position: vec3::new_zero(),
-        scale: vec3::new_one(),
-        rotation: quat::new_identity(),
-        matrix: [
-            1.0, 0.0, 0.0, 0.0, //
-            0.0, 1.0, 0.0, 0.0, //
-            0.0, 0.0, 1.0, 0.0, //
-            0.0, 0.0, 0.0, 1.0, //
-        ],
-        __webGLVertexBuffer: None,
-        __webGLFaceBuffer: None,
-        __webGLNormalBuffer: None,
-        __webGLColorBuffer: None,
+    let material = PhongMaterial {
+        color: [1.0, 0.0, 0.0, 1.0],
+        ..Default::default()
     };
 
-    mesh
+    Mesh::new(geometry, material.into())
 }
@@ -0,0 +1,232 @@
+extern crate gmath;
+use gmath::vec3;
+
+/// Light that reaches every surface equally, regardless of orientation.
+#[derive(Clone)]
+pub struct AmbientLight {
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+/// Light from an infinitely distant source, e.g. the sun.
+#[derive(Clone)]
+pub struct DirectionalLight {
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// direction the light travels in, in the space of its scene node
+    pub direction: [f32; 3],
+}
+
+/// Light emitted in every direction from a single point, e.g. a bulb.
+#[derive(Clone)]
+pub struct PointLight {
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub position: [f32; 3],
+    /// range after which the light has no effect, 0.0 for unlimited
+    pub distance: f32,
+    /// how quickly the light dims over `distance`
+    pub decay: f32,
+}
+
+/// Light emitted from a single point in a cone, e.g. a flashlight.
+#[derive(Clone)]
+pub struct SpotLight {
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub position: [f32; 3],
+    /// direction the cone points in, in the space of its scene node
+    pub direction: [f32; 3],
+    pub distance: f32,
+    pub decay: f32,
+    /// half angle of the cone in radians
+    pub angle: f32,
+    /// fraction of the cone, 0.0 to 1.0, over which the edge fades out
+    pub penumbra: f32,
+}
+
+#[derive(Clone)]
+pub enum Light {
+    Ambient(AmbientLight),
+    Directional(DirectionalLight),
+    Point(PointLight),
+    Spot(SpotLight),
+}
+
+impl AmbientLight {
+    pub fn new(color: [f32; 3], intensity: f32) -> AmbientLight {
+        AmbientLight { color, intensity }
+    }
+}
+
+impl DirectionalLight {
+    pub fn new(color: [f32; 3], intensity: f32, direction: [f32; 3]) -> DirectionalLight {
+        DirectionalLight {
+            color,
+            intensity,
+            direction,
+        }
+    }
+}
+
+impl PointLight {
+    pub fn new(color: [f32; 3], intensity: f32, position: [f32; 3]) -> PointLight {
+        PointLight {
+            color,
+            intensity,
+            position,
+            distance: 0.0,
+            decay: 1.0,
+        }
+    }
+}
+
+impl SpotLight {
+    pub fn new(
+        color: [f32; 3],
+        intensity: f32,
+        position: [f32; 3],
+        direction: [f32; 3],
+        angle: f32,
+    ) -> SpotLight {
+        SpotLight {
+            color,
+            intensity,
+            position,
+            direction,
+            distance: 0.0,
+            decay: 1.0,
+            angle,
+            penumbra: 0.0,
+        }
+    }
+}
+
+impl From<AmbientLight> for Light {
+    fn from(light: AmbientLight) -> Self {
+        Light::Ambient(light)
+    }
+}
+
+impl From<DirectionalLight> for Light {
+    fn from(light: DirectionalLight) -> Self {
+        Light::Directional(light)
+    }
+}
+
+impl From<PointLight> for Light {
+    fn from(light: PointLight) -> Self {
+        Light::Point(light)
+    }
+}
+
+impl From<SpotLight> for Light {
+    fn from(light: SpotLight) -> Self {
+        Light::Spot(light)
+    }
+}
+
+/// Number of lights of each kind, the renderer builds one program per combination.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct LightCounts {
+    pub directional: usize,
+    pub point: usize,
+    pub spot: usize,
+}
+
+/// Light parameters flattened into the layout the shaders expect: colors
+/// premultiplied by intensity, positions and normalized directions in view space.
+#[derive(Default)]
+pub struct LightUniforms {
+    pub ambient: [f32; 3],
+    pub directional: Vec<DirectionalLight>,
+    pub point: Vec<PointLight>,
+    pub spot: Vec<SpotLight>,
+}
+
+fn scaled(color: &[f32; 3], intensity: f32) -> [f32; 3] {
+    [
+        color[0] * intensity,
+        color[1] * intensity,
+        color[2] * intensity,
+    ]
+}
+
+fn transform_direction(direction: &[f32; 3], matrix: &[f32; 16]) -> [f32; 3] {
+    let mut rotated = vec3::new_zero();
+    vec3::transform_mat4_rotation(&mut rotated, direction, matrix);
+    let mut out = vec3::new_zero();
+    vec3::norm(&mut out, &rotated);
+    out
+}
+
+fn transform_position(position: &[f32; 3], matrix: &[f32; 16]) -> [f32; 3] {
+    let mut out = vec3::new_zero();
+    vec3::transform_mat4(&mut out, position, matrix);
+    out
+}
+
+impl Light {
+    /// The light with its position and direction carried through `matrix`.
+    pub fn transformed(&self, matrix: &[f32; 16]) -> Light {
+        match self {
+            Light::Ambient(light) => Light::Ambient(light.clone()),
+            Light::Directional(light) => Light::Directional(DirectionalLight {
+                direction: transform_direction(&light.direction, matrix),
+                ..light.clone()
+            }),
+            Light::Point(light) => Light::Point(PointLight {
+                position: transform_position(&light.position, matrix),
+                ..light.clone()
+            }),
+            Light::Spot(light) => Light::Spot(SpotLight {
+                position: transform_position(&light.position, matrix),
+                direction: transform_direction(&light.direction, matrix),
+                ..light.clone()
+            }),
+        }
+    }
+}
+
+impl LightUniforms {
+    pub fn from_lights(lights: &[Light], view_matrix: &[f32; 16]) -> LightUniforms {
+        let mut uniforms = LightUniforms::default();
+        for light in lights {
+            match light {
+                Light::Ambient(light) => {
+                    let color = scaled(&light.color, light.intensity);
+                    for (ambient, value) in uniforms.ambient.iter_mut().zip(&color) {
+                        *ambient += value;
+                    }
+                }
+                Light::Directional(light) => uniforms.directional.push(DirectionalLight {
+                    color: scaled(&light.color, light.intensity),
+                    intensity: 1.0,
+                    direction: transform_direction(&light.direction, view_matrix),
+                }),
+                Light::Point(light) => uniforms.point.push(PointLight {
+                    color: scaled(&light.color, light.intensity),
+                    intensity: 1.0,
+                    position: transform_position(&light.position, view_matrix),
+                    ..light.clone()
+                }),
+                Light::Spot(light) => uniforms.spot.push(SpotLight {
+                    color: scaled(&light.color, light.intensity),
+                    intensity: 1.0,
+                    position: transform_position(&light.position, view_matrix),
+                    direction: transform_direction(&light.direction, view_matrix),
+                    ..light.clone()
+                }),
+            }
+        }
+        uniforms
+    }
+
+    pub fn counts(&self) -> LightCounts {
+        LightCounts {
+            directional: self.directional.len(),
+            point: self.point.len(),
+            spot: self.spot.len(),
+        }
+    }
+}
@@ -1,38 +1,297 @@
+use super::registry::{LoadFuture, Loader};
+use super::resolver::{resolve_uri, FetchResolver, ResourceError, ResourceResolver};
+use crate::animation::animation_clip::AnimationClip;
+use crate::error::{Error, Result};
+use crate::animation::keyframe_track::{Interpolation, KeyframeTrack, TrackProperty};
 use crate::material::Material;
 use crate::mesh::Mesh;
+use crate::object3d::{Object3D, ObjectKind};
+use crate::skeleton::{Bone, Skeleton};
+use crate::skinned_mesh::SkinnedMesh;
+use crate::texture::{Filter, Texture, TextureFormat, TextureSource, Wrapping};
 use base64;
-use gltf::{buffer, Document, Gltf};
+use futures::{future, Future};
+use gltf::animation::{self, util::ReadOutputs};
+use gltf::{buffer, image, texture, Document, Gltf};
+use std::collections::HashMap;
 use std::path::Path;
-use std::sync::Arc;
-use web_sys::console;
+use std::rc::Rc;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::JsCast;
+extern crate gmath;
+use gmath::mat4;
 
-pub fn load_file(path: impl AsRef<Path>) -> Result<Vec<Mesh>, gltf::Error> {
-    GltfFile::load_file(path).map(|file| file.model())
+pub fn load_file(path: impl AsRef<Path>) -> Result<Object3D> {
+    GltfFile::load_file(path)?.model()
 }
 
-pub fn load_gltf_string(string: &str) -> Result<Vec<Mesh>, gltf::Error> {
+pub fn load_gltf_string(string: &str) -> Result<Object3D> {
     web_sys::console::log_1(&"* load_gltf_string".into());
 
-    GltfFile::load_gltf(string).map(|file| file.model())
+    GltfFile::load_gltf(string)?.model()
+}
+
+/// Like `load_gltf_string`, resolving relative image URIs against `base_url`, the
+/// URL the file was fetched from.
+pub fn load_gltf_string_with_base_url(string: &str, base_url: &str) -> Result<Object3D> {
+    GltfFile::load_gltf_with_base_url(string, base_url)?.model()
+}
+
+/// Loads either a JSON glTF or a binary GLB container, e.g. the bytes returned by
+/// `utils::get_array_buffer_from_xhr`.
+pub fn load_gltf_bytes(bytes: &[u8], base_url: &str) -> Result<Object3D> {
+    GltfFile::load_gltf_bytes(bytes, base_url)?.model()
+}
+
+/// Loads a glTF or GLB whose buffers may live in separate files, each one is
+/// fetched through `resolver` relative to `base_url`, like the images.
+pub fn load_gltf_async(
+    bytes: &[u8],
+    base_url: &str,
+    resolver: Rc<dyn ResourceResolver>,
+) -> Box<dyn Future<Item = Object3D, Error = Error>> {
+    Box::new(GltfFile::load_gltf_async(bytes, base_url, resolver).and_then(|file| file.model()))
+}
+
+/// glTF files, either JSON or binary GLB containers.
+pub struct GltfLoader;
+
+impl Loader for GltfLoader {
+    fn extensions(&self) -> &[&str] {
+        &["gltf", "glb"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["model/gltf+json", "model/gltf-binary"]
+    }
+
+    /// GLB files start with `glTF`, JSON ones have a top-level `asset` property.
+    fn sniff(&self, bytes: &[u8]) -> bool {
+        if bytes.starts_with(b"glTF") {
+            return true;
+        }
+        // `asset` may come after megabytes of embedded buffers, search the whole file
+        let json = bytes
+            .iter()
+            .find(|byte| !byte.is_ascii_whitespace())
+            .is_some_and(|&byte| byte == b'{');
+        json && bytes.windows(7).any(|window| window == b"\"asset\"")
+    }
+
+    fn load(&self, bytes: &[u8], url: &str, resolver: Rc<dyn ResourceResolver>) -> LoadFuture {
+        load_gltf_async(bytes, url, resolver)
+    }
+}
+
+fn wrapping(mode: texture::WrappingMode) -> Wrapping {
+    match mode {
+        texture::WrappingMode::Repeat => Wrapping::Repeat,
+        texture::WrappingMode::ClampToEdge => Wrapping::ClampToEdge,
+        texture::WrappingMode::MirroredRepeat => Wrapping::MirroredRepeat,
+    }
+}
+
+/// Applies the sampler of the glTF `texture` to `result`.
+fn with_sampler(texture: &gltf::Texture, mut result: Texture) -> Rc<Texture> {
+    let sampler = texture.sampler();
+    result.wrap_s = wrapping(sampler.wrap_s());
+    result.wrap_t = wrapping(sampler.wrap_t());
+    if let Some(filter) = sampler.mag_filter() {
+        result.mag_filter = match filter {
+            texture::MagFilter::Nearest => Filter::Nearest,
+            texture::MagFilter::Linear => Filter::Linear,
+        };
+    }
+    if let Some(filter) = sampler.min_filter() {
+        result.min_filter = match filter {
+            texture::MinFilter::Nearest => Filter::Nearest,
+            texture::MinFilter::Linear => Filter::Linear,
+            texture::MinFilter::NearestMipmapNearest => Filter::NearestMipmapNearest,
+            texture::MinFilter::LinearMipmapNearest => Filter::LinearMipmapNearest,
+            texture::MinFilter::NearestMipmapLinear => Filter::NearestMipmapLinear,
+            texture::MinFilter::LinearMipmapLinear => Filter::LinearMipmapLinear,
+        };
+    }
+    // glTF texture coordinates start at the top left of the image
+    result.flip_y = false;
+    Rc::new(result)
+}
+
+/// Pixels decoded by `gltf::import`, converted to a layout WebGL accepts.
+fn decoded_source(data: &image::Data) -> TextureSource {
+    let (format, pixels) = match data.format {
+        image::Format::R8 => (TextureFormat::Luminance, data.pixels.clone()),
+        image::Format::R8G8 => (
+            TextureFormat::Rgb,
+            data.pixels
+                .chunks(2)
+                .flat_map(|rg| vec![rg[0], rg[1], 0])
+                .collect(),
+        ),
+        image::Format::R8G8B8 => (TextureFormat::Rgb, data.pixels.clone()),
+        image::Format::R8G8B8A8 => (TextureFormat::Rgba, data.pixels.clone()),
+        image::Format::B8G8R8 => (
+            TextureFormat::Rgb,
+            data.pixels
+                .chunks(3)
+                .flat_map(|bgr| vec![bgr[2], bgr[1], bgr[0]])
+                .collect(),
+        ),
+        image::Format::B8G8R8A8 => (
+            TextureFormat::Rgba,
+            data.pixels
+                .chunks(4)
+                .flat_map(|bgra| vec![bgra[2], bgra[1], bgra[0], bgra[3]])
+                .collect(),
+        ),
+    };
+    TextureSource::Data {
+        width: data.width,
+        height: data.height,
+        format,
+        pixels,
+    }
+}
+
+/// URL the browser can load the image from: its URI or, for images stored in a
+/// buffer view, a blob of the view's bytes.
+fn image_url(
+    image: &gltf::Image,
+    buffers: &[buffer::Data],
+    base_url: &str,
+) -> Result<String> {
+    let url = match image.source() {
+        image::Source::Uri { uri, .. } => resolve_uri(base_url, uri),
+        image::Source::View { view, mime_type } => {
+            let start = view.offset();
+            let bytes = start
+                .checked_add(view.length())
+                .and_then(|end| buffers.get(view.buffer().index())?.get(start..end))
+                .ok_or_else(|| {
+                    Error::Parse(format!(
+                        "image {} lies outside of its buffer",
+                        image.index()
+                    ))
+                })?;
+            let parts = js_sys::Array::new();
+            parts.push(&js_sys::Uint8Array::from(bytes));
+            let options = web_sys::BlobPropertyBag::new();
+            options.set_type(mime_type);
+            let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(&parts, &options)
+                .map_err(|_| Error::Allocation(String::from("image blob")))?;
+            web_sys::Url::create_object_url_with_blob(&blob)
+                .map_err(|_| Error::Allocation(String::from("image url")))?
+        }
+    };
+    Ok(url)
+}
+
+/// Frees the blob of an image stored in a buffer view once the browser has
+/// read it, whether it could be decoded or not.
+fn revoke_when_loaded(texture: &Texture, url: String) {
+    let image = match &texture.source {
+        TextureSource::Image(image) => image,
+        TextureSource::Data { .. } => {
+            let _ = web_sys::Url::revoke_object_url(&url);
+            return;
+        }
+    };
+    // only one of load and error fires
+    let revoke = Closure::once_into_js(move || {
+        let _ = web_sys::Url::revoke_object_url(&url);
+    });
+    let _ = image.add_event_listener_with_callback("load", revoke.unchecked_ref());
+    let _ = image.add_event_listener_with_callback("error", revoke.unchecked_ref());
+}
+
+/// Contents of a buffer stored in the file itself, either the GLB BIN chunk or a
+/// base64 data URI. `None` when the buffer lives in an external file.
+fn embedded_buffer(
+    buffer: &gltf::Buffer,
+    blob: &mut Option<Vec<u8>>,
+) -> Option<std::result::Result<Vec<u8>, gltf::Error>> {
+    match buffer.source() {
+        buffer::Source::Bin => Some(blob.take().ok_or(gltf::Error::MissingBlob)),
+        buffer::Source::Uri(uri) if uri.starts_with("data:") => Some(match uri.find(";base64,") {
+            Some(start) => {
+                base64::decode(&uri[start + ";base64,".len()..]).map_err(gltf::Error::Base64)
+            }
+            None => Err(gltf::Error::UnsupportedScheme),
+        }),
+        buffer::Source::Uri(_) => None,
+    }
+}
+
+fn buffer_uri<'a>(buffer: &gltf::Buffer<'a>) -> &'a str {
+    match buffer.source() {
+        buffer::Source::Uri(uri) => uri,
+        buffer::Source::Bin => "",
+    }
+}
+
+/// Checks the length the document declares and pads the data to 4 bytes.
+fn checked_buffer(
+    buffer: &gltf::Buffer,
+    mut data: Vec<u8>,
+) -> std::result::Result<buffer::Data, gltf::Error> {
+    if data.len() < buffer.length() {
+        return Err(gltf::Error::BufferLength {
+            buffer: buffer.index(),
+            expected: buffer.length(),
+            actual: data.len(),
+        });
+    }
+    data.resize(data.len().next_multiple_of(4), 0);
+    Ok(buffer::Data(data))
+}
+
+fn missing_resource(url: &str, reason: &str) -> Error {
+    Error::MissingResource(ResourceError {
+        url: url.to_string(),
+        reason: reason.to_string(),
+    })
+}
+
+/// Sets the default morph target weights, missing ones stay at zero.
+fn set_weights(mesh: &mut Mesh, weights: &[f32]) {
+    for (influence, &weight) in mesh.morph_target_influences.iter_mut().zip(weights) {
+        *influence = weight;
+    }
+}
+
+/// `Object3D::id`s of the loaded nodes, by glTF node index.
+#[derive(Default)]
+struct NodeIds {
+    nodes: HashMap<usize, usize>,
+    /// the objects holding the primitives of each node's mesh
+    meshes: HashMap<usize, Vec<usize>>,
 }
 
 #[derive(Clone)] //Debug,
 pub struct GltfFile {
     document: gltf::Document,
     buffers: Vec<gltf::buffer::Data>,
-    materials: Vec<Arc<Material>>,
+    materials: Vec<Rc<Material>>,
 }
 
 impl GltfFile {
-    pub fn load_file(path: impl AsRef<Path>) -> Result<Self, gltf::Error> {
-        let (document, buffers, _) = gltf::import(path)?;
+    pub fn load_file(path: impl AsRef<Path>) -> Result<Self> {
+        let (document, buffers, images) = gltf::import(path)?;
+
+        let textures: Vec<_> = document
+            .textures()
+            .map(|texture| {
+                let source = decoded_source(&images[texture.source().index()]);
+                with_sampler(&texture, Texture::new(source))
+            })
+            .collect();
 
         // Load all the materials first, this assumes that the material index
         // that primitive refers to is loaded in the same order as document.materials()
-        let materials: Vec<_> = document
+        let materials = document
             .materials()
-            .map(|material| Arc::new(Material::from(material)))
-            .collect();
+            .map(|material| Material::from_gltf(&material, &textures).map(Rc::new))
+            .collect::<Result<Vec<_>>>()?;
 
         Ok(Self {
             document,
@@ -40,94 +299,365 @@ impl GltfFile {
             materials,
         })
     }
-    pub fn load_gltf(gltf_str: &str) -> Result<Self, gltf::Error> {
-        let gltf_data = Gltf::from_slice(gltf_str.as_bytes()).unwrap();
+    pub fn load_gltf(gltf_str: &str) -> Result<Self> {
+        GltfFile::load_gltf_with_base_url(gltf_str, "")
+    }
+    pub fn load_gltf_with_base_url(gltf_str: &str, base_url: &str) -> Result<Self> {
+        GltfFile::load_gltf_bytes(gltf_str.as_bytes(), base_url)
+    }
+    /// Accepts JSON glTF as well as GLB, whose first buffer is the embedded BIN chunk.
+    /// Every buffer must be embedded, use `load_gltf_async` for files that refer
+    /// to external `.bin` files.
+    pub fn load_gltf_bytes(bytes: &[u8], base_url: &str) -> Result<Self> {
+        let gltf_data = Gltf::from_slice(bytes)?;
         let document = gltf_data.document;
-        let blob = gltf_data.blob;
-        // let buffers = gltf_data.buffers();
-
-        fn import_buffer_data(
-            document: &Document,
-            mut blob: Option<Vec<u8>>,
-        ) -> Result<Vec<buffer::Data>, gltf::Error> {
-            let mut buffers = Vec::new();
-            for buffer in document.buffers() {
-                let mut data = match buffer.source() {
-                    buffer::Source::Bin => blob.take().ok_or(gltf::Error::MissingBlob),
-                    buffer::Source::Uri(uri) => {
-                        let m = &uri["data:".len()..].split(";base64,").nth(1).unwrap();
-                        base64::decode(&m).map_err(gltf::Error::Base64)
-                    }
-                }?;
-                if data.len() < buffer.length() {
-                    return Err(gltf::Error::BufferLength {
-                        buffer: buffer.index(),
-                        expected: buffer.length(),
-                        actual: data.len(),
-                    });
-                }
-                while data.len() % 4 != 0 {
-                    data.push(0);
+        let mut blob = gltf_data.blob;
+
+        let mut buffers = Vec::new();
+        for buffer in document.buffers() {
+            let data = match embedded_buffer(&buffer, &mut blob) {
+                Some(data) => data?,
+                None => {
+                    let url = resolve_uri(base_url, buffer_uri(&buffer));
+                    return Err(missing_resource(
+                        &url,
+                        "external buffers need load_gltf_async",
+                    ));
                 }
-                buffers.push(buffer::Data(data));
-            }
-            Ok(buffers)
+            };
+            buffers.push(checked_buffer(&buffer, data)?);
         }
 
-        let buffers_data = match import_buffer_data(&document, blob) {
-            Err(err) => match err {
-                gltf::Error::MissingBlob => {
-                    console::error_1(&"Missing blob".into());
-                    panic!();
-                }
-                gltf::Error::BufferLength {
-                    buffer: _,
-                    expected: _,
-                    actual: _,
-                } => {
-                    console::error_1(&"Wrong buffer length".into());
-                    panic!();
-                }
-                _ => {
-                    console::error_1(&"Unexpected error".into());
-                    panic!();
-                }
-            },
-            Ok(buffers) => buffers,
+        GltfFile::from_buffers(document, buffers, base_url, &FetchResolver)
+    }
+    /// Like `load_gltf_bytes`, fetching the external buffers through `resolver`.
+    pub fn load_gltf_async(
+        bytes: &[u8],
+        base_url: &str,
+        resolver: Rc<dyn ResourceResolver>,
+    ) -> Box<dyn Future<Item = Self, Error = Error>> {
+        let gltf_data = match Gltf::from_slice(bytes) {
+            Ok(gltf_data) => gltf_data,
+            Err(err) => return Box::new(future::err(err.into())),
         };
+        let document = gltf_data.document;
+        let mut blob = gltf_data.blob;
+
+        let requests: Vec<Box<dyn Future<Item = Vec<u8>, Error = Error>>> = document
+            .buffers()
+            .map(|buffer| match embedded_buffer(&buffer, &mut blob) {
+                Some(data) => Box::new(future::result(data.map_err(Error::from)))
+                    as Box<dyn Future<Item = _, Error = _>>,
+                None => Box::new(
+                    resolver
+                        .fetch(&resolve_uri(base_url, buffer_uri(&buffer)))
+                        .map_err(Error::from),
+                ),
+            })
+            .collect();
+
+        let base_url = base_url.to_string();
+        Box::new(future::join_all(requests).and_then(move |data| {
+            let buffers = document
+                .buffers()
+                .zip(data)
+                .map(|(buffer, data)| checked_buffer(&buffer, data))
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            GltfFile::from_buffers(document, buffers, &base_url, &*resolver)
+        }))
+    }
+    /// Creates the textures and materials once every buffer is available.
+    fn from_buffers(
+        document: Document,
+        buffers: Vec<buffer::Data>,
+        base_url: &str,
+        resolver: &dyn ResourceResolver,
+    ) -> Result<Self> {
+        let mut textures = Vec::new();
+        for texture in document.textures() {
+            let url = image_url(&texture.source(), &buffers, base_url)?;
+            let image = resolver.load_texture(&url)?;
+            if let image::Source::View { .. } = texture.source().source() {
+                revoke_when_loaded(&image, url);
+            }
+            textures.push(with_sampler(&texture, image));
+        }
 
         // Load all the materials first, this assumes that the material index
         // that primitive refers to is loaded in the same order as document.materials()
-        let materials: Vec<_> = document
+        let materials = document
             .materials()
-            .map(|material| Arc::new(Material::from(material)))
-            .collect();
-            
+            .map(|material| Material::from_gltf(&material, &textures).map(Rc::new))
+            .collect::<Result<Vec<_>>>()?;
+
         Ok(Self {
             document,
-            buffers: buffers_data,
+            buffers,
             materials,
         })
     }
-    pub fn model(&self) -> Vec<Mesh> {
-        let mut meshes = Vec::new();
-        for mesh in self.document.meshes() {
-            for primitive in mesh.primitives() {
-                meshes.push(Mesh::from_gltf(
-                    &self.buffers,
-                    &primitive,
-                    &self.materials,
-                    // Mat4::<f32>::identity(),
-                    [
-                        1.0, 0.0, 0.0, 0.0, //
-                        0.0, 1.0, 0.0, 0.0, //
-                        0.0, 0.0, 1.0, 0.0, //
-                        0.0, 0.0, 0.0, 1.0, //
-                    ],
+    /// The default scene (or the first one) as a node tree. Meshes used by several
+    /// nodes are read once and copied for each of them.
+    pub fn model(&self) -> Result<Object3D> {
+        let mut model = Object3D::group();
+        let mut meshes: Vec<Option<Vec<Mesh>>> = self.document.meshes().map(|_| None).collect();
+        let mut node_ids = NodeIds::default();
+
+        let scene = self
+            .document
+            .default_scene()
+            .or_else(|| self.document.scenes().next());
+        match scene {
+            Some(scene) => {
+                if let Some(name) = scene.name() {
+                    model.name = name.to_string();
+                }
+                for node in scene.nodes() {
+                    model.add(self.load_node(&node, &mut meshes, &mut node_ids)?);
+                }
+            }
+            // a document without scenes is a library of meshes, show them all
+            None => {
+                for mesh in self.document.meshes() {
+                    for primitive in self.load_mesh(&mesh)? {
+                        model.add(primitive);
+                    }
+                }
+            }
+        }
+
+        // joints may come after the meshes they deform, so bones are created with
+        // glTF node indices and pointed at the loaded nodes once the tree is complete
+        model.traverse_mut(&mut |object| {
+            if let ObjectKind::SkinnedMesh(skinned) = &mut object.kind {
+                for bone in skinned.skeleton.bones.iter_mut() {
+                    bone.node = node_ids
+                        .nodes
+                        .get(&bone.node)
+                        .cloned()
+                        .unwrap_or(usize::MAX);
+                }
+            }
+        });
+
+        model.animations = self
+            .document
+            .animations()
+            .map(|animation| Rc::new(self.load_animation(&animation, &node_ids)))
+            .collect();
+
+        Ok(model)
+    }
+
+    /// One clip per glTF animation. Channels targeting nodes outside the loaded scene
+    /// are dropped; morph weights go to every primitive of the node's mesh.
+    fn load_animation(&self, animation: &gltf::Animation, node_ids: &NodeIds) -> AnimationClip {
+        let mut tracks = Vec::new();
+        for channel in animation.channels() {
+            let reader = channel.reader(|buffer| Some(&self.buffers[buffer.index()]));
+            let times: Vec<f32> = match reader.read_inputs() {
+                Some(inputs) => inputs.collect(),
+                None => continue,
+            };
+            let (property, values): (TrackProperty, Vec<f32>) = match reader.read_outputs() {
+                Some(ReadOutputs::Translations(values)) => (
+                    TrackProperty::Translation,
+                    values.flat_map(|v| v.to_vec()).collect(),
+                ),
+                Some(ReadOutputs::Rotations(values)) => (
+                    TrackProperty::Rotation,
+                    values.into_f32().flat_map(|v| v.to_vec()).collect(),
+                ),
+                Some(ReadOutputs::Scales(values)) => (
+                    TrackProperty::Scale,
+                    values.flat_map(|v| v.to_vec()).collect(),
+                ),
+                Some(ReadOutputs::MorphTargetWeights(values)) => {
+                    (TrackProperty::MorphWeights, values.into_f32().collect())
+                }
+                None => continue,
+            };
+            let interpolation = match channel.sampler().interpolation() {
+                animation::Interpolation::Step => Interpolation::Step,
+                animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+                // Catmull-Rom isn't part of glTF 2.0, play it linearly
+                _ => Interpolation::Linear,
+            };
+
+            let target = channel.target().node().index();
+            let nodes = match property {
+                TrackProperty::MorphWeights => node_ids.meshes.get(&target).cloned(),
+                _ => node_ids.nodes.get(&target).map(|&id| vec![id]),
+            };
+            for node in nodes.unwrap_or_default() {
+                tracks.push(KeyframeTrack::new(
+                    node,
+                    property,
+                    times.clone(),
+                    values.clone(),
+                    interpolation,
                 ));
             }
         }
+        AnimationClip::new(animation.name().unwrap_or(""), tracks)
+    }
+
+    /// The bones of `skin`, referring to glTF node indices.
+    fn load_skeleton(&self, skin: &gltf::Skin) -> Skeleton {
+        let reader = skin.reader(|buffer| Some(&self.buffers[buffer.index()]));
+        let mut inverse_bind_matrices = reader
+            .read_inverse_bind_matrices()
+            .map(|matrices| matrices.collect::<Vec<_>>())
+            .unwrap_or_default()
+            .into_iter();
+
+        let bones = skin
+            .joints()
+            .map(|joint| {
+                let mut inverse_bind_matrix = mat4::new_identity();
+                // identity when the skin has no inverseBindMatrices
+                if let Some(matrix) = inverse_bind_matrices.next() {
+                    for (column, values) in matrix.iter().enumerate() {
+                        inverse_bind_matrix[column * 4..column * 4 + 4].copy_from_slice(values);
+                    }
+                }
+                Bone {
+                    node: joint.index(),
+                    inverse_bind_matrix,
+                }
+            })
+            .collect();
+        Skeleton::new(bones)
+    }
+
+    fn load_mesh(&self, mesh: &gltf::Mesh) -> Result<Vec<Mesh>> {
+        mesh.primitives()
+            .map(|primitive| {
+                let mut result = Mesh::from_gltf(&self.buffers, &primitive, &self.materials)?;
+                if let Some(weights) = mesh.weights() {
+                    set_weights(&mut result, weights);
+                }
+                Ok(result)
+            })
+            .collect()
+    }
+
+    fn load_node(
+        &self,
+        node: &gltf::Node,
+        meshes: &mut Vec<Option<Vec<Mesh>>>,
+        node_ids: &mut NodeIds,
+    ) -> Result<Object3D> {
+        let mut object = Object3D::group();
+        node_ids.nodes.insert(node.index(), object.id());
+        if let Some(name) = node.name() {
+            object.name = name.to_string();
+        }
+
+        match node.transform() {
+            gltf::scene::Transform::Matrix { matrix } => {
+                for (column, values) in matrix.iter().enumerate() {
+                    object.matrix[column * 4..column * 4 + 4].copy_from_slice(values);
+                }
+                object.matrix_auto_update = false;
+            }
+            gltf::scene::Transform::Decomposed {
+                translation,
+                rotation,
+                scale,
+            } => {
+                object.position = translation;
+                object.rotation = rotation;
+                object.scale = scale;
+            }
+        }
+
+        if let Some(mesh) = node.mesh() {
+            if meshes[mesh.index()].is_none() {
+                meshes[mesh.index()] = Some(self.load_mesh(&mesh)?);
+            }
+            let primitives = meshes[mesh.index()].as_ref().unwrap();
+            let skeleton = node.skin().map(|skin| self.load_skeleton(&skin));
+            let kind = |primitive: &Mesh| {
+                let mut primitive = primitive.clone();
+                // the node's weights override the mesh's
+                if let Some(weights) = node.weights() {
+                    set_weights(&mut primitive, weights);
+                }
+                match &skeleton {
+                    Some(skeleton) => {
+                        ObjectKind::SkinnedMesh(SkinnedMesh::new(primitive, skeleton.clone()))
+                    }
+                    None => ObjectKind::Mesh(primitive),
+                }
+            };
+            let mut mesh_ids = Vec::new();
+            if primitives.len() == 1 {
+                object.kind = kind(&primitives[0]);
+                mesh_ids.push(object.id());
+            } else {
+                // one child per primitive, they share the node's transform
+                for primitive in primitives.iter() {
+                    let mut child = Object3D::new(kind(primitive));
+                    if let Some(name) = mesh.name() {
+                        child.name = name.to_string();
+                    }
+                    mesh_ids.push(child.id());
+                    object.add(child);
+                }
+            }
+            node_ids.meshes.insert(node.index(), mesh_ids);
+        }
+
+        for child in node.children() {
+            object.add(self.load_node(&child, meshes, node_ids)?);
+        }
+        Ok(object)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loaders::resolver::FileResolver;
+
+    const TRIANGLE: &[u8] = include_bytes!("../../tests/fixtures/triangle.gltf");
+
+    fn fixtures() -> Rc<dyn ResourceResolver> {
+        Rc::new(FileResolver::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures"
+        )))
+    }
 
-        meshes
+    #[test]
+    fn fetches_external_buffers_through_resolver() {
+        let model = load_gltf_async(TRIANGLE, "triangle.gltf", fixtures())
+            .wait()
+            .unwrap();
+        let object = model.get_object_by_name("triangle").unwrap();
+        match &object.kind {
+            ObjectKind::Mesh(mesh) => {
+                assert_eq!(mesh.geometry.vertex_count(), 3);
+                assert_eq!(mesh.geometry.indices.len(), 3);
+            }
+            _ => panic!("expected a mesh"),
+        }
+    }
+
+    #[test]
+    fn reports_missing_buffer() {
+        match load_gltf_async(TRIANGLE, "missing/triangle.gltf", fixtures()).wait() {
+            Err(Error::MissingResource(err)) => assert_eq!(err.url, "missing/triangle.bin"),
+            other => panic!("expected a missing resource, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn embedded_loading_refuses_external_buffers() {
+        match load_gltf_bytes(TRIANGLE, "triangle.gltf") {
+            Err(Error::MissingResource(err)) => assert_eq!(err.url, "triangle.bin"),
+            other => panic!("expected a missing resource, got {:?}", other.map(|_| ())),
+        }
     }
 }
@@ -0,0 +1,382 @@
+use super::resolver::{ResourceError, ResourceFuture, ResourceResolver};
+use crate::error::{Error, Result};
+use crate::texture::{Texture, TextureSource};
+use futures::sync::oneshot;
+use futures::{future, Future};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use wasm_bindgen::prelude::Closure;
+use wasm_bindgen::JsCast;
+use web_sys::{HtmlImageElement, ProgressEvent, XmlHttpRequest, XmlHttpRequestResponseType};
+
+/// Counts of one load, passed to the `on_progress` callback.
+#[derive(Clone, Debug, Default)]
+pub struct Progress {
+    /// the file whose state changed
+    pub url: String,
+    pub items_loaded: usize,
+    pub items_total: usize,
+    /// bytes of the files downloaded so far, images aren't counted
+    pub bytes_loaded: u64,
+    /// bytes announced by the server, files without a `Content-Length` count
+    /// what has arrived of them
+    pub bytes_total: u64,
+}
+
+type Waiter = oneshot::Sender<std::result::Result<Vec<u8>, ResourceError>>;
+type ProgressCallback = Rc<dyn Fn(&Progress)>;
+type ErrorCallback = Rc<dyn Fn(&ResourceError)>;
+
+/// A download in flight, shared by every `fetch` of its URL.
+struct Request {
+    xhr: XmlHttpRequest,
+    loaded: u64,
+    total: u64,
+    waiters: Vec<Waiter>,
+    _on_progress: Closure<dyn FnMut(ProgressEvent)>,
+}
+
+#[derive(Default)]
+struct State {
+    requests: HashMap<String, Request>,
+    /// images by URL, loading or loaded, until they fail
+    images: HashMap<String, HtmlImageElement>,
+    pending_images: Vec<String>,
+    cache: HashMap<String, Vec<u8>>,
+    items_loaded: usize,
+    items_total: usize,
+    /// bytes of the finished requests
+    bytes_loaded: u64,
+    bytes_total: u64,
+    on_progress: Option<ProgressCallback>,
+    on_load: Option<Rc<dyn Fn()>>,
+    on_error: Option<ErrorCallback>,
+}
+
+impl State {
+    fn pending(&self) -> bool {
+        !self.requests.is_empty() || !self.pending_images.is_empty()
+    }
+
+    /// Counting restarts with the first request after everything has finished.
+    fn item_start(&mut self) {
+        if !self.pending() {
+            self.items_loaded = 0;
+            self.items_total = 0;
+            self.bytes_loaded = 0;
+            self.bytes_total = 0;
+        }
+        self.items_total += 1;
+    }
+
+    fn progress(&self, url: &str) -> Progress {
+        let requests = self.requests.values();
+        Progress {
+            url: url.to_string(),
+            items_loaded: self.items_loaded,
+            items_total: self.items_total,
+            bytes_loaded: self.bytes_loaded
+                + requests.clone().map(|request| request.loaded).sum::<u64>(),
+            bytes_total: self.bytes_total
+                + requests
+                    .map(|request| request.total.max(request.loaded))
+                    .sum::<u64>(),
+        }
+    }
+}
+
+/// Downloads every file of a load, the model and the materials, buffers and
+/// images it refers to, reporting progress until all of them have arrived.
+/// Concurrent requests for the same URL share one download and finished files
+/// are kept in memory, so loading a model twice fetches nothing the second time.
+///
+/// Clones share their state; pass the manager to a loader as its resolver.
+#[derive(Clone, Default)]
+pub struct LoadingManager {
+    state: Rc<RefCell<State>>,
+}
+
+impl LoadingManager {
+    pub fn new() -> LoadingManager {
+        LoadingManager::default()
+    }
+
+    /// Called whenever a file starts, progresses or finishes.
+    pub fn on_progress(&self, callback: impl Fn(&Progress) + 'static) {
+        self.state.borrow_mut().on_progress = Some(Rc::new(callback));
+    }
+
+    /// Called once nothing is pending anymore, whether files failed or not.
+    pub fn on_load(&self, callback: impl Fn() + 'static) {
+        self.state.borrow_mut().on_load = Some(Rc::new(callback));
+    }
+
+    /// Called for every file that cannot be loaded, including aborted ones.
+    pub fn on_error(&self, callback: impl Fn(&ResourceError) + 'static) {
+        self.state.borrow_mut().on_error = Some(Rc::new(callback));
+    }
+
+    /// Cancels every pending download, their futures fail with an error.
+    pub fn abort(&self) {
+        let (requests, images) = {
+            let state = self.state.borrow();
+            let requests: Vec<XmlHttpRequest> = state
+                .requests
+                .values()
+                .map(|request| request.xhr.clone())
+                .collect();
+            let images: Vec<HtmlImageElement> = state
+                .pending_images
+                .iter()
+                .filter_map(|url| state.images.get(url).cloned())
+                .collect();
+            (requests, images)
+        };
+        // both fire their end handlers, which need the state
+        for xhr in requests {
+            let _ = xhr.abort();
+        }
+        for image in images {
+            image.set_src("");
+        }
+    }
+
+    /// Forgets the downloaded files and images.
+    pub fn clear_cache(&self) {
+        let mut state = self.state.borrow_mut();
+        state.cache.clear();
+        let pending = state.pending_images.clone();
+        state.images.retain(|url, _| pending.contains(url));
+    }
+
+    fn start_request(&self, url: &str) -> Result<Request> {
+        let xhr = XmlHttpRequest::new().map_err(|_| Error::Allocation(String::from("request")))?;
+        let request_error = |_| {
+            Error::MissingResource(ResourceError {
+                url: url.to_string(),
+                reason: String::from("cannot send request"),
+            })
+        };
+        xhr.open_with_async("GET", url, true)
+            .map_err(request_error)?;
+        xhr.set_response_type(XmlHttpRequestResponseType::Arraybuffer);
+
+        let state = Rc::downgrade(&self.state);
+        let progress_url = url.to_string();
+        let on_progress = Closure::wrap(Box::new(move |event: ProgressEvent| {
+            if let Some(state) = state.upgrade() {
+                let callback = {
+                    let mut state = state.borrow_mut();
+                    if let Some(request) = state.requests.get_mut(&progress_url) {
+                        request.loaded = event.loaded() as u64;
+                        if event.length_computable() {
+                            request.total = event.total() as u64;
+                        }
+                    }
+                    state
+                        .on_progress
+                        .clone()
+                        .map(|callback| (callback, state.progress(&progress_url)))
+                };
+                if let Some((callback, progress)) = callback {
+                    callback(&progress);
+                }
+            }
+        }) as Box<dyn FnMut(ProgressEvent)>);
+        xhr.set_onprogress(Some(on_progress.as_ref().unchecked_ref()));
+
+        // loadend follows load, error and abort alike
+        let manager = Rc::downgrade(&self.state);
+        let end_url = url.to_string();
+        let on_end = Closure::once_into_js(move || {
+            if let Some(state) = manager.upgrade() {
+                finish_request(&state, &end_url);
+            }
+        });
+        xhr.set_onloadend(Some(on_end.unchecked_ref()));
+
+        xhr.send().map_err(request_error)?;
+        Ok(Request {
+            xhr,
+            loaded: 0,
+            total: 0,
+            waiters: Vec::new(),
+            _on_progress: on_progress,
+        })
+    }
+}
+
+/// Hands the response of `url` to everyone waiting for it.
+fn finish_request(state: &Rc<RefCell<State>>, url: &str) {
+    let (waiters, result, callbacks, progress) = {
+        let mut state = state.borrow_mut();
+        let request = match state.requests.remove(url) {
+            Some(request) => request,
+            None => return,
+        };
+        // the closure is dropped with the request
+        request.xhr.set_onprogress(None);
+
+        let status = request.xhr.status().unwrap_or(0);
+        let result = if (200..300).contains(&status) {
+            let response = request.xhr.response().unwrap_or_default();
+            Ok(js_sys::Uint8Array::new(&response).to_vec())
+        } else {
+            Err(ResourceError {
+                url: url.to_string(),
+                reason: match status {
+                    // network errors and aborted requests have no status
+                    0 => String::from("request failed or was aborted"),
+                    status => format!("HTTP {}", status),
+                },
+            })
+        };
+
+        state.items_loaded += 1;
+        state.bytes_loaded += request.loaded;
+        state.bytes_total += request.total.max(request.loaded);
+        if let Ok(bytes) = &result {
+            state.cache.insert(url.to_string(), bytes.clone());
+        }
+        let callbacks = (
+            state.on_progress.clone(),
+            state.on_error.clone(),
+            state.on_load.clone().filter(|_| !state.pending()),
+        );
+        (request.waiters, result, callbacks, state.progress(url))
+    };
+
+    let (on_progress, on_error, on_load) = callbacks;
+    if let (Some(on_error), Err(err)) = (on_error, &result) {
+        on_error(err);
+    }
+    for waiter in waiters {
+        let _ = waiter.send(result.clone());
+    }
+    if let Some(on_progress) = on_progress {
+        on_progress(&progress);
+    }
+    if let Some(on_load) = on_load {
+        on_load();
+    }
+}
+
+/// Counts an image as loaded, failed images are forgotten so they can be retried.
+fn finish_image(state: &Rc<RefCell<State>>, url: &str, failed: bool) {
+    let (callbacks, progress) = {
+        let mut state = state.borrow_mut();
+        state.pending_images.retain(|pending| pending != url);
+        state.items_loaded += 1;
+        if failed {
+            state.images.remove(url);
+        }
+        let callbacks = (
+            state.on_progress.clone(),
+            state.on_error.clone().filter(|_| failed),
+            state.on_load.clone().filter(|_| !state.pending()),
+        );
+        (callbacks, state.progress(url))
+    };
+
+    let (on_progress, on_error, on_load) = callbacks;
+    if let Some(on_error) = on_error {
+        on_error(&ResourceError {
+            url: url.to_string(),
+            reason: String::from("cannot load image"),
+        });
+    }
+    if let Some(on_progress) = on_progress {
+        on_progress(&progress);
+    }
+    if let Some(on_load) = on_load {
+        on_load();
+    }
+}
+
+impl ResourceResolver for LoadingManager {
+    fn fetch(&self, url: &str) -> ResourceFuture {
+        let (sender, receiver) = oneshot::channel();
+        {
+            let mut state = self.state.borrow_mut();
+            if let Some(bytes) = state.cache.get(url) {
+                return Box::new(future::ok(bytes.clone()));
+            }
+            if let Some(request) = state.requests.get_mut(url) {
+                request.waiters.push(sender);
+                return wait(url, receiver);
+            }
+        }
+
+        let mut request = match self.start_request(url) {
+            Ok(request) => request,
+            Err(err) => {
+                return Box::new(future::err(ResourceError {
+                    url: url.to_string(),
+                    reason: err.to_string(),
+                }))
+            }
+        };
+        request.waiters.push(sender);
+        let (callback, progress) = {
+            let mut state = self.state.borrow_mut();
+            state.item_start();
+            state.requests.insert(url.to_string(), request);
+            (state.on_progress.clone(), state.progress(url))
+        };
+        if let Some(callback) = callback {
+            callback(&progress);
+        }
+        wait(url, receiver)
+    }
+
+    /// Images are shared by URL too, every texture gets its own sampler state.
+    fn load_texture(&self, url: &str) -> Result<Texture> {
+        if let Some(image) = self.state.borrow().images.get(url) {
+            return Ok(Texture::new(TextureSource::Image(image.clone())));
+        }
+
+        let texture = Texture::from_url(url)?;
+        let image = match &texture.source {
+            TextureSource::Image(image) => image.clone(),
+            TextureSource::Data { .. } => return Ok(texture),
+        };
+        // only one of load and error fires
+        let state = Rc::downgrade(&self.state);
+        let image_url = url.to_string();
+        let on_end = Closure::once_into_js(move |event: web_sys::Event| {
+            if let Some(state) = state.upgrade() {
+                finish_image(&state, &image_url, event.type_() == "error");
+            }
+        });
+        image.set_onload(Some(on_end.unchecked_ref()));
+        image.set_onerror(Some(on_end.unchecked_ref()));
+
+        let (callback, progress) = {
+            let mut state = self.state.borrow_mut();
+            state.item_start();
+            state.images.insert(url.to_string(), image);
+            state.pending_images.push(url.to_string());
+            (state.on_progress.clone(), state.progress(url))
+        };
+        if let Some(callback) = callback {
+            callback(&progress);
+        }
+        Ok(texture)
+    }
+}
+
+fn wait(
+    url: &str,
+    receiver: oneshot::Receiver<std::result::Result<Vec<u8>, ResourceError>>,
+) -> ResourceFuture {
+    let url = url.to_string();
+    Box::new(receiver.then(move |result| match result {
+        Ok(result) => result,
+        // the manager was dropped with the request in flight
+        Err(_) => Err(ResourceError {
+            url,
+            reason: String::from("request was cancelled"),
+        }),
+    }))
+}
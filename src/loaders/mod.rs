@@ -1,4 +1,11 @@
 pub mod obj_to_mesh;
 pub mod obj;
+pub mod obj_parser;
+pub mod mtl;
 pub mod gltf;
+pub mod stl;
+pub mod ply;
+pub mod resolver;
+pub mod registry;
+pub mod loading_manager;
 
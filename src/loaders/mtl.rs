@@ -0,0 +1,274 @@
+use super::resolver::{resolve_uri, ResourceResolver};
+use crate::error::{Error, Result};
+use crate::material::{Material, PhongMaterial};
+use std::rc::Rc;
+
+/// One `newmtl` block of an MTL library.
+#[derive(Clone, Debug)]
+pub struct MtlMaterial {
+    pub name: String,
+    /// `Ka`
+    pub ambient: [f32; 3],
+    /// `Kd`
+    pub diffuse: [f32; 3],
+    /// `Ks`
+    pub specular: [f32; 3],
+    /// `Ns`
+    pub shininess: f32,
+    /// `d`, or one minus `Tr`
+    pub opacity: f32,
+    /// `illum`: 0 color only, 1 diffuse, 2 and above diffuse and specular
+    pub illumination: u32,
+    /// `map_Kd`, relative to the MTL file
+    pub map: Option<String>,
+    /// `map_Bump` or `bump`, relative to the MTL file
+    pub bump_map: Option<String>,
+    /// the `-bm` option of the bump map
+    pub bump_scale: f32,
+}
+
+impl MtlMaterial {
+    fn new(name: &str) -> MtlMaterial {
+        // defaults of the MTL specification
+        MtlMaterial {
+            name: name.to_string(),
+            ambient: [0.2, 0.2, 0.2],
+            diffuse: [0.8, 0.8, 0.8],
+            specular: [0.0, 0.0, 0.0],
+            shininess: 0.0,
+            opacity: 1.0,
+            illumination: 2,
+            map: None,
+            bump_map: None,
+            bump_scale: 1.0,
+        }
+    }
+
+    /// The Phong material described by the block, textures are loaded through
+    /// `resolver` relative to `base_url`, the URL of the MTL file.
+    pub fn to_material(&self, base_url: &str, resolver: &dyn ResourceResolver) -> Material {
+        let texture = |path: &Option<String>| {
+            let url = resolve_uri(base_url, path.as_ref()?);
+            match resolver.load_texture(&url) {
+                Ok(texture) => Some(Rc::new(texture)),
+                Err(err) => {
+                    web_sys::console::warn_1(&format!("{}: {}", url, err).into());
+                    None
+                }
+            }
+        };
+        let [r, g, b] = self.diffuse;
+
+        PhongMaterial {
+            color: [r, g, b, self.opacity],
+            ambient: Some(self.ambient),
+            // illumination models 0 and 1 have no highlights
+            specular: if self.illumination < 2 {
+                [0.0, 0.0, 0.0]
+            } else {
+                self.specular
+            },
+            shininess: self.shininess,
+            map: texture(&self.map),
+            bump_map: texture(&self.bump_map),
+            bump_scale: self.bump_scale,
+        }
+        .into()
+    }
+}
+
+/// Parses an MTL library. Statements may come in any order, unknown ones are
+/// skipped.
+///
+/// `wavefront_obj::mtl::parse` can't read the libraries exporters write: it
+/// requires `Ns`, `Ka`, `Kd`, `Ks`, `d` and `illum` in exactly that order and
+/// knows neither `Tr`, bump maps nor texture options.
+pub fn parse_mtl(string: &str) -> Result<Vec<MtlMaterial>> {
+    let mut materials: Vec<MtlMaterial> = Vec::new();
+
+    for (number, line) in string.lines().enumerate() {
+        let error = |reason: &str| Error::Parse(format!("line {}: {}", number + 1, reason));
+        let line = match line.find('#') {
+            Some(start) => &line[..start],
+            None => line,
+        };
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let arguments: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            if arguments.is_empty() {
+                return Err(error("missing material name"));
+            }
+            materials.push(MtlMaterial::new(&arguments.join(" ")));
+            continue;
+        }
+        // statements before the first newmtl have nothing to apply to
+        let material = match materials.last_mut() {
+            Some(material) => material,
+            None => continue,
+        };
+
+        let numbers = || {
+            arguments
+                .iter()
+                .map(|argument| argument.parse::<f32>())
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(|_| error(&format!("invalid number in `{}`", line.trim())))
+        };
+        let number = || {
+            numbers()?
+                .first()
+                .cloned()
+                .ok_or_else(|| error(&format!("missing value for `{}`", keyword)))
+        };
+        let color = || match numbers()?[..] {
+            [r, g, b, ..] => Ok([r, g, b]),
+            // a single value is a gray
+            [v] => Ok([v, v, v]),
+            _ => Err(error(&format!("missing color for `{}`", keyword))),
+        };
+        let texture = || {
+            let (options, path) = texture_options(&arguments);
+            if path.is_empty() {
+                return Err(error(&format!("missing file name for `{}`", keyword)));
+            }
+            Ok((options, path))
+        };
+
+        match keyword {
+            "Ka" => material.ambient = color()?,
+            "Kd" => material.diffuse = color()?,
+            "Ks" => material.specular = color()?,
+            "Ns" => material.shininess = number()?,
+            "d" => material.opacity = number()?,
+            "Tr" => material.opacity = 1.0 - number()?,
+            "illum" => material.illumination = number()? as u32,
+            "map_Kd" => material.map = Some(texture()?.1),
+            "map_Bump" | "map_bump" | "bump" => {
+                let (options, path) = texture()?;
+                material.bump_map = Some(path);
+                if let Some((_, values)) = options.iter().find(|(option, _)| *option == "-bm") {
+                    material.bump_scale = values
+                        .first()
+                        .and_then(|scale| scale.parse().ok())
+                        .ok_or_else(|| error("invalid -bm option"))?;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(materials)
+}
+
+/// Splits the arguments of a texture statement into its options, each with
+/// its values, and the file name after them, which may contain spaces.
+fn texture_options<'a>(arguments: &[&'a str]) -> (Vec<(&'a str, Vec<&'a str>)>, String) {
+    let mut options = Vec::new();
+    let mut rest = arguments;
+    while let Some((&option, tail)) = rest.split_first() {
+        let (min, max) = match option {
+            "-blendu" | "-blendv" | "-bm" | "-boost" | "-cc" | "-clamp" | "-imfchan"
+            | "-texres" | "-type" => (1, 1),
+            "-mm" => (2, 2),
+            // -o, -s and -t take one to three numbers
+            "-o" | "-s" | "-t" => (1, 3),
+            _ => break,
+        };
+        let count = tail
+            .iter()
+            .take(max)
+            .enumerate()
+            .take_while(|&(i, value)| i < min || value.parse::<f32>().is_ok())
+            .count();
+        options.push((option, tail[..count].to_vec()));
+        rest = &tail[count..];
+    }
+    (options, rest.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_statements_in_any_order() {
+        let materials = parse_mtl(
+            "newmtl skin # the head\n\
+             map_Kd textures/skin.png\n\
+             illum 1\n\
+             Kd 0.5 0.25 1.0\n\
+             Ns 20\n",
+        )
+        .unwrap();
+        assert_eq!(materials.len(), 1);
+        let skin = &materials[0];
+        assert_eq!(skin.name, "skin");
+        assert_eq!(skin.diffuse, [0.5, 0.25, 1.0]);
+        assert_eq!(skin.shininess, 20.0);
+        assert_eq!(skin.illumination, 1);
+        assert_eq!(skin.map.as_ref().unwrap(), "textures/skin.png");
+        // unset statements keep the defaults of the specification
+        assert_eq!(skin.ambient, [0.2, 0.2, 0.2]);
+        assert_eq!(skin.opacity, 1.0);
+    }
+
+    #[test]
+    fn reads_one_value_gray() {
+        let materials = parse_mtl("newmtl gray\nKd 0.4\nKs 1\n").unwrap();
+        assert_eq!(materials[0].diffuse, [0.4, 0.4, 0.4]);
+        assert_eq!(materials[0].specular, [1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn transparency_is_inverse_of_dissolve() {
+        let materials = parse_mtl("newmtl glass\nTr 0.75\nnewmtl solid\nd 0.5\n").unwrap();
+        assert_eq!(materials[0].opacity, 0.25);
+        assert_eq!(materials[1].opacity, 0.5);
+    }
+
+    #[test]
+    fn reads_bump_maps_and_scale() {
+        let materials = parse_mtl(
+            "newmtl a\nbump -bm 0.5 bump.png\nnewmtl b\nmap_Bump normal.png\n",
+        )
+        .unwrap();
+        assert_eq!(materials[0].bump_map.as_ref().unwrap(), "bump.png");
+        assert_eq!(materials[0].bump_scale, 0.5);
+        assert_eq!(materials[1].bump_map.as_ref().unwrap(), "normal.png");
+        assert_eq!(materials[1].bump_scale, 1.0);
+        assert!(parse_mtl("newmtl a\nbump -bm bump.png\n").is_err());
+    }
+
+    #[test]
+    fn reads_file_names_with_spaces_after_options() {
+        let materials = parse_mtl(
+            "newmtl a\nmap_Kd -s 1 1 1 -o 0.5 -clamp on my texture.png\n\
+             bump -bm 2 -mm 0 1 bump map.png\n",
+        )
+        .unwrap();
+        assert_eq!(materials[0].map.as_ref().unwrap(), "my texture.png");
+        assert_eq!(materials[0].bump_map.as_ref().unwrap(), "bump map.png");
+        assert_eq!(materials[0].bump_scale, 2.0);
+        assert!(parse_mtl("newmtl a\nmap_Kd -clamp on\n").is_err());
+    }
+
+    #[test]
+    fn skips_statements_before_newmtl() {
+        let materials = parse_mtl("Kd 1 0 0\nTr 1\nnewmtl red\n").unwrap();
+        assert_eq!(materials.len(), 1);
+        assert_eq!(materials[0].diffuse, [0.8, 0.8, 0.8]);
+        assert_eq!(materials[0].opacity, 1.0);
+    }
+
+    #[test]
+    fn reports_line_of_invalid_number() {
+        match parse_mtl("newmtl a\n\nKd 1 x 0\n") {
+            Err(Error::Parse(message)) => assert!(message.starts_with("line 3:")),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+}
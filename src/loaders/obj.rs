@@ -1,29 +1,98 @@
-use crate::material::Material;
-use crate::mesh::Mesh;
+use crate::material::{BasicMaterial, Material, PhongMaterial};
+use crate::mesh::{DrawMode, Mesh};
 extern crate wavefront_obj;
-use crate::loaders::obj_to_mesh::Mesh as ObjMesh;
-use gmath::{mat4, quat, vec2, vec3};
-use wavefront_obj::{mtl, obj};
+use super::mtl::parse_mtl;
+use super::obj_parser;
+use super::registry::{LoadFuture, Loader};
+use super::resolver::{resolve_uri, ResourceResolver};
+use crate::error::{Error, Result};
+use crate::loaders::obj_to_mesh::{draw_mode, Mesh as ObjMesh};
+use crate::object3d::Object3D;
+use futures::{future, Future};
+use std::collections::HashMap;
+use std::rc::Rc;
+use wavefront_obj::obj;
 extern crate web_sys;
 
-pub fn load_obj_string(string: &str) -> Result<Vec<Mesh>, ()> {
+/// Loads an OBJ file without its material library, every object gets the
+/// default material.
+pub fn load_obj_string(string: &str) -> Result<Vec<Mesh>> {
     web_sys::console::log_1(&"* load_obj_string".into());
-    ObjFile::load_obj_string(string).map(|file| file.model())
+    ObjFile::load_obj_string(string).and_then(|file| file.model(&HashMap::new()))
+}
+
+/// Loads an OBJ file and the MTL library it names, which is fetched through
+/// `resolver` relative to `base_url`, the URL of the OBJ file, like its textures.
+pub fn load_obj_async(
+    string: &str,
+    base_url: &str,
+    resolver: Rc<dyn ResourceResolver>,
+) -> Box<dyn Future<Item = Vec<Mesh>, Error = Error>> {
+    let file = match ObjFile::load_obj_string(string) {
+        Ok(file) => file,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    Box::new(
+        file.load_materials(base_url, resolver)
+            .and_then(move |materials| file.model(&materials)),
+    )
+}
+
+/// Wavefront OBJ files, the meshes are children of a group.
+pub struct ObjLoader;
+
+impl Loader for ObjLoader {
+    fn extensions(&self) -> &[&str] {
+        &["obj"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["model/obj"]
+    }
+
+    /// OBJ has no signature, the first statement has to be an OBJ keyword.
+    fn sniff(&self, bytes: &[u8]) -> bool {
+        let head = &bytes[..bytes.len().min(1024)];
+        if head.contains(&0) {
+            return false;
+        }
+        String::from_utf8_lossy(head)
+            .lines()
+            .map(|line| line.trim())
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+            .and_then(|line| line.split_whitespace().next())
+            .is_some_and(|keyword| {
+                ["v", "vt", "vn", "f", "l", "p", "o", "g", "s", "mtllib", "usemtl"]
+                    .contains(&keyword)
+            })
+    }
+
+    fn load(&self, bytes: &[u8], url: &str, resolver: Rc<dyn ResourceResolver>) -> LoadFuture {
+        Box::new(
+            load_obj_async(&String::from_utf8_lossy(bytes), url, resolver).map(|meshes| {
+                let mut group = Object3D::group();
+                for mesh in meshes {
+                    group.add(mesh);
+                }
+                group
+            }),
+        )
+    }
 }
 
 struct ObjFile {
-    objSet: obj::ObjSet,
+    obj_set: obj::ObjSet,
 }
 
 impl ObjFile {
-    fn load_obj_string(string: &str) -> Result<Self, ()> {
+    fn load_obj_string(string: &str) -> Result<Self> {
         web_sys::console::log_1(&"* obj::parse start".into());
 
-        let objSet = obj::parse(string).unwrap();
+        let obj_set = obj_parser::parse(string)?;
 
-        // let obj_len = objSet.objects.len() as f64;
+        // let obj_len = obj_set.objects.len() as f64;
         // web_sys::console::log_1(&JsValue::from_f64(obj_len));
-        // // log_num(objSet.objects[0].geometry[0].shapes.len() as f64);
+        // // log_num(obj_set.objects[0].geometry[0].shapes.len() as f64);
 
         // fn vertexVecToVec(vertexVec: Vec<obj::Vertex>) -> Vec<f32> {
         //     let mut vec = Vec::new();
@@ -36,49 +105,96 @@ impl ObjFile {
         // }
         web_sys::console::log_1(&"* obj::parse finished".into());
 
-        Ok(Self { objSet })
+        Ok(Self { obj_set })
     }
-    fn model(&self) -> Vec<Mesh> {
-        web_sys::console::log_1(&"* ObjFile model".into());
 
-        let mtl_msg = String::from("not have mtl");
+    /// Fetches the `mtllib` of the file, by material name. A library that
+    /// cannot be read or parsed leaves the objects with the default material.
+    fn load_materials(
+        &self,
+        base_url: &str,
+        resolver: Rc<dyn ResourceResolver>,
+    ) -> Box<dyn Future<Item = HashMap<String, Material>, Error = Error>> {
+        let url = match self.obj_set.material_library {
+            Some(ref library) => resolve_uri(base_url, library),
+            None => return Box::new(future::ok(HashMap::new())),
+        };
 
-        let mat_lib = &self.objSet
-            .material_library
-            .as_ref().unwrap_or(&mtl_msg);
+        let future = resolver.fetch(&url).then(move |result| {
+            let materials = result
+                .map_err(Error::from)
+                .and_then(|bytes| parse_mtl(&String::from_utf8_lossy(&bytes)));
+            match materials {
+                Ok(materials) => Ok(materials
+                    .iter()
+                    .map(|material| {
+                        let result = material.to_material(&url, &*resolver);
+                        (material.name.clone(), result)
+                    })
+                    .collect()),
+                Err(err) => {
+                    web_sys::console::warn_1(&format!("{}: {}", url, err).into());
+                    Ok(HashMap::new())
+                }
+            }
+        });
+        Box::new(future)
+    }
 
-        web_sys::console::log_1(&mat_lib.as_str().into());
+    /// One mesh per object, material and primitive type, shapes of the same
+    /// `usemtl` are merged. Lines and points get an unlit material of the same color.
+    fn model(&self, materials: &HashMap<String, Material>) -> Result<Vec<Mesh>> {
+        web_sys::console::log_1(&"* ObjFile model".into());
 
         let mut meshes = Vec::new();
 
-        for object in &self.objSet.objects {
-            let obj_mesh = ObjMesh::from_object(&object, false);
-            let geometry2 = obj_mesh.to_geometry();
-
-            let material = Material {
-                color: [1.0, 0.0, 1.0, 1.0],
-            };
-
-            let mesh = Mesh {
-                geometry: geometry2,
-                material: material,
-                position: vec3::new_zero(),
-                scale: [3.0, 3.0, 3.0],
-                rotation: quat::new_identity(),
-                matrix: [
-                    1.0, 0.0, 0.0, 0.0, //
-                    0.0, 1.0, 0.0, 0.0, //
-                    0.0, 0.0, 1.0, 0.0, //
-                    0.0, 0.0, 0.0, 1.0, //
-                ],
-                __webGLVertexBuffer: None,
-                __webGLFaceBuffer: None,
-                __webGLNormalBuffer: None,
-                __webGLColorBuffer: None,
-            };
-
-            meshes.push(mesh);
+        for object in &self.obj_set.objects {
+            let mut groups: Vec<(DrawMode, obj::Geometry)> = Vec::new();
+            for geometry in &object.geometry {
+                for shape in &geometry.shapes {
+                    let mode = draw_mode(&shape.primitive);
+                    let group = groups.iter_mut().find(|(group_mode, group)| {
+                        *group_mode == mode && group.material_name == geometry.material_name
+                    });
+                    match group {
+                        Some((_, group)) => group.shapes.push(shape.clone()),
+                        None => groups.push((
+                            mode,
+                            obj::Geometry {
+                                material_name: geometry.material_name.clone(),
+                                shapes: vec![shape.clone()],
+                            },
+                        )),
+                    }
+                }
+            }
+
+            for (mode, geometry) in groups {
+                let obj_mesh =
+                    ObjMesh::from_geometries(object, std::slice::from_ref(&geometry), false);
+                let geometry2 = obj_mesh.to_geometry()?;
+
+                let material = geometry
+                    .material_name
+                    .as_ref()
+                    .and_then(|name| materials.get(name))
+                    .cloned()
+                    .unwrap_or_else(|| PhongMaterial::default().into());
+                let material = match mode {
+                    DrawMode::Triangles => material,
+                    _ => BasicMaterial {
+                        color: material.color(),
+                        ..Default::default()
+                    }
+                    .into(),
+                };
+
+                let mut mesh = Mesh::new(geometry2, material);
+                mesh.mode = mode;
+
+                meshes.push(mesh);
+            }
         }
-        meshes
+        Ok(meshes)
     }
 }
@@ -0,0 +1,462 @@
+use crate::error::Result;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use wavefront_obj::ParseError;
+use wavefront_obj::obj::{
+    Geometry, Normal, ObjSet, Object, Primitive, Shape, TVertex, VTNIndex, Vertex,
+};
+
+/// Parses an OBJ file into the `wavefront_obj` types the mesh builder reads.
+/// Unlike `wavefront_obj::obj::parse`, which fans every polygon around its last
+/// corner, polygons are ear clipped so that concave faces keep their shape,
+/// polylines become separate segments and `p` statements become points.
+pub fn parse(string: &str) -> Result<ObjSet> {
+    let mut parser = Parser::default();
+    for (number, line) in string.lines().enumerate() {
+        let line = match line.find('#') {
+            Some(start) => &line[..start],
+            None => line,
+        };
+        parser.statement(line).map_err(|message| ParseError {
+            line_number: number + 1,
+            message,
+        })?;
+    }
+    Ok(parser.finish())
+}
+
+#[derive(Default)]
+struct Parser {
+    material_library: Option<String>,
+    objects: Vec<Object>,
+    /// every `v`, `vt` and `vn` of the file, faces index them across objects
+    vertices: Vec<Vertex>,
+    tex_vertices: Vec<TVertex>,
+    normals: Vec<Normal>,
+    /// indices into the file's lists by indices into the current object's
+    vertex_map: HashMap<usize, usize>,
+    tex_vertex_map: HashMap<usize, usize>,
+    normal_map: HashMap<usize, usize>,
+    material: Option<String>,
+    groups: Vec<String>,
+    smoothing_groups: Vec<u32>,
+}
+
+fn numbers(arguments: &[&str]) -> std::result::Result<Vec<f64>, String> {
+    arguments
+        .iter()
+        .map(|argument| {
+            argument
+                .parse()
+                .map_err(|_| format!("expected a number but got `{}`", argument))
+        })
+        .collect()
+}
+
+/// Converts a one-based or negative, i.e. relative to the end, OBJ index.
+fn resolve_index(index: &str, count: usize) -> std::result::Result<usize, String> {
+    let value: isize = index
+        .parse()
+        .map_err(|_| format!("expected an index but got `{}`", index))?;
+    let resolved = if value < 0 {
+        count as isize + value
+    } else {
+        value - 1
+    };
+    if value == 0 || resolved < 0 || resolved as usize >= count {
+        return Err(format!("index {} is out of range", value));
+    }
+    Ok(resolved as usize)
+}
+
+impl Parser {
+    fn statement(&mut self, line: &str) -> std::result::Result<(), String> {
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => return Ok(()),
+        };
+        let arguments: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => match numbers(&arguments)?[..] {
+                // a w or a vertex color may follow
+                [x, y, z, ..] => self.vertices.push(Vertex { x, y, z }),
+                _ => return Err(String::from("a vertex needs three coordinates")),
+            },
+            "vt" => match numbers(&arguments)?[..] {
+                [u, v, ..] => self.tex_vertices.push(TVertex { u, v, w: 0.0 }),
+                [u] => self.tex_vertices.push(TVertex { u, v: 0.0, w: 0.0 }),
+                _ => return Err(String::from("a texture vertex needs coordinates")),
+            },
+            "vn" => match numbers(&arguments)?[..] {
+                [x, y, z] => self.normals.push(Normal { x, y, z }),
+                _ => return Err(String::from("a normal needs three coordinates")),
+            },
+            "f" => {
+                if arguments.len() < 3 {
+                    return Err(String::from("a face needs at least three vertices"));
+                }
+                let corners = self.corners(&arguments)?;
+                let positions: Vec<[f64; 3]> = arguments
+                    .iter()
+                    .map(|argument| self.position(argument))
+                    .collect::<std::result::Result<_, _>>()?;
+                for [a, b, c] in triangulate(&positions) {
+                    self.add_shape(Primitive::Triangle(corners[a], corners[b], corners[c]));
+                }
+            }
+            "l" => {
+                if arguments.len() < 2 {
+                    return Err(String::from("a line needs at least two vertices"));
+                }
+                let corners = self.corners(&arguments)?;
+                for segment in corners.windows(2) {
+                    self.add_shape(Primitive::Line(segment[0], segment[1]));
+                }
+            }
+            "p" => {
+                for corner in self.corners(&arguments)? {
+                    self.add_shape(Primitive::Point(corner));
+                }
+            }
+            "o" => self.start_object(arguments.join(" ")),
+            "g" => self.groups = arguments.iter().map(|name| name.to_string()).collect(),
+            "s" => {
+                self.smoothing_groups = match arguments.first() {
+                    None | Some(&"off") | Some(&"0") => Vec::new(),
+                    Some(group) => vec![group
+                        .parse()
+                        .map_err(|_| format!("invalid smoothing group `{}`", group))?],
+                }
+            }
+            "usemtl" => self.material = Some(arguments.join(" ")),
+            // only the first library is used
+            "mtllib" if self.material_library.is_none() => {
+                self.material_library = arguments.first().map(|name| name.to_string());
+            }
+            // free-form curves and surfaces, display attributes...
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn position(&self, corner: &str) -> std::result::Result<[f64; 3], String> {
+        let index = corner.split('/').next().unwrap_or("");
+        let vertex = self.vertices[resolve_index(index, self.vertices.len())?];
+        Ok([vertex.x, vertex.y, vertex.z])
+    }
+
+    /// Parses `v`, `v/vt`, `v//vn` or `v/vt/vn` corners into indices of the
+    /// current object, copying the referenced data into it.
+    fn corners(&mut self, arguments: &[&str]) -> std::result::Result<Vec<VTNIndex>, String> {
+        arguments
+            .iter()
+            .map(|argument| {
+                let mut parts = argument.split('/');
+                let vertex = match parts.next() {
+                    Some(index) if !index.is_empty() => self.vertex(index)?,
+                    _ => return Err(format!("missing vertex index in `{}`", argument)),
+                };
+                let tex_vertex = match parts.next() {
+                    Some(index) if !index.is_empty() => Some(self.tex_vertex(index)?),
+                    _ => None,
+                };
+                let normal = match parts.next() {
+                    Some(index) if !index.is_empty() => Some(self.normal(index)?),
+                    _ => None,
+                };
+                if parts.next().is_some() {
+                    return Err(format!("too many indices in `{}`", argument));
+                }
+                Ok((vertex, tex_vertex, normal))
+            })
+            .collect()
+    }
+
+    fn vertex(&mut self, index: &str) -> std::result::Result<usize, String> {
+        let index = resolve_index(index, self.vertices.len())?;
+        self.ensure_object();
+        let object = self.objects.last_mut().unwrap();
+        let vertices = &self.vertices;
+        Ok(*self.vertex_map.entry(index).or_insert_with(|| {
+            object.vertices.push(vertices[index]);
+            object.vertices.len() - 1
+        }))
+    }
+
+    fn tex_vertex(&mut self, index: &str) -> std::result::Result<usize, String> {
+        let index = resolve_index(index, self.tex_vertices.len())?;
+        self.ensure_object();
+        let object = self.objects.last_mut().unwrap();
+        let tex_vertices = &self.tex_vertices;
+        Ok(*self.tex_vertex_map.entry(index).or_insert_with(|| {
+            object.tex_vertices.push(tex_vertices[index]);
+            object.tex_vertices.len() - 1
+        }))
+    }
+
+    fn normal(&mut self, index: &str) -> std::result::Result<usize, String> {
+        let index = resolve_index(index, self.normals.len())?;
+        self.ensure_object();
+        let object = self.objects.last_mut().unwrap();
+        let normals = &self.normals;
+        Ok(*self.normal_map.entry(index).or_insert_with(|| {
+            object.normals.push(normals[index]);
+            object.normals.len() - 1
+        }))
+    }
+
+    fn start_object(&mut self, name: String) {
+        self.objects.push(Object {
+            name,
+            vertices: Vec::new(),
+            tex_vertices: Vec::new(),
+            normals: Vec::new(),
+            geometry: Vec::new(),
+        });
+        self.vertex_map.clear();
+        self.tex_vertex_map.clear();
+        self.normal_map.clear();
+    }
+
+    /// Files without `o` statements get a single unnamed object.
+    fn ensure_object(&mut self) {
+        if self.objects.is_empty() {
+            self.start_object(String::new());
+        }
+    }
+
+    fn add_shape(&mut self, primitive: Primitive) {
+        let shape = Shape {
+            primitive,
+            groups: self.groups.clone(),
+            smoothing_groups: self.smoothing_groups.clone(),
+        };
+        let material = self.material.clone();
+        self.ensure_object();
+        let object = self.objects.last_mut().unwrap();
+        match object.geometry.last_mut() {
+            Some(geometry) if geometry.material_name == material => geometry.shapes.push(shape),
+            _ => object.geometry.push(Geometry {
+                material_name: material,
+                shapes: vec![shape],
+            }),
+        }
+    }
+
+    fn finish(self) -> ObjSet {
+        ObjSet {
+            material_library: self.material_library,
+            objects: self.objects,
+        }
+    }
+}
+
+/// Splits a polygon into triangles by ear clipping, returned as corner indices
+/// in the polygon's winding order. Polygons that aren't simple, e.g. ones that
+/// intersect themselves, are fanned once no ear is left.
+pub fn triangulate(points: &[[f64; 3]]) -> Vec<[usize; 3]> {
+    let count = points.len();
+    if count < 3 {
+        return Vec::new();
+    }
+    if count == 3 {
+        return vec![[0, 1, 2]];
+    }
+
+    // Newell's method, robust for concave and slightly non-planar polygons
+    let mut normal = [0.0; 3];
+    for i in 0..count {
+        let (a, b) = (points[i], points[(i + 1) % count]);
+        normal[0] += (a[1] - b[1]) * (a[2] + b[2]);
+        normal[1] += (a[2] - b[2]) * (a[0] + b[0]);
+        normal[2] += (a[0] - b[0]) * (a[1] + b[1]);
+    }
+    // drop the axis the polygon faces most, the remaining two keep their cyclic
+    // order so counter-clockwise in 2D is the direction of the normal
+    let axis = (0..3)
+        .max_by(|&a, &b| {
+            normal[a]
+                .abs()
+                .partial_cmp(&normal[b].abs())
+                .unwrap_or(Ordering::Equal)
+        })
+        .unwrap();
+    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+    let winding = if normal[axis] < 0.0 { -1.0 } else { 1.0 };
+    let projected: Vec<[f64; 2]> = points.iter().map(|p| [p[u], p[v]]).collect();
+    let area = |a: usize, b: usize, c: usize| {
+        let (a, b, c) = (projected[a], projected[b], projected[c]);
+        winding * ((b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0]))
+    };
+
+    let mut remaining: Vec<usize> = (0..count).collect();
+    let mut triangles = Vec::with_capacity(count - 2);
+    while remaining.len() > 3 {
+        let len = remaining.len();
+        let corners = |i: usize| {
+            (
+                remaining[(i + len - 1) % len],
+                remaining[i],
+                remaining[(i + 1) % len],
+            )
+        };
+        let ear = (0..len).find(|&i| {
+            let (a, b, c) = corners(i);
+            // reflex or degenerate corners can't be cut off
+            if area(a, b, c) <= 0.0 {
+                return false;
+            }
+            !remaining.iter().any(|&p| {
+                p != a
+                    && p != b
+                    && p != c
+                    && area(a, b, p) >= 0.0
+                    && area(b, c, p) >= 0.0
+                    && area(c, a, p) >= 0.0
+            })
+        });
+        match ear {
+            Some(i) => {
+                let (a, b, c) = corners(i);
+                triangles.push([a, b, c]);
+                remaining.remove(i);
+            }
+            None => break,
+        }
+    }
+    for i in 1..remaining.len() - 1 {
+        triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+    }
+    triangles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Twice the signed area of a triangle in the xy plane.
+    fn doubled_area(points: &[[f64; 3]], [a, b, c]: [usize; 3]) -> f64 {
+        let (a, b, c) = (points[a], points[b], points[c]);
+        (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+    }
+
+    fn primitives(set: &ObjSet) -> Vec<Primitive> {
+        set.objects[0]
+            .geometry
+            .iter()
+            .flat_map(|geometry| geometry.shapes.iter().map(|shape| shape.primitive))
+            .collect()
+    }
+
+    #[test]
+    fn ear_clips_concave_polygon() {
+        // an L of area 3, the corner at (1, 1) is reflex
+        let l_shape = [
+            [0.0, 0.0, 0.0],
+            [2.0, 0.0, 0.0],
+            [2.0, 1.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [1.0, 2.0, 0.0],
+            [0.0, 2.0, 0.0],
+        ];
+        let triangles = triangulate(&l_shape);
+        assert_eq!(triangles.len(), 4);
+        // every triangle keeps the winding, so none reaches outside the polygon
+        for &triangle in triangles.iter() {
+            assert!(doubled_area(&l_shape, triangle) > 0.0);
+        }
+        let area: f64 = triangles
+            .iter()
+            .map(|&triangle| doubled_area(&l_shape, triangle) / 2.0)
+            .sum();
+        assert!((area - 3.0).abs() < 1e-9);
+
+        // the same L wound the other way round faces -z
+        let reversed: Vec<[f64; 3]> = l_shape.iter().rev().cloned().collect();
+        let triangles = triangulate(&reversed);
+        assert_eq!(triangles.len(), 4);
+        let area: f64 = triangles
+            .iter()
+            .map(|&triangle| doubled_area(&reversed, triangle) / 2.0)
+            .sum();
+        assert!((area + 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn fans_self_intersecting_polygon() {
+        // a figure eight, no ear is left after the first one
+        let eight = [
+            [0.0, 0.0, 0.0],
+            [2.0, 2.0, 0.0],
+            [4.0, 0.0, 0.0],
+            [4.0, 2.0, 0.0],
+            [2.0, 0.0, 0.0],
+            [0.0, 2.0, 0.0],
+        ];
+        assert_eq!(
+            triangulate(&eight),
+            vec![[5, 0, 1], [1, 2, 3], [1, 3, 4], [1, 4, 5]]
+        );
+    }
+
+    #[test]
+    fn splits_polylines_and_points() {
+        let set = parse("v 0 0 0\nv 1 0 0\nv 1 1 0\nl 1 2 3\np 1 3\n").unwrap();
+        assert_eq!(
+            primitives(&set),
+            vec![
+                Primitive::Line((0, None, None), (1, None, None)),
+                Primitive::Line((1, None, None), (2, None, None)),
+                Primitive::Point((0, None, None)),
+                Primitive::Point((2, None, None)),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_relative_indices() {
+        let set = parse(
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 1\nvn 0 0 1\n\
+             f -4/-2/-1 -3/-1/-1 -2/-1/-1 -1/-2/-1\n",
+        )
+        .unwrap();
+        let object = &set.objects[0];
+        assert_eq!(object.vertices.len(), 4);
+        assert_eq!(object.tex_vertices.len(), 2);
+        assert_eq!(object.normals.len(), 1);
+        assert_eq!(
+            primitives(&set),
+            vec![
+                Primitive::Triangle(
+                    (3, Some(0), Some(0)),
+                    (0, Some(0), Some(0)),
+                    (1, Some(1), Some(0))
+                ),
+                Primitive::Triangle(
+                    (1, Some(1), Some(0)),
+                    (2, Some(1), Some(0)),
+                    (3, Some(0), Some(0))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_range_indices() {
+        let vertices = "v 0 0 0\nv 1 0 0\nv 1 1 0\n";
+        for face in &["f 1 2 4", "f 0 1 2", "f -4 -2 -1", "f 1/1 2/1 3/1", "l 1 9", "p 0"] {
+            match parse(&format!("{}{}\n", vertices, face)) {
+                Err(err) => assert!(err.to_string().contains("line 4"), "{}", err),
+                Ok(_) => panic!("`{}` was accepted", face),
+            }
+        }
+    }
+
+    #[test]
+    fn uses_first_material_library() {
+        let set = parse("mtllib a.mtl b.mtl\nmtllib c.mtl\n").unwrap();
+        assert_eq!(set.material_library.unwrap(), "a.mtl");
+    }
+}
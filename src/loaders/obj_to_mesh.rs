@@ -6,16 +6,18 @@ use half::f16;
 use std::collections::HashMap;
 use std::f64;
 use std::mem::size_of;
-use wavefront_obj::obj::{Normal, Object, Primitive, TVertex, VTNIndex, Vertex};
+use wavefront_obj::obj::{self, Normal, Object, Primitive, TVertex, VTNIndex, Vertex};
 
-use crate::geometry::Geometry;
+use crate::error::Result;
+use crate::geometry::{self, Geometry, Indices};
+use crate::mesh::DrawMode;
 
 fn pack_normalized(val: f64, max: u32) -> u32 {
     f64::ceil(val * max as f64) as u32
 }
 
 fn pack_i2_10_10_10(normal: Normal, w: f64) -> u32 {
-    (pack_normalized(normal.x, 511) << 0)
+    pack_normalized(normal.x, 511)
         | (pack_normalized(normal.y, 511) << 10)
         | (pack_normalized(normal.z, 511) << 20)
         | (pack_normalized(w, 1)) << 30
@@ -67,28 +69,36 @@ fn has_attribute(vtni: VTNIndex, attr: Attribute) -> bool {
     }
 }
 
-fn has_all(obj: &Object, attr: Attribute) -> bool {
-    for geo in &obj.geometry {
-        for shape in &geo.shapes {
-            match shape.primitive {
-                Primitive::Triangle(v1, v2, v3) => {
-                    if !has_attribute(v1, attr)
-                        || !has_attribute(v2, attr)
-                        || !has_attribute(v3, attr)
-                    {
-                        return false;
-                    }
-                }
-                _ => panic!("Unsupported primitive mode"),
-            }
-        }
+/// The corners of a primitive, in order.
+fn corners(primitive: &Primitive) -> Vec<VTNIndex> {
+    match *primitive {
+        Primitive::Point(v1) => vec![v1],
+        Primitive::Line(v1, v2) => vec![v1, v2],
+        Primitive::Triangle(v1, v2, v3) => vec![v1, v2, v3],
     }
-    true
 }
 
-fn get_offset(obj: &Object, attr: Attribute, offset: &mut usize) -> Option<usize> {
+pub fn draw_mode(primitive: &Primitive) -> DrawMode {
+    match primitive {
+        Primitive::Point(_) => DrawMode::Points,
+        Primitive::Line(..) => DrawMode::Lines,
+        Primitive::Triangle(..) => DrawMode::Triangles,
+    }
+}
+
+fn has_all(geometries: &[obj::Geometry], attr: Attribute) -> bool {
+    geometries.iter().all(|geo| {
+        geo.shapes.iter().all(|shape| {
+            corners(&shape.primitive)
+                .into_iter()
+                .all(|vtni| has_attribute(vtni, attr))
+        })
+    })
+}
+
+fn get_offset(geometries: &[obj::Geometry], attr: Attribute, offset: &mut usize) -> Option<usize> {
     let orig_offs = *offset;
-    if has_all(obj, attr) {
+    if has_all(geometries, attr) {
         *offset += size_of_attribute(attr);
         return Some(orig_offs);
     }
@@ -96,11 +106,11 @@ fn get_offset(obj: &Object, attr: Attribute, offset: &mut usize) -> Option<usize
 }
 
 impl VertexFieldOffsets {
-    fn from_object(obj: &Object, with_tangent: bool) -> Self {
+    fn from_geometries(geometries: &[obj::Geometry], with_tangent: bool) -> Self {
         let mut offset = size_of_attribute(Attribute::Position);
 
         VertexFieldOffsets {
-            normal: get_offset(obj, Attribute::Normal, &mut offset),
+            normal: get_offset(geometries, Attribute::Normal, &mut offset),
             tangent: if with_tangent {
                 let orig_offset = offset;
                 offset += size_of_attribute(Attribute::Tangent);
@@ -108,7 +118,7 @@ impl VertexFieldOffsets {
             } else {
                 None
             },
-            tex0: get_offset(obj, Attribute::Tex0, &mut offset),
+            tex0: get_offset(geometries, Attribute::Tex0, &mut offset),
         }
     }
 }
@@ -168,6 +178,8 @@ pub struct Mesh {
     pub indices: Vec<usize>,
     pub map: HashMap<VTNIndex, usize>,
     pub format: VertexFieldOffsets,
+    /// primitive type of every shape, meshes are built from one kind at a time
+    pub mode: DrawMode,
     pub min: Vertex,
     pub max: Vertex,
 }
@@ -247,7 +259,17 @@ fn mul(a: Vertex, b: f64) -> Vertex {
 
 impl Mesh {
     pub fn from_object(obj: &Object, generate_tangents: bool) -> Self {
-        let format = VertexFieldOffsets::from_object(&obj, generate_tangents);
+        Mesh::from_geometries(obj, &obj.geometry, generate_tangents)
+    }
+
+    /// Builds a mesh of some of the geometry groups of `obj`, e.g. the faces of
+    /// one `usemtl` statement. The shapes must all be triangles, lines or points.
+    pub fn from_geometries(
+        obj: &Object,
+        geometries: &[obj::Geometry],
+        generate_tangents: bool,
+    ) -> Self {
+        let format = VertexFieldOffsets::from_geometries(geometries, generate_tangents);
         let mut mesh = Mesh {
             vertices: Vec::new(),
             indices: Vec::new(),
@@ -262,23 +284,24 @@ impl Mesh {
                 y: f64::MIN,
                 z: f64::MIN,
             },
-            format: format,
+            format,
+            mode: geometries
+                .iter()
+                .flat_map(|geo| geo.shapes.first())
+                .map(|shape| draw_mode(&shape.primitive))
+                .next()
+                .unwrap_or(DrawMode::Triangles),
         };
 
-        for geo in &obj.geometry {
+        for geo in geometries {
             for shape in &geo.shapes {
-                match shape.primitive {
-                    Primitive::Triangle(v1, v2, v3) => {
-                        mesh.add_index(v1, &obj, &format);
-                        mesh.add_index(v2, &obj, &format);
-                        mesh.add_index(v3, &obj, &format);
-                    }
-                    _ => panic!("Unsupported primitive mode"),
+                for vtni in corners(&shape.primitive) {
+                    mesh.add_index(vtni, obj, &format);
                 }
             }
         }
 
-        if generate_tangents {
+        if generate_tangents && mesh.mode == DrawMode::Triangles {
             //http://gamedev.stackexchange.com/questions/68612/how-to-compute-tangent-and-bitangent-vectors
 
             let mut tan1 = vec![
@@ -300,7 +323,7 @@ impl Mesh {
 
             let mut ii = 0;
             while ii < mesh.indices.len() {
-                let i1 = mesh.indices[ii + 0];
+                let i1 = mesh.indices[ii];
                 let i2 = mesh.indices[ii + 1];
                 let i3 = mesh.indices[ii + 2];
 
@@ -403,9 +426,11 @@ impl Mesh {
             _ => 4,
         }
     }
-    pub fn to_geometry(&self) -> Geometry {
+    pub fn to_geometry(&self) -> Result<Geometry> {
         let mut vertices = Vec::new();
         let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        let mut tangents = Vec::new();
         let mut indices = Vec::new();
 
         for vertex in &self.vertices {
@@ -418,16 +443,41 @@ impl Mesh {
                 normals.push(normal.y as f32);
                 normals.push(normal.z as f32);
             }
+
+            if let Some(tex) = vertex.tex {
+                uvs.push(tex.u as f32);
+                uvs.push(tex.v as f32);
+            }
+
+            if let Some(tangent) = vertex.tangent {
+                tangents.push(tangent.x as f32);
+                tangents.push(tangent.y as f32);
+                tangents.push(tangent.z as f32);
+                tangents.push(vertex.tangent_handedness as f32);
+            }
         }
 
         for indi in &self.indices {
-            indices.push(*indi as u16);
+            indices.push(*indi as u32);
         }
 
-        Geometry {
-            vertices: vertices,
-            normals: normals,
-            indices: indices,
+        let mut geometry = Geometry::new(Indices::from_u32(indices));
+        geometry.set_attribute(geometry::POSITION, geometry::Attribute::new(vertices, 3));
+        let has_normals = !normals.is_empty();
+        if has_normals {
+            geometry.set_attribute(geometry::NORMAL, geometry::Attribute::new(normals, 3));
+        }
+        if !uvs.is_empty() {
+            geometry.set_attribute(geometry::UV, geometry::Attribute::new(uvs, 2));
+        }
+        if !tangents.is_empty() {
+            geometry.set_attribute(geometry::TANGENT, geometry::Attribute::new(tangents, 4));
+        }
+        geometry.validate()?;
+        // lines and points are drawn unlit
+        if !has_normals && self.mode == DrawMode::Triangles {
+            geometry.compute_vertex_normals();
         }
+        Ok(geometry)
     }
 }
@@ -0,0 +1,634 @@
+use super::obj_parser::triangulate;
+use super::registry::{LoadFuture, Loader};
+use super::resolver::ResourceResolver;
+use crate::error::{Error, Result};
+use crate::geometry::{self, Attribute, Geometry, Indices};
+use crate::material::{BasicMaterial, PhongMaterial};
+use crate::mesh::{DrawMode, Mesh};
+use crate::object3d::Object3D;
+use futures::future;
+use std::rc::Rc;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Scalar> {
+        Some(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    /// Integer colors span the whole range of their type.
+    fn color_scale(self) -> f32 {
+        match self {
+            Scalar::U8 => 255.0,
+            Scalar::U16 => 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Property {
+    Scalar(String, Scalar),
+    /// the type of the length, then of the items
+    List(String, Scalar, Scalar),
+}
+
+impl Property {
+    fn name(&self) -> &str {
+        match self {
+            Property::Scalar(name, _) | Property::List(name, _, _) => name,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+/// The vertices, and faces if any, of a PLY file.
+#[derive(Clone, Debug, Default)]
+pub struct PlyFile {
+    /// three components per vertex
+    pub positions: Vec<f32>,
+    pub normals: Option<Vec<f32>>,
+    /// three or, with alpha, four components per vertex, from 0 to 1
+    pub colors: Option<Vec<f32>>,
+    pub uvs: Option<Vec<f32>>,
+    /// every other scalar vertex property, e.g. the `intensity` of a scan
+    pub custom: Vec<(String, Vec<f32>)>,
+    /// three indices per triangle, polygons are triangulated
+    pub indices: Vec<u32>,
+}
+
+/// Reads the values of the body, `Ascii` ones separated by whitespace.
+enum Reader<'a> {
+    /// the text that hasn't been read yet
+    Ascii(&'a str),
+    Binary {
+        bytes: &'a [u8],
+        offset: usize,
+        big_endian: bool,
+    },
+}
+
+impl<'a> Reader<'a> {
+    fn read(&mut self, scalar: Scalar) -> Result<f64> {
+        match self {
+            Reader::Ascii(text) => {
+                let rest = text.trim_start();
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                let (token, rest) = rest.split_at(end);
+                *text = rest;
+                if token.is_empty() {
+                    return Err(Error::Parse(String::from("PLY data ends early")));
+                }
+                token
+                    .parse()
+                    .map_err(|_| Error::Parse(format!("expected a number but got `{}`", token)))
+            }
+            Reader::Binary {
+                bytes,
+                offset,
+                big_endian,
+            } => {
+                let size = scalar.size();
+                let data = bytes
+                    .get(*offset..*offset + size)
+                    .ok_or_else(|| Error::Parse(String::from("PLY data ends early")))?;
+                *offset += size;
+                let mut word = [0; 8];
+                word[..size].copy_from_slice(data);
+                if *big_endian {
+                    word[..size].reverse();
+                }
+                let [a, b, c, d, ..] = word;
+                Ok(match scalar {
+                    Scalar::I8 => a as i8 as f64,
+                    Scalar::U8 => a as f64,
+                    Scalar::I16 => i16::from_le_bytes([a, b]) as f64,
+                    Scalar::U16 => u16::from_le_bytes([a, b]) as f64,
+                    Scalar::I32 => i32::from_le_bytes([a, b, c, d]) as f64,
+                    Scalar::U32 => u32::from_le_bytes([a, b, c, d]) as f64,
+                    Scalar::F32 => f32::from_le_bytes([a, b, c, d]) as f64,
+                    Scalar::F64 => f64::from_le_bytes(word),
+                })
+            }
+        }
+    }
+
+    /// Whether the rest of the data is long enough for `count` elements of
+    /// `properties`, so that counts from the header can be trusted with an
+    /// allocation. Every ASCII value takes at least one character.
+    fn can_hold(&self, count: usize, properties: &[Property]) -> bool {
+        let (size, remaining) = match self {
+            Reader::Ascii(text) => (properties.len(), text.len()),
+            Reader::Binary { bytes, offset, .. } => (
+                properties
+                    .iter()
+                    .map(|property| match property {
+                        Property::Scalar(_, scalar) | Property::List(_, scalar, _) => scalar.size(),
+                    })
+                    .sum(),
+                bytes.len() - *offset,
+            ),
+        };
+        count
+            .checked_mul(size)
+            .is_some_and(|size| size <= remaining)
+    }
+}
+
+fn parse_header(bytes: &[u8]) -> Result<(Format, Vec<Element>, usize)> {
+    let error = |reason: &str| Error::Parse(format!("PLY header: {}", reason));
+    let end = bytes
+        .windows(b"end_header".len())
+        .position(|window| window == b"end_header")
+        .ok_or_else(|| error("missing end_header"))?;
+    // the body starts after the line break of end_header, `\r\n` or `\n`
+    let mut body = end + b"end_header".len();
+    if bytes.get(body) == Some(&b'\r') {
+        body += 1;
+    }
+    if bytes.get(body) == Some(&b'\n') {
+        body += 1;
+    }
+
+    let header = String::from_utf8_lossy(&bytes[..end]);
+    let mut lines = header.lines();
+    if lines.next().map(|line| line.trim()) != Some("ply") {
+        return Err(error("missing `ply` signature"));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens[..] {
+            ["format", name, _] => {
+                format = Some(match name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::LittleEndian,
+                    "binary_big_endian" => Format::BigEndian,
+                    _ => return Err(error(&format!("unknown format `{}`", name))),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| error(&format!("invalid count of `{}`", name)))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => {
+                let property = match (Scalar::parse(count), Scalar::parse(item)) {
+                    (Some(count), Some(item)) => Property::List(name.to_string(), count, item),
+                    _ => return Err(error(&format!("unknown type in `{}`", line.trim()))),
+                };
+                elements
+                    .last_mut()
+                    .ok_or_else(|| error("property before any element"))?
+                    .properties
+                    .push(property);
+            }
+            ["property", scalar, name] => {
+                let scalar = Scalar::parse(scalar)
+                    .ok_or_else(|| error(&format!("unknown type in `{}`", line.trim())))?;
+                elements
+                    .last_mut()
+                    .ok_or_else(|| error("property before any element"))?
+                    .properties
+                    .push(Property::Scalar(name.to_string(), scalar));
+            }
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return Err(error(&format!("unexpected `{}`", line.trim()))),
+        }
+    }
+    let format = format.ok_or_else(|| error("missing format"))?;
+    Ok((format, elements, body))
+}
+
+/// Which vertex attribute a property belongs to, and its component.
+fn vertex_slot(name: &str) -> Option<(&'static str, usize)> {
+    Some(match name {
+        "x" => (geometry::POSITION, 0),
+        "y" => (geometry::POSITION, 1),
+        "z" => (geometry::POSITION, 2),
+        "nx" => (geometry::NORMAL, 0),
+        "ny" => (geometry::NORMAL, 1),
+        "nz" => (geometry::NORMAL, 2),
+        "red" | "r" | "diffuse_red" => (geometry::COLOR, 0),
+        "green" | "g" | "diffuse_green" => (geometry::COLOR, 1),
+        "blue" | "b" | "diffuse_blue" => (geometry::COLOR, 2),
+        "alpha" | "a" | "diffuse_alpha" => (geometry::COLOR, 3),
+        "u" | "s" | "texture_u" | "texture_s" => (geometry::UV, 0),
+        "v" | "t" | "texture_v" | "texture_t" => (geometry::UV, 1),
+        _ => return None,
+    })
+}
+
+/// Reads an ASCII or binary PLY file. Besides `vertex` and `face`, elements
+/// are skipped, as are the list properties of vertices and the other
+/// properties of faces.
+pub fn parse_ply(bytes: &[u8]) -> Result<PlyFile> {
+    let (format, elements, body) = parse_header(bytes)?;
+    let mut reader = match format {
+        Format::Ascii => Reader::Ascii(
+            std::str::from_utf8(&bytes[body..])
+                .map_err(|_| Error::Parse(String::from("ASCII PLY data isn't text")))?,
+        ),
+        _ => Reader::Binary {
+            bytes: &bytes[body..],
+            offset: 0,
+            big_endian: format == Format::BigEndian,
+        },
+    };
+
+    let mut file = PlyFile::default();
+    let mut vertex_count = 0;
+    for element in &elements {
+        if !reader.can_hold(element.count, &element.properties) {
+            return Err(Error::Parse(format!(
+                "PLY header announces {} `{}` elements, more than the file holds",
+                element.count, element.name
+            )));
+        }
+        match element.name.as_str() {
+            "vertex" => {
+                vertex_count = element.count;
+                read_vertices(&mut reader, element, &mut file)?;
+            }
+            "face" => read_faces(&mut reader, element, vertex_count, &mut file)?,
+            _ => {
+                for _ in 0..element.count {
+                    for property in &element.properties {
+                        read_property(&mut reader, property)?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(file)
+}
+
+/// Reads one value or list.
+fn read_property(reader: &mut Reader, property: &Property) -> Result<Vec<f64>> {
+    match property {
+        Property::Scalar(_, scalar) => Ok(vec![reader.read(*scalar)?]),
+        Property::List(_, count, item) => {
+            let count = reader.read(*count)? as usize;
+            (0..count).map(|_| reader.read(*item)).collect()
+        }
+    }
+}
+
+fn read_vertices(reader: &mut Reader, element: &Element, file: &mut PlyFile) -> Result<()> {
+    let has = |attribute: &str| {
+        element
+            .properties
+            .iter()
+            .any(|property| vertex_slot(property.name()).map(|(name, _)| name) == Some(attribute))
+    };
+    if !has(geometry::POSITION) {
+        return Err(Error::Parse(String::from("PLY vertices have no position")));
+    }
+    let color_size = if element
+        .properties
+        .iter()
+        .any(|property| vertex_slot(property.name()) == Some((geometry::COLOR, 3)))
+    {
+        4
+    } else {
+        3
+    };
+
+    let count = element.count;
+    file.positions = vec![0.0; count * 3];
+    file.normals = if has(geometry::NORMAL) {
+        Some(vec![0.0; count * 3])
+    } else {
+        None
+    };
+    // colors without alpha are opaque
+    file.colors = if has(geometry::COLOR) {
+        Some(vec![1.0; count * color_size])
+    } else {
+        None
+    };
+    file.uvs = if has(geometry::UV) {
+        Some(vec![0.0; count * 2])
+    } else {
+        None
+    };
+    file.custom = element
+        .properties
+        .iter()
+        .filter_map(|property| match property {
+            Property::Scalar(name, _) if vertex_slot(name).is_none() => {
+                Some((name.clone(), Vec::with_capacity(count)))
+            }
+            _ => None,
+        })
+        .collect();
+
+    for vertex in 0..count {
+        let mut custom = file.custom.iter_mut();
+        for property in &element.properties {
+            let values = read_property(reader, property)?;
+            let scalar = match property {
+                Property::Scalar(_, scalar) => *scalar,
+                Property::List(..) => continue,
+            };
+            let value = values[0];
+            match vertex_slot(property.name()) {
+                Some((geometry::POSITION, i)) => file.positions[vertex * 3 + i] = value as f32,
+                Some((geometry::NORMAL, i)) => {
+                    if let Some(normals) = &mut file.normals {
+                        normals[vertex * 3 + i] = value as f32;
+                    }
+                }
+                Some((geometry::COLOR, i)) => {
+                    if let Some(colors) = &mut file.colors {
+                        colors[vertex * color_size + i] = value as f32 / scalar.color_scale();
+                    }
+                }
+                Some((_, i)) => {
+                    if let Some(uvs) = &mut file.uvs {
+                        uvs[vertex * 2 + i] = value as f32;
+                    }
+                }
+                None => {
+                    if let Some((_, values)) = custom.next() {
+                        values.push(value as f32);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_faces(
+    reader: &mut Reader,
+    element: &Element,
+    vertex_count: usize,
+    file: &mut PlyFile,
+) -> Result<()> {
+    for _ in 0..element.count {
+        for property in &element.properties {
+            let values = read_property(reader, property)?;
+            if property.name() != "vertex_indices" && property.name() != "vertex_index" {
+                continue;
+            }
+            let corners = values
+                .iter()
+                .map(|&index| {
+                    if index >= 0.0 && index < vertex_count as f64 && index.fract() == 0.0 {
+                        Ok(index as u32)
+                    } else {
+                        Err(Error::Parse(format!(
+                            "PLY face refers to vertex {} of {}",
+                            index, vertex_count
+                        )))
+                    }
+                })
+                .collect::<Result<Vec<u32>>>()?;
+            let points: Vec<[f64; 3]> = corners
+                .iter()
+                .map(|&index| {
+                    let position = &file.positions[index as usize * 3..index as usize * 3 + 3];
+                    [position[0] as f64, position[1] as f64, position[2] as f64]
+                })
+                .collect();
+            for [a, b, c] in triangulate(&points) {
+                file.indices
+                    .extend_from_slice(&[corners[a], corners[b], corners[c]]);
+            }
+        }
+    }
+    Ok(())
+}
+
+impl PlyFile {
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len() / 3
+    }
+
+    /// Files without faces are point clouds.
+    pub fn is_point_cloud(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    /// The triangles or, for a point cloud, every vertex in order.
+    pub fn to_geometry(&self) -> Geometry {
+        let indices = if self.is_point_cloud() {
+            (0..self.vertex_count() as u32).collect()
+        } else {
+            self.indices.clone()
+        };
+        let mut geometry = Geometry::new(Indices::from_u32(indices));
+        geometry.set_attribute(
+            geometry::POSITION,
+            Attribute::new(self.positions.clone(), 3),
+        );
+        if let Some(colors) = &self.colors {
+            let size = colors.len() / self.vertex_count().max(1);
+            geometry.set_attribute(geometry::COLOR, Attribute::new(colors.clone(), size));
+        }
+        if let Some(uvs) = &self.uvs {
+            geometry.set_attribute(geometry::UV, Attribute::new(uvs.clone(), 2));
+        }
+        for (name, values) in &self.custom {
+            geometry.set_attribute(name, Attribute::new(values.clone(), 1));
+        }
+        match &self.normals {
+            Some(normals) => {
+                geometry.set_attribute(geometry::NORMAL, Attribute::new(normals.clone(), 3))
+            }
+            None if !self.is_point_cloud() => geometry.compute_vertex_normals(),
+            None => {}
+        }
+        geometry
+    }
+
+    /// A lit triangle mesh, or an unlit point cloud drawn with `POINTS`. Both
+    /// are tinted by the vertex colors.
+    pub fn to_mesh(&self) -> Mesh {
+        if self.is_point_cloud() {
+            let mut mesh = Mesh::new(self.to_geometry(), BasicMaterial::default().into());
+            mesh.mode = DrawMode::Points;
+            mesh
+        } else {
+            Mesh::new(self.to_geometry(), PhongMaterial::default().into())
+        }
+    }
+}
+
+/// PLY files, ASCII or binary.
+pub struct PlyLoader;
+
+impl Loader for PlyLoader {
+    fn extensions(&self) -> &[&str] {
+        &["ply"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/ply", "text/plain+ply"]
+    }
+
+    fn sniff(&self, bytes: &[u8]) -> bool {
+        bytes.starts_with(b"ply\n") || bytes.starts_with(b"ply\r\n")
+    }
+
+    fn load(&self, bytes: &[u8], _url: &str, _resolver: Rc<dyn ResourceResolver>) -> LoadFuture {
+        let result = parse_ply(bytes).map(|file| Object3D::from(file.to_mesh()));
+        Box::new(future::result(result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASCII: &[u8] = include_bytes!("../../tests/fixtures/quad_ascii.ply");
+    const BIG_ENDIAN: &[u8] = include_bytes!("../../tests/fixtures/points_big_endian.ply");
+    const LITTLE_ENDIAN: &[u8] = include_bytes!("../../tests/fixtures/triangle_little_endian.ply");
+
+    #[test]
+    fn reads_ascii_vertices_and_faces() {
+        let file = parse_ply(ASCII).unwrap();
+        assert_eq!(file.vertex_count(), 4);
+        assert_eq!(&file.positions[6..9], &[1.0, 1.0, 0.0]);
+        // the quad is split into two triangles
+        assert_eq!(file.indices.len(), 6);
+        assert!(!file.is_point_cloud());
+        let colors = file.colors.as_ref().unwrap();
+        assert_eq!(colors.len(), 12);
+        assert_eq!(&colors[3..6], &[0.0, 1.0, 0.0]);
+        assert_eq!(file.custom.len(), 1);
+        assert_eq!(file.custom[0].0, "intensity");
+        assert_eq!(file.custom[0].1, vec![0.5, 0.25, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn computes_normals_of_meshes() {
+        let geometry = parse_ply(ASCII).unwrap().to_geometry();
+        let normals = geometry.attribute(geometry::NORMAL).unwrap();
+        assert_eq!(normals.get_item(0), vec![0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn reads_big_endian_point_cloud() {
+        let file = parse_ply(BIG_ENDIAN).unwrap();
+        assert!(file.is_point_cloud());
+        assert_eq!(&file.positions[..3], &[1.5, -2.0, 3.25]);
+        assert_eq!(&file.positions[6..], &[-1.0, 1.0, -1.0]);
+        let colors = file.colors.as_ref().unwrap();
+        // alpha makes four components
+        assert_eq!(&colors[..8], &[1.0, 0.0, 0.0, 1.0, 0.0, 1.0, 0.0, 0.0]);
+
+        let mesh = file.to_mesh();
+        assert_eq!(mesh.mode, DrawMode::Points);
+        assert_eq!(mesh.geometry.indices.len(), 3);
+        assert!(mesh.geometry.attribute(geometry::NORMAL).is_none());
+    }
+
+    #[test]
+    fn skips_other_elements_and_properties() {
+        let file = parse_ply(LITTLE_ENDIAN).unwrap();
+        assert_eq!(file.indices, vec![0, 1, 2]);
+        assert_eq!(file.uvs.unwrap(), vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0]);
+        assert!(file.custom.is_empty());
+    }
+
+    #[test]
+    fn rejects_truncated_binary() {
+        match parse_ply(&LITTLE_ENDIAN[..LITTLE_ENDIAN.len() - 2]) {
+            Err(Error::Parse(message)) => assert!(message.contains("ends early")),
+            other => panic!("expected a parse error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn rejects_faces_of_missing_vertices() {
+        let ply = "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\n\
+                   property float y\nproperty float z\nelement face 1\n\
+                   property list uchar int vertex_index\nend_header\n0 0 0\n3 0 1 2\n";
+        match parse_ply(ply.as_bytes()) {
+            Err(Error::Parse(message)) => assert!(message.contains("vertex 1 of 1")),
+            other => panic!("expected a parse error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn rejects_faces_of_invalid_indices() {
+        for index in &["-1", "0.5", "nan"] {
+            let ply = format!(
+                "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\n\
+                 property float y\nproperty float z\nelement face 1\n\
+                 property list uchar float vertex_index\nend_header\n\
+                 0 0 0\n1 0 0\n0 1 0\n3 0 1 {}\n",
+                index
+            );
+            match parse_ply(ply.as_bytes()) {
+                Err(Error::Parse(message)) => assert!(message.contains("of 3")),
+                other => panic!("expected a parse error, got {:?}", other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_counts_larger_than_the_data() {
+        let header = |format: &str| {
+            format!(
+                "ply\nformat {} 1.0\nelement vertex 4000000000\nproperty float x\n\
+                 property float y\nproperty float z\nend_header\n0 0 0\n",
+                format
+            )
+        };
+        for format in &["ascii", "binary_little_endian"] {
+            match parse_ply(header(format).as_bytes()) {
+                Err(Error::Parse(message)) => assert!(message.contains("4000000000")),
+                other => panic!("expected a parse error, got {:?}", other.map(|_| ())),
+            }
+        }
+    }
+}
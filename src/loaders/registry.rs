@@ -0,0 +1,119 @@
+use super::gltf::GltfLoader;
+use super::obj::ObjLoader;
+use super::ply::PlyLoader;
+use super::resolver::ResourceResolver;
+use super::stl::StlLoader;
+use crate::error::Error;
+use crate::object3d::Object3D;
+use futures::{future, Future};
+use std::rc::Rc;
+
+pub type LoadFuture = Box<dyn Future<Item = Object3D, Error = Error>>;
+
+/// A model format the registry can pick by file name, MIME type or content.
+pub trait Loader {
+    /// Lower-case file extensions, without the dot.
+    fn extensions(&self) -> &[&str];
+
+    fn mime_types(&self) -> &[&str];
+
+    /// Whether `bytes`, the contents of the whole file, look like this format.
+    fn sniff(&self, bytes: &[u8]) -> bool;
+
+    /// Loads the file at `url` from its contents; the files it refers to, such as
+    /// materials, buffers or images, are loaded through `resolver` relative to `url`.
+    fn load(&self, bytes: &[u8], url: &str, resolver: Rc<dyn ResourceResolver>) -> LoadFuture;
+}
+
+/// The extension of the last path segment of `url`, ignoring the query and fragment.
+pub fn extension(url: &str) -> Option<String> {
+    let path = url.split(['?', '#'].as_ref()).next().unwrap_or("");
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let dot = name.rfind('.')?;
+    Some(name[dot + 1..].to_ascii_lowercase())
+}
+
+/// Picks the loader of a file, e.g. one the user dropped onto the page.
+pub struct LoaderRegistry {
+    loaders: Vec<Box<dyn Loader>>,
+}
+
+impl LoaderRegistry {
+    pub fn new() -> LoaderRegistry {
+        LoaderRegistry {
+            loaders: Vec::new(),
+        }
+    }
+
+    /// Adds a loader, it takes precedence over the ones registered before for
+    /// the same extension, MIME type or content.
+    pub fn register<L: Loader + 'static>(&mut self, loader: L) {
+        self.loaders.insert(0, Box::new(loader));
+    }
+
+    pub fn for_extension(&self, extension: &str) -> Option<&dyn Loader> {
+        let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+        self.find(|loader| loader.extensions().contains(&extension.as_str()))
+    }
+
+    /// Parameters such as `; charset=utf-8` are ignored.
+    pub fn for_mime_type(&self, mime_type: &str) -> Option<&dyn Loader> {
+        let mime_type = mime_type
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_ascii_lowercase();
+        self.find(|loader| loader.mime_types().contains(&mime_type.as_str()))
+    }
+
+    pub fn for_content(&self, bytes: &[u8]) -> Option<&dyn Loader> {
+        self.find(|loader| loader.sniff(bytes))
+    }
+
+    /// Tries the extension of `url`, then `mime_type`, then the content. Servers
+    /// and browsers often report generic types like `application/octet-stream`,
+    /// those simply match no loader.
+    pub fn select(&self, url: &str, mime_type: Option<&str>, bytes: &[u8]) -> Option<&dyn Loader> {
+        extension(url)
+            .and_then(|extension| self.for_extension(&extension))
+            .or_else(|| mime_type.and_then(|mime_type| self.for_mime_type(mime_type)))
+            .or_else(|| self.for_content(bytes))
+    }
+
+    /// Loads a file whose contents are already at hand, see `select`.
+    pub fn load(
+        &self,
+        bytes: &[u8],
+        url: &str,
+        mime_type: Option<&str>,
+        resolver: Rc<dyn ResourceResolver>,
+    ) -> LoadFuture {
+        match self.select(url, mime_type, bytes) {
+            Some(loader) => loader.load(bytes, url, resolver),
+            None => Box::new(future::err(Error::Unsupported(format!(
+                "no loader for {}",
+                url
+            )))),
+        }
+    }
+
+    fn find(&self, matches: impl Fn(&dyn Loader) -> bool) -> Option<&dyn Loader> {
+        self.loaders
+            .iter()
+            .map(|loader| loader.as_ref())
+            .find(|&loader| matches(loader))
+    }
+}
+
+/// A registry of every format the crate reads.
+impl Default for LoaderRegistry {
+    fn default() -> LoaderRegistry {
+        let mut registry = LoaderRegistry::new();
+        registry.register(ObjLoader);
+        registry.register(GltfLoader);
+        registry.register(StlLoader::default());
+        registry.register(PlyLoader);
+        registry
+    }
+}
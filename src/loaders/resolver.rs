@@ -0,0 +1,76 @@
+use crate::error::Result;
+use crate::texture::Texture;
+use crate::utils;
+use futures::{future, Future};
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+
+/// A file referenced by a model could not be read.
+#[derive(Debug, Clone)]
+pub struct ResourceError {
+    pub url: String,
+    pub reason: String,
+}
+
+impl fmt::Display for ResourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cannot load {}: {}", self.url, self.reason)
+    }
+}
+
+impl Error for ResourceError {}
+
+/// Resolves `uri` against the URL of the file that references it.
+pub fn resolve_uri(base_url: &str, uri: &str) -> String {
+    if uri.starts_with("data:") || uri.contains("://") || uri.starts_with('/') {
+        return uri.to_string();
+    }
+    match base_url.rfind('/') {
+        Some(end) => format!("{}{}", &base_url[..=end], uri),
+        None => uri.to_string(),
+    }
+}
+
+pub type ResourceFuture = Box<dyn Future<Item = Vec<u8>, Error = ResourceError>>;
+
+/// Reads the files a model refers to, such as the `.bin` buffers of a glTF.
+/// URLs are already resolved against the URL of the model.
+pub trait ResourceResolver {
+    fn fetch(&self, url: &str) -> ResourceFuture;
+
+    /// Creates a texture whose image the browser loads from `url`.
+    fn load_texture(&self, url: &str) -> Result<Texture> {
+        Texture::from_url(url)
+    }
+}
+
+/// Downloads resources with the browser's `fetch`.
+pub struct FetchResolver;
+
+impl ResourceResolver for FetchResolver {
+    fn fetch(&self, url: &str) -> ResourceFuture {
+        utils::get_array_buffer_from_xhr(url)
+    }
+}
+
+/// Reads resources from the filesystem, URLs are paths relative to `root`.
+pub struct FileResolver {
+    pub root: PathBuf,
+}
+
+impl FileResolver {
+    pub fn new(root: impl Into<PathBuf>) -> FileResolver {
+        FileResolver { root: root.into() }
+    }
+}
+
+impl ResourceResolver for FileResolver {
+    fn fetch(&self, url: &str) -> ResourceFuture {
+        let result = std::fs::read(self.root.join(url)).map_err(|err| ResourceError {
+            url: url.to_string(),
+            reason: err.to_string(),
+        });
+        Box::new(future::result(result))
+    }
+}
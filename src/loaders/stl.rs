@@ -0,0 +1,481 @@
+use super::registry::{LoadFuture, Loader};
+use super::resolver::ResourceResolver;
+use crate::error::{Error, Result};
+use crate::geometry::{self, Attribute, Geometry, Indices};
+use crate::material::PhongMaterial;
+use crate::mesh::Mesh;
+use crate::object3d::Object3D;
+use futures::future;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+const HEADER_SIZE: usize = 80;
+/// a normal, three corners and the attribute byte count
+const TRIANGLE_SIZE: usize = 50;
+
+/// The triangles of an STL file.
+#[derive(Clone, Debug, Default)]
+pub struct StlFile {
+    /// the name after `solid` in ASCII files
+    pub name: String,
+    /// three corners per triangle
+    pub positions: Vec<[f32; 3]>,
+    /// one unit normal per triangle, computed from the corners when the file's is zero
+    pub normals: Vec<[f32; 3]>,
+    /// one color per triangle, only binary files with a `COLOR=` header have them
+    pub colors: Option<Vec<[f32; 3]>>,
+}
+
+/// Reads an ASCII or binary STL file, the format is told apart by content.
+pub fn parse_stl(bytes: &[u8]) -> Result<StlFile> {
+    if is_binary(bytes) {
+        parse_binary(bytes)
+    } else {
+        parse_ascii(&String::from_utf8_lossy(bytes))
+    }
+}
+
+/// Size of a binary file of `count` triangles, `None` if it doesn't fit in memory.
+fn binary_size(count: usize) -> Option<usize> {
+    count
+        .checked_mul(TRIANGLE_SIZE)?
+        .checked_add(HEADER_SIZE + 4)
+}
+
+/// Whether the size of the file is the one its binary header announces.
+fn has_binary_size(bytes: &[u8]) -> bool {
+    bytes.len() >= HEADER_SIZE + 4
+        && binary_size(read_u32(bytes, HEADER_SIZE) as usize) == Some(bytes.len())
+}
+
+/// Binary files may start with `solid` too, a file whose size matches the
+/// triangle count of a binary header or that isn't text is binary.
+fn is_binary(bytes: &[u8]) -> bool {
+    if has_binary_size(bytes) {
+        return true;
+    }
+    let text = std::str::from_utf8(bytes).map(|text| text.trim_start());
+    match text {
+        Ok(text) => !text.starts_with("solid") || text.contains('\0'),
+        Err(_) => true,
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut word = [0; 4];
+    word.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(word)
+}
+
+fn read_vector(bytes: &[u8], offset: usize) -> [f32; 3] {
+    let component = |i: usize| f32::from_bits(read_u32(bytes, offset + i * 4));
+    [component(0), component(1), component(2)]
+}
+
+fn parse_binary(bytes: &[u8]) -> Result<StlFile> {
+    if bytes.len() < HEADER_SIZE + 4 {
+        return Err(Error::Parse(String::from(
+            "STL file is too short for a header",
+        )));
+    }
+    let count = read_u32(bytes, HEADER_SIZE) as usize;
+    let size = binary_size(count).ok_or_else(|| {
+        Error::Parse(format!("STL file of {} triangles is too large", count))
+    })?;
+    if bytes.len() < size {
+        return Err(Error::Parse(format!(
+            "STL file of {} triangles needs {} bytes but has {}",
+            count,
+            size,
+            bytes.len()
+        )));
+    }
+
+    // Materialise Magics writes `COLOR=` and a default RGBA color into the
+    // header, faces without the 0x8000 bit have their own 5-bit RGB color
+    let header = &bytes[..HEADER_SIZE];
+    let default_color = header
+        .windows(10)
+        .find(|window| window.starts_with(b"COLOR="))
+        .map(|window| {
+            let channel = |i: usize| window[6 + i] as f32 / 255.0;
+            [channel(0), channel(1), channel(2)]
+        });
+
+    let mut file = StlFile::default();
+    let mut colors = Vec::new();
+    for triangle in 0..count {
+        let offset = HEADER_SIZE + 4 + triangle * TRIANGLE_SIZE;
+        let corners = [
+            read_vector(bytes, offset + 12),
+            read_vector(bytes, offset + 24),
+            read_vector(bytes, offset + 36),
+        ];
+        file.add_triangle(read_vector(bytes, offset), corners);
+
+        if let Some(default_color) = default_color {
+            let attribute = u16::from_le_bytes([bytes[offset + 48], bytes[offset + 49]]);
+            colors.push(if attribute & 0x8000 == 0 {
+                let channel = |shift: u16| ((attribute >> shift) & 0x1f) as f32 / 31.0;
+                [channel(0), channel(5), channel(10)]
+            } else {
+                default_color
+            });
+        }
+    }
+    if default_color.is_some() {
+        file.colors = Some(colors);
+    }
+    Ok(file)
+}
+
+fn parse_ascii(string: &str) -> Result<StlFile> {
+    let mut file = StlFile::default();
+    // the normal and corners of the facet being read
+    let mut facet: Option<([f32; 3], Vec<[f32; 3]>)> = None;
+
+    for (number, line) in string.lines().enumerate() {
+        let error = |reason: String| Error::Parse(format!("line {}: {}", number + 1, reason));
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let arguments: Vec<&str> = tokens.collect();
+        let vector = |arguments: &[&str]| -> Result<[f32; 3]> {
+            let numbers = arguments
+                .iter()
+                .map(|argument| argument.parse::<f32>())
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(|_| error(format!("invalid number in `{}`", line.trim())))?;
+            match numbers[..] {
+                [x, y, z] => Ok([x, y, z]),
+                _ => Err(error(format!(
+                    "expected three numbers in `{}`",
+                    line.trim()
+                ))),
+            }
+        };
+
+        match keyword {
+            "solid" => {
+                if file.name.is_empty() {
+                    file.name = arguments.join(" ");
+                }
+            }
+            "facet" => match arguments.split_first() {
+                Some((&"normal", normal)) => facet = Some((vector(normal)?, Vec::new())),
+                _ => return Err(error(String::from("expected `facet normal`"))),
+            },
+            "vertex" => match &mut facet {
+                Some((_, corners)) => corners.push(vector(&arguments)?),
+                None => return Err(error(String::from("vertex outside of a facet"))),
+            },
+            "endfacet" => match facet.take() {
+                Some((normal, corners)) => {
+                    if corners.len() < 3 {
+                        return Err(error(String::from("a facet needs three vertices")));
+                    }
+                    // the format only has triangles, larger facets are fanned
+                    for i in 1..corners.len() - 1 {
+                        file.add_triangle(normal, [corners[0], corners[i], corners[i + 1]]);
+                    }
+                }
+                None => return Err(error(String::from("`endfacet` without a facet"))),
+            },
+            "outer" | "endloop" | "endsolid" => {}
+            keyword => return Err(error(format!("unexpected `{}`", keyword))),
+        }
+    }
+    Ok(file)
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(v: [f32; 3]) -> Option<[f32; 3]> {
+    let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    if length > 0.0 && length.is_finite() {
+        Some([v[0] / length, v[1] / length, v[2] / length])
+    } else {
+        None
+    }
+}
+
+impl StlFile {
+    /// Many exporters write zero normals, those are taken from the winding.
+    fn add_triangle(&mut self, normal: [f32; 3], corners: [[f32; 3]; 3]) {
+        let normal = normalize(normal)
+            .or_else(|| {
+                normalize(cross(
+                    sub(corners[1], corners[0]),
+                    sub(corners[2], corners[0]),
+                ))
+            })
+            .unwrap_or([0.0, 0.0, 0.0]);
+        self.positions.extend_from_slice(&corners);
+        self.normals.push(normal);
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.normals.len()
+    }
+
+    /// Every corner is a vertex of its own with the normal of its face, which
+    /// keeps the hard edges of CAD models.
+    ///
+    /// With `weld`, corners at the same position, and of the same color, share
+    /// a vertex whose normal averages the faces around it, so that scanned or
+    /// sculpted surfaces look smooth. Triangles that collapse are dropped.
+    pub fn to_geometry(&self, weld: bool) -> Geometry {
+        if weld {
+            return self.welded_geometry();
+        }
+
+        let per_corner = |values: &[[f32; 3]]| -> Vec<f32> {
+            values
+                .iter()
+                .flat_map(|value| value.iter().cycle().take(9).cloned())
+                .collect()
+        };
+        let indices = (0..self.positions.len() as u32).collect();
+        let mut geometry = Geometry::new(Indices::from_u32(indices));
+        geometry.set_attribute(
+            geometry::POSITION,
+            Attribute::new(
+                self.positions.iter().flatten().cloned().collect::<Vec<_>>(),
+                3,
+            ),
+        );
+        geometry.set_attribute(
+            geometry::NORMAL,
+            Attribute::new(per_corner(&self.normals), 3),
+        );
+        if let Some(colors) = &self.colors {
+            geometry.set_attribute(geometry::COLOR, Attribute::new(per_corner(colors), 3));
+        }
+        geometry
+    }
+
+    fn welded_geometry(&self) -> Geometry {
+        let mut vertices: HashMap<[u32; 6], u32> = HashMap::new();
+        let mut positions = Vec::new();
+        let mut colors = Vec::new();
+        let mut indices = Vec::with_capacity(self.positions.len());
+
+        for (triangle, corners) in self.positions.chunks(3).enumerate() {
+            let color = self.colors.as_ref().map(|colors| colors[triangle]);
+            let mut triangle_indices = [0; 3];
+            for (corner, position) in corners.iter().enumerate() {
+                let [r, g, b] = color.unwrap_or([0.0; 3]);
+                // -0.0 and 0.0 are the same point
+                let bits = |v: f32| (v + 0.0).to_bits();
+                let key = [
+                    bits(position[0]),
+                    bits(position[1]),
+                    bits(position[2]),
+                    r.to_bits(),
+                    g.to_bits(),
+                    b.to_bits(),
+                ];
+                triangle_indices[corner] = *vertices.entry(key).or_insert_with(|| {
+                    positions.extend_from_slice(position);
+                    if let Some(color) = color {
+                        colors.extend_from_slice(&color);
+                    }
+                    (positions.len() / 3 - 1) as u32
+                });
+            }
+            let [a, b, c] = triangle_indices;
+            if a != b && b != c && c != a {
+                indices.extend_from_slice(&triangle_indices);
+            }
+        }
+
+        let mut geometry = Geometry::new(Indices::from_u32(indices));
+        geometry.set_attribute(geometry::POSITION, Attribute::new(positions, 3));
+        if self.colors.is_some() {
+            geometry.set_attribute(geometry::COLOR, Attribute::new(colors, 3));
+        }
+        geometry.compute_vertex_normals();
+        geometry
+    }
+}
+
+/// STL files, ASCII or binary.
+#[derive(Clone, Copy, Default)]
+pub struct StlLoader {
+    /// merge corners and smooth the normals, see `StlFile::to_geometry`
+    pub weld: bool,
+}
+
+impl Loader for StlLoader {
+    fn extensions(&self) -> &[&str] {
+        &["stl"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &[
+            "model/stl",
+            "model/x.stl-ascii",
+            "model/x.stl-binary",
+            "application/sla",
+        ]
+    }
+
+    /// Binary files have no signature, only the size given by their header.
+    fn sniff(&self, bytes: &[u8]) -> bool {
+        if has_binary_size(bytes) {
+            return true;
+        }
+        let head = String::from_utf8_lossy(&bytes[..bytes.len().min(1024)]);
+        let mut tokens = head.split_whitespace();
+        tokens.next() == Some("solid") && tokens.any(|token| token == "facet")
+    }
+
+    fn load(&self, bytes: &[u8], _url: &str, _resolver: Rc<dyn ResourceResolver>) -> LoadFuture {
+        let weld = self.weld;
+        let result = parse_stl(bytes).map(|file| {
+            let mesh = Mesh::new(file.to_geometry(weld), PhongMaterial::default().into());
+            Object3D::from(mesh).with_name(&file.name)
+        });
+        Box::new(future::result(result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASCII: &[u8] = include_bytes!("../../tests/fixtures/tetrahedron_ascii.stl");
+    const BINARY: &[u8] = include_bytes!("../../tests/fixtures/tetrahedron_binary.stl");
+    const COLORED: &[u8] = include_bytes!("../../tests/fixtures/colored_binary.stl");
+
+    fn assert_near(actual: [f32; 3], expected: [f32; 3]) {
+        for axis in 0..3 {
+            assert!(
+                (actual[axis] - expected[axis]).abs() < 1e-5,
+                "{:?} != {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn reads_ascii_facets() {
+        let file = parse_stl(ASCII).unwrap();
+        assert_eq!(file.name, "tetrahedron");
+        assert_eq!(file.triangle_count(), 4);
+        assert_eq!(file.positions.len(), 12);
+        assert_near(file.normals[0], [0.0, 0.0, -1.0]);
+        // the file's normal isn't unit length
+        assert_near(file.normals[1], [0.0, -1.0, 0.0]);
+        // and this one is zero
+        let slope = 1.0 / 3f32.sqrt();
+        assert_near(file.normals[3], [slope, slope, slope]);
+        assert!(file.colors.is_none());
+    }
+
+    #[test]
+    fn reads_binary_that_starts_with_solid() {
+        assert!(BINARY.starts_with(b"solid"));
+        let binary = parse_stl(BINARY).unwrap();
+        let ascii = parse_stl(ASCII).unwrap();
+        assert_eq!(binary.positions, ascii.positions);
+        for (binary, ascii) in binary.normals.iter().zip(&ascii.normals) {
+            assert_near(*binary, *ascii);
+        }
+        assert!(binary.colors.is_none());
+    }
+
+    #[test]
+    fn reads_face_colors() {
+        let file = parse_stl(COLORED).unwrap();
+        let colors = file.colors.unwrap();
+        // the 0x8000 bit selects the header's color
+        assert_near(colors[0], [1.0, 0.0, 0.0]);
+        assert_near(colors[1], [0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn rejects_truncated_binary() {
+        match parse_stl(&BINARY[..BINARY.len() - 10]) {
+            Err(Error::Parse(message)) => assert!(message.contains("4 triangles")),
+            other => panic!("expected a parse error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn rejects_huge_triangle_count() {
+        let mut bytes = vec![0; HEADER_SIZE + 4 + TRIANGLE_SIZE];
+        bytes[HEADER_SIZE..HEADER_SIZE + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(!StlLoader::default().sniff(&bytes));
+        match parse_stl(&bytes) {
+            Err(Error::Parse(_)) => {}
+            other => panic!("expected a parse error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn reports_ascii_line() {
+        let stl = "solid bad\n facet normal 0 0 1\n  outer loop\n   vertex 0 0 x\n";
+        match parse_stl(stl.as_bytes()) {
+            Err(Error::Parse(message)) => assert!(message.starts_with("line 4:")),
+            other => panic!("expected a parse error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn flat_geometry_has_face_normals() {
+        let geometry = parse_stl(ASCII).unwrap().to_geometry(false);
+        assert_eq!(geometry.vertex_count(), 12);
+        assert_eq!(geometry.indices.len(), 12);
+        let normals = geometry.attribute(geometry::NORMAL).unwrap();
+        for corner in 0..3 {
+            assert_near(
+                [
+                    normals.get_item(corner)[0],
+                    normals.get_item(corner)[1],
+                    normals.get_item(corner)[2],
+                ],
+                [0.0, 0.0, -1.0],
+            );
+        }
+    }
+
+    #[test]
+    fn welded_geometry_is_smooth() {
+        let geometry = parse_stl(BINARY).unwrap().to_geometry(true);
+        assert_eq!(geometry.vertex_count(), 4);
+        assert_eq!(geometry.indices.len(), 12);
+        let positions = geometry.attribute(geometry::POSITION).unwrap();
+        let normals = geometry.attribute(geometry::NORMAL).unwrap();
+        let center = [0.25, 0.25, 0.25];
+        for vertex in 0..4 {
+            let position = positions.get_item(vertex);
+            let normal = normals.get_item(vertex);
+            let outward = (0..3)
+                .map(|axis| (position[axis] - center[axis]) * normal[axis])
+                .sum::<f32>();
+            assert!(outward > 0.0, "vertex {} normal points inward", vertex);
+        }
+    }
+
+    #[test]
+    fn welding_keeps_colors_apart() {
+        let geometry = parse_stl(COLORED).unwrap().to_geometry(true);
+        // the two faces share an edge but not a color
+        assert_eq!(geometry.vertex_count(), 6);
+        assert!(geometry.attribute(geometry::COLOR).is_some());
+    }
+}
@@ -1,26 +1,343 @@
-use crate::utils::log_num;
+use crate::error::{Error, Result};
+use crate::texture::Texture;
+use std::rc::Rc;
 
+/// Blinn-Phong material, the classic `Kd`/`Ks`/`Ns` model of OBJ/MTL files.
 #[derive(Clone)]
-pub struct Material {
+pub struct PhongMaterial {
     pub color: [f32; 4],
+    /// reflected ambient light, `Ka` in MTL files. `None` reflects it like
+    /// the diffuse color, i.e. `color`, the vertex colors and `map`
+    pub ambient: Option<[f32; 3]>,
+    /// Blinn-Phong highlight color, `Ks` in MTL files
+    pub specular: [f32; 3],
+    /// Blinn-Phong exponent, `Ns` in MTL files
+    pub shininess: f32,
+    /// multiplied with `color`, `map_Kd` in MTL files
+    pub map: Option<Rc<Texture>>,
+    /// height field perturbing the normals, `map_Bump` in MTL files
+    pub bump_map: Option<Rc<Texture>>,
+    pub bump_scale: f32,
 }
-impl Default for Material {
+impl Default for PhongMaterial {
     fn default() -> Self {
         Self {
             color: [0.5, 0.5, 0.5, 1.0],
+            ambient: None,
+            specular: [0.067, 0.067, 0.067],
+            shininess: 30.0,
+            map: None,
+            bump_map: None,
+            bump_scale: 1.0,
         }
     }
 }
 
-impl<'a> From<gltf::Material<'a>> for Material {
-    fn from(mat: gltf::Material<'a>) -> Self {
-        let [r, g, b, a] = mat.pbr_metallic_roughness().base_color_factor();
-        log_num(r as f64);
-        log_num(g as f64);
-        log_num(b as f64);
+/// Unlit material, the color is drawn as is. Used for lines and points, which
+/// have no normals to light.
+#[derive(Clone)]
+pub struct BasicMaterial {
+    pub color: [f32; 4],
+    /// multiplied with `color`
+    pub map: Option<Rc<Texture>>,
+    /// diameter of points in pixels
+    pub size: f32,
+}
+impl Default for BasicMaterial {
+    fn default() -> Self {
+        Self {
+            color: [1.0, 1.0, 1.0, 1.0],
+            map: None,
+            size: 1.0,
+        }
+    }
+}
 
+/// glTF metallic-roughness material.
+#[derive(Clone)]
+pub struct StandardMaterial {
+    /// `baseColorFactor`
+    pub color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    pub emissive: [f32; 3],
+    /// `baseColorTexture` in sRGB, multiplied with `color`
+    pub map: Option<Rc<Texture>>,
+    /// roughness in the green channel, metalness in the blue one
+    pub metallic_roughness_map: Option<Rc<Texture>>,
+    /// in sRGB, multiplied with `emissive`
+    pub emissive_map: Option<Rc<Texture>>,
+    /// tangent-space normals, tangents follow the texture coordinates
+    pub normal_map: Option<Rc<Texture>>,
+    pub normal_scale: f32,
+    /// ambient occlusion in the red channel, only darkens ambient light
+    pub occlusion_map: Option<Rc<Texture>>,
+    pub occlusion_strength: f32,
+    /// fragments with a lower alpha are discarded, `None` unless alphaMode is MASK
+    pub alpha_cutoff: Option<f32>,
+    /// alphaMode BLEND
+    pub transparent: bool,
+    pub double_sided: bool,
+}
+impl Default for StandardMaterial {
+    fn default() -> Self {
+        // defaults from the glTF 2.0 specification
         Self {
-            color: [r, g, b, a],
+            color: [1.0, 1.0, 1.0, 1.0],
+            metallic: 1.0,
+            roughness: 1.0,
+            emissive: [0.0, 0.0, 0.0],
+            map: None,
+            metallic_roughness_map: None,
+            emissive_map: None,
+            normal_map: None,
+            normal_scale: 1.0,
+            occlusion_map: None,
+            occlusion_strength: 1.0,
+            alpha_cutoff: None,
+            transparent: false,
+            double_sided: false,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum Material {
+    Basic(BasicMaterial),
+    Phong(PhongMaterial),
+    Standard(StandardMaterial),
+}
+
+/// Which shading model a material uses, selects the fragment shader.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MaterialType {
+    Basic,
+    Phong,
+    Standard,
+}
+
+/// Which texture slots a material fills, each one adds a sampler to the shader.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct MaterialMaps {
+    pub map: bool,
+    pub metallic_roughness_map: bool,
+    pub emissive_map: bool,
+    pub occlusion_map: bool,
+    pub bump_map: bool,
+    pub normal_map: bool,
+}
+
+impl MaterialMaps {
+    pub fn any(&self) -> bool {
+        self.map
+            || self.metallic_roughness_map
+            || self.emissive_map
+            || self.occlusion_map
+            || self.bump_map
+            || self.normal_map
+    }
+}
+
+impl Material {
+    pub fn material_type(&self) -> MaterialType {
+        match self {
+            Material::Basic(_) => MaterialType::Basic,
+            Material::Phong(_) => MaterialType::Phong,
+            Material::Standard(_) => MaterialType::Standard,
+        }
+    }
+
+    pub fn maps(&self) -> MaterialMaps {
+        match self {
+            Material::Basic(material) => MaterialMaps {
+                map: material.map.is_some(),
+                ..Default::default()
+            },
+            Material::Phong(material) => MaterialMaps {
+                map: material.map.is_some(),
+                bump_map: material.bump_map.is_some(),
+                ..Default::default()
+            },
+            Material::Standard(material) => MaterialMaps {
+                map: material.map.is_some(),
+                metallic_roughness_map: material.metallic_roughness_map.is_some(),
+                emissive_map: material.emissive_map.is_some(),
+                occlusion_map: material.occlusion_map.is_some(),
+                bump_map: false,
+                normal_map: material.normal_map.is_some(),
+            },
+        }
+    }
+
+    /// Every texture the material samples.
+    pub fn textures(&self) -> Vec<&Rc<Texture>> {
+        match self {
+            Material::Basic(material) => material.map.iter().collect(),
+            Material::Phong(material) => {
+                material.map.iter().chain(material.bump_map.iter()).collect()
+            }
+            Material::Standard(material) => material
+                .map
+                .iter()
+                .chain(material.metallic_roughness_map.iter())
+                .chain(material.emissive_map.iter())
+                .chain(material.normal_map.iter())
+                .chain(material.occlusion_map.iter())
+                .collect(),
+        }
+    }
+
+    pub fn color(&self) -> [f32; 4] {
+        match self {
+            Material::Basic(material) => material.color,
+            Material::Phong(material) => material.color,
+            Material::Standard(material) => material.color,
+        }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material::Phong(PhongMaterial::default())
+    }
+}
+
+impl From<BasicMaterial> for Material {
+    fn from(material: BasicMaterial) -> Self {
+        Material::Basic(material)
+    }
+}
+
+impl From<PhongMaterial> for Material {
+    fn from(material: PhongMaterial) -> Self {
+        Material::Phong(material)
+    }
+}
+
+impl From<StandardMaterial> for Material {
+    fn from(material: StandardMaterial) -> Self {
+        Material::Standard(material)
+    }
+}
+
+impl Material {
+    /// Builds the glTF material, `textures` are the document's textures in order.
+    /// Meshes only load TEXCOORD_0, so textures reading another set are refused.
+    pub fn from_gltf(mat: &gltf::Material, textures: &[Rc<Texture>]) -> Result<Material> {
+        let pbr = mat.pbr_metallic_roughness();
+        let texture = |slot: &str, texture: gltf::Texture, tex_coord: u32| {
+            if tex_coord != 0 {
+                return Err(Error::Unsupported(format!(
+                    "{} of glTF material {} reads TEXCOORD_{}",
+                    slot,
+                    mat.name().unwrap_or("(unnamed)"),
+                    tex_coord
+                )));
+            }
+            Ok(textures[texture.index()].clone())
+        };
+        let info = |slot: &str, info: Option<gltf::texture::Info>| {
+            info.map(|info| texture(slot, info.texture(), info.tex_coord()))
+                .transpose()
+        };
+        let normal = mat.normal_texture();
+        let occlusion = mat.occlusion_texture();
+
+        Ok(Material::Standard(StandardMaterial {
+            color: pbr.base_color_factor(),
+            metallic: pbr.metallic_factor(),
+            roughness: pbr.roughness_factor(),
+            emissive: mat.emissive_factor(),
+            map: info("baseColorTexture", pbr.base_color_texture())?,
+            metallic_roughness_map: info(
+                "metallicRoughnessTexture",
+                pbr.metallic_roughness_texture(),
+            )?,
+            emissive_map: info("emissiveTexture", mat.emissive_texture())?,
+            normal_map: normal
+                .as_ref()
+                .map(|normal| texture("normalTexture", normal.texture(), normal.tex_coord()))
+                .transpose()?,
+            normal_scale: normal.as_ref().map(|normal| normal.scale()).unwrap_or(1.0),
+            occlusion_map: occlusion
+                .as_ref()
+                .map(|occlusion| {
+                    texture("occlusionTexture", occlusion.texture(), occlusion.tex_coord())
+                })
+                .transpose()?,
+            occlusion_strength: occlusion
+                .as_ref()
+                .map(|occlusion| occlusion.strength())
+                .unwrap_or(1.0),
+            alpha_cutoff: match mat.alpha_mode() {
+                gltf::material::AlphaMode::Mask => Some(mat.alpha_cutoff()),
+                _ => None,
+            },
+            transparent: mat.alpha_mode() == gltf::material::AlphaMode::Blend,
+            double_sided: mat.double_sided(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::{TextureFormat, TextureSource};
+
+    fn textures() -> Vec<Rc<Texture>> {
+        let texture = || {
+            Rc::new(Texture::new(TextureSource::Data {
+                width: 1,
+                height: 1,
+                format: TextureFormat::Rgba,
+                pixels: vec![255; 4],
+            }))
+        };
+        vec![texture(), texture()]
+    }
+
+    /// Builds the material `json` of a document with two textures.
+    fn material(json: &str, textures: &[Rc<Texture>]) -> Result<Material> {
+        let document = format!(
+            r#"{{"asset": {{"version": "2.0"}},
+                "images": [{{"uri": "a.png"}}],
+                "textures": [{{"source": 0}}, {{"source": 0}}],
+                "materials": [{}]}}"#,
+            json
+        );
+        let gltf = gltf::Gltf::from_slice(document.as_bytes()).unwrap();
+        let mat = gltf.materials().next().unwrap();
+        Material::from_gltf(&mat, textures)
+    }
+
+    #[test]
+    fn reads_normal_texture_and_scale() {
+        let textures = textures();
+        let json = r#"{"normalTexture": {"index": 1, "scale": 0.5}}"#;
+        match material(json, &textures).unwrap() {
+            Material::Standard(material) => {
+                assert!(Rc::ptr_eq(material.normal_map.as_ref().unwrap(), &textures[1]));
+                assert_eq!(material.normal_scale, 0.5);
+                assert!(material.map.is_none());
+            }
+            _ => panic!("expected a standard material"),
+        }
+    }
+
+    #[test]
+    fn refuses_second_texture_coordinate_set() {
+        let textures = textures();
+        for json in &[
+            r#"{"pbrMetallicRoughness": {"baseColorTexture": {"index": 0, "texCoord": 1}}}"#,
+            r#"{"occlusionTexture": {"index": 0, "texCoord": 1}}"#,
+            r#"{"normalTexture": {"index": 0, "texCoord": 1}}"#,
+        ] {
+            match material(json, &textures) {
+                Err(Error::Unsupported(message)) => assert!(message.contains("TEXCOORD_1")),
+                Err(err) => panic!("expected unsupported, got {}", err),
+                Ok(_) => panic!("expected unsupported for {}", json),
+            }
         }
+        assert!(material(r#"{"emissiveTexture": {"index": 1}}"#, &textures).is_ok());
     }
 }
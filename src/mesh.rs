@@ -1,96 +1,225 @@
-use crate::geometry::Geometry;
-use crate::material::Material;
-use std::sync::Arc;
-extern crate gmath;
-use gmath::{mat4, quat, vec2, vec3};
+use crate::geometry::{self, Attribute, Geometry, Indices};
+use gltf::mesh::util::{ReadColors, ReadJoints, ReadTexCoords, ReadWeights};
+use crate::error::{Error, Result};
+use crate::material::{BasicMaterial, Material};
+use crate::webgl_renderer::GeometryBuffers;
+use std::rc::Rc;
+use web_sys::WebGlRenderingContext;
 
+/// How the indices of a mesh are assembled into primitives.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DrawMode {
+    Triangles,
+    /// every pair of indices is a segment
+    Lines,
+    Points,
+}
+
+impl DrawMode {
+    pub fn to_gl(self) -> u32 {
+        match self {
+            DrawMode::Triangles => WebGlRenderingContext::TRIANGLES,
+            DrawMode::Lines => WebGlRenderingContext::LINES,
+            DrawMode::Points => WebGlRenderingContext::POINTS,
+        }
+    }
+
+    /// Number of indices in one primitive.
+    pub fn primitive_size(self) -> usize {
+        match self {
+            DrawMode::Triangles => 3,
+            DrawMode::Lines => 2,
+            DrawMode::Points => 1,
+        }
+    }
+}
+
+/// A geometry drawn with a material. Its transform comes from the `Object3D`
+/// node holding it.
 #[allow(non_snake_case)]
 pub struct Mesh {
     pub geometry: Geometry,
     pub material: Material,
-    pub position: [f32; 3],
-    pub scale: [f32; 3],
-    pub rotation: [f32; 4],
-    pub matrix: [f32; 16],
-    pub __webGLVertexBuffer: Option<web_sys::WebGlBuffer>,
-    pub __webGLFaceBuffer: Option<web_sys::WebGlBuffer>,
-    pub __webGLNormalBuffer: Option<web_sys::WebGlBuffer>,
-    pub __webGLColorBuffer: Option<web_sys::WebGlBuffer>,
+    pub mode: DrawMode,
+    /// weights of the geometry's morph targets, e.g. set by animation tracks
+    pub morph_target_influences: Vec<f32>,
+    pub __webGLBuffers: Vec<GeometryBuffers>,
+}
+
+/// Copies the geometry and material; GPU buffers aren't shared, the copy uploads
+/// its own the first time it is drawn.
+impl Clone for Mesh {
+    fn clone(&self) -> Self {
+        Mesh {
+            mode: self.mode,
+            morph_target_influences: self.morph_target_influences.clone(),
+            ..Mesh::new(self.geometry.clone(), self.material.clone())
+        }
+    }
 }
 
 impl Mesh {
+    pub fn new(geometry: Geometry, material: Material) -> Mesh {
+        Mesh {
+            geometry,
+            material,
+            mode: DrawMode::Triangles,
+            morph_target_influences: Vec::new(),
+            __webGLBuffers: Vec::new(),
+        }
+    }
+
     pub fn from_gltf(
         buffers: &[gltf::buffer::Data],
         primitive: &gltf::Primitive,
-        materials: &[Arc<Material>],
-        transform: [f32; 16],
-    ) -> Self {
-        // We're only dealing with triangle meshes
-        assert_eq!(
-            gltf::mesh::Mode::Triangles,
-            primitive.mode(),
-            "Not handling non-triangle glTF primitives"
-        );
+        materials: &[Rc<Material>],
+    ) -> Result<Self> {
+        let mode = match primitive.mode() {
+            gltf::mesh::Mode::Triangles => DrawMode::Triangles,
+            gltf::mesh::Mode::Lines => DrawMode::Lines,
+            gltf::mesh::Mode::Points => DrawMode::Points,
+            mode => {
+                return Err(Error::Unsupported(format!(
+                    "glTF primitive mode {:?}",
+                    mode
+                )))
+            }
+        };
 
         let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
-        let indices = reader
-            .read_indices()
-            .map(|read_indices| read_indices.into_u32().collect::<Vec<_>>())
-            .expect("Failed to read glTF indices");
-        let positions: Vec<_> = reader
-            .read_positions()
-            .expect("Failed to read glTF positions")
-            .map(|data| data)
-            .collect();
-        let normals: Vec<_> = reader
-            .read_normals()
-            .expect("Failed to read glTF normals")
-            .map(|data| data)
-            .collect();
         let material = primitive.material().index().map(|id| materials[id].clone()).unwrap_or_default();
-        // Not handling optional normals yet
-        assert_eq!(
-            positions.len(),
-            normals.len(),
-            "Position vector and normals vector have different lengths"
-        );
-
-        fn to1dVec<T: Copy>(arr2d: Vec<[T; 3]>) -> Vec<T> {
-            let mut vec = Vec::new();
-            for subVec in arr2d {
-                vec.push(subVec[0]);
-                vec.push(subVec[1]);
-                vec.push(subVec[2]);
-            }
-            vec
-        }
 
-        let mut newIndeces = Vec::new();
-        for value in indices {
-            newIndeces.push(value as u16);
+        fn flatten<T: Clone, A: AsRef<[T]>>(items: impl Iterator<Item = A>) -> Vec<T> {
+            items.flat_map(|item| item.as_ref().to_vec()).collect()
+        }
+        // set 0 uses the plain name, further sets get their index appended
+        fn set_name(name: &str, set: u32) -> String {
+            if set == 0 {
+                name.to_string()
+            } else {
+                format!("{}{}", name, set)
+            }
         }
 
-        let geometry = Geometry {
-            vertices: to1dVec(positions),
-            indices: newIndeces,
-            normals: to1dVec(normals),
-        };
-        let material1 = Material {
-            color: [1.0, 0.0, 1.0, 1.0],
+        let positions: Vec<f32> = reader
+            .read_positions()
+            .map(flatten)
+            .ok_or_else(|| Error::Parse(String::from("glTF primitive without positions")))?;
+        // non-indexed primitives draw their vertices in order
+        let indices = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32 / 3).collect(),
         };
-        let mesh = Mesh {
-            geometry: geometry,
-            material: (*material).clone(),
-            position: vec3::new_zero(),
-            scale: vec3::new_one(),
-            rotation: quat::new_identity(),
-            matrix: transform,
-            __webGLVertexBuffer: None,
-            __webGLFaceBuffer: None,
-            __webGLNormalBuffer: None,
-            __webGLColorBuffer: None,
+        let mut geometry = Geometry::new(Indices::from_u32(indices));
+        geometry.set_attribute(geometry::POSITION, Attribute::new(positions, 3));
+        // missing normals are computed once the indices are known to be valid
+        let compute_normals = match reader.read_normals() {
+            Some(normals) => {
+                geometry.set_attribute(geometry::NORMAL, Attribute::new(flatten(normals), 3));
+                false
+            }
+            None => mode == DrawMode::Triangles,
         };
+        if let Some(tangents) = reader.read_tangents() {
+            geometry.set_attribute(geometry::TANGENT, Attribute::new(flatten(tangents), 4));
+        }
+
+        let mut set = 0;
+        while let Some(uvs) = reader.read_tex_coords(set) {
+            let attribute = match uvs {
+                ReadTexCoords::U8(uvs) => Attribute::normalized(flatten(uvs), 2),
+                ReadTexCoords::U16(uvs) => Attribute::normalized(flatten(uvs), 2),
+                ReadTexCoords::F32(uvs) => Attribute::new(flatten(uvs), 2),
+            };
+            geometry.set_attribute(&set_name(geometry::UV, set), attribute);
+            set += 1;
+        }
+
+        let mut set = 0;
+        while let Some(colors) = reader.read_colors(set) {
+            let attribute = match colors {
+                ReadColors::RgbU8(colors) => Attribute::normalized(flatten(colors), 3),
+                ReadColors::RgbU16(colors) => Attribute::normalized(flatten(colors), 3),
+                ReadColors::RgbF32(colors) => Attribute::new(flatten(colors), 3),
+                ReadColors::RgbaU8(colors) => Attribute::normalized(flatten(colors), 4),
+                ReadColors::RgbaU16(colors) => Attribute::normalized(flatten(colors), 4),
+                ReadColors::RgbaF32(colors) => Attribute::new(flatten(colors), 4),
+            };
+            geometry.set_attribute(&set_name(geometry::COLOR, set), attribute);
+            set += 1;
+        }
+
+        let mut set = 0;
+        while let Some(joints) = reader.read_joints(set) {
+            let attribute = match joints {
+                ReadJoints::U8(joints) => Attribute::new(flatten(joints), 4),
+                ReadJoints::U16(joints) => Attribute::new(flatten(joints), 4),
+            };
+            geometry.set_attribute(&set_name(geometry::SKIN_INDEX, set), attribute);
+            set += 1;
+        }
 
-        mesh
+        let mut set = 0;
+        while let Some(weights) = reader.read_weights(set) {
+            let attribute = match weights {
+                ReadWeights::U8(weights) => Attribute::normalized(flatten(weights), 4),
+                ReadWeights::U16(weights) => Attribute::normalized(flatten(weights), 4),
+                ReadWeights::F32(weights) => Attribute::new(flatten(weights), 4),
+            };
+            geometry.set_attribute(&set_name(geometry::SKIN_WEIGHT, set), attribute);
+            set += 1;
+        }
+
+        // targets are offsets from the base attributes; a target without
+        // normals moves only the positions
+        let vertex_count = geometry.vertex_count();
+        let mut position_targets = Vec::new();
+        let mut normal_targets = Vec::new();
+        let mut has_normals = false;
+        for (positions, normals, _) in reader.read_morph_targets() {
+            let positions = match positions {
+                Some(positions) => flatten(positions),
+                None => vec![0.0; vertex_count * 3],
+            };
+            let normals = match normals {
+                Some(normals) => {
+                    has_normals = true;
+                    flatten(normals)
+                }
+                None => vec![0.0; vertex_count * 3],
+            };
+            position_targets.push(Attribute::new(positions, 3));
+            normal_targets.push(Attribute::new(normals, 3));
+        }
+        let target_count = position_targets.len();
+        if target_count > 0 {
+            geometry
+                .morph_attributes
+                .insert(geometry::POSITION.to_string(), position_targets);
+            if has_normals {
+                geometry
+                    .morph_attributes
+                    .insert(geometry::NORMAL.to_string(), normal_targets);
+            }
+        }
+
+        geometry.validate()?;
+        if compute_normals {
+            geometry.compute_vertex_normals();
+        }
+
+        // lines and points are unlit
+        let material = match mode {
+            DrawMode::Triangles => (*material).clone(),
+            _ => BasicMaterial {
+                color: material.color(),
+                ..Default::default()
+            }
+            .into(),
+        };
+        let mut mesh = Mesh::new(geometry, material);
+        mesh.mode = mode;
+        mesh.morph_target_influences = vec![0.0; target_count];
+        Ok(mesh)
     }
 }
@@ -0,0 +1,254 @@
+use crate::animation::animation_clip::AnimationClip;
+use crate::camera::Camera;
+use crate::instanced_mesh::InstancedMesh;
+use crate::light::Light;
+use crate::mesh::Mesh;
+use crate::skeleton::Skeleton;
+use crate::skinned_mesh::SkinnedMesh;
+extern crate gmath;
+use gmath::{mat4, quat, vec3};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_OBJECT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// What a node of the scene graph holds besides its transform and children.
+pub enum ObjectKind {
+    /// only groups its children
+    Group,
+    Mesh(Mesh),
+    InstancedMesh(InstancedMesh),
+    SkinnedMesh(SkinnedMesh),
+    /// light fields are in the node's local space
+    Light(Light),
+    /// `camera.matrix` follows the node, it is the inverse of its world matrix
+    Camera(Camera),
+}
+
+/// A node of the scene graph. Children are placed relative to their parent, so
+/// moving a node moves its whole subtree.
+pub struct Object3D {
+    id: usize,
+    pub name: String,
+    pub kind: ObjectKind,
+    pub position: [f32; 3],
+    pub scale: [f32; 3],
+    pub rotation: [f32; 4],
+    /// local transform, composed from position/scale/rotation
+    pub matrix: [f32; 16],
+    /// transform from local to world space, parent world matrix times `matrix`
+    pub matrix_world: [f32; 16],
+    /// set to false to drive `matrix` directly instead of through position/scale/rotation
+    pub matrix_auto_update: bool,
+    /// hides the node and its subtree
+    pub visible: bool,
+    pub children: Vec<Object3D>,
+    /// clips animating this subtree, e.g. imported along with a model
+    pub animations: Vec<Rc<AnimationClip>>,
+}
+
+impl Object3D {
+    pub fn new(kind: ObjectKind) -> Object3D {
+        Object3D {
+            id: NEXT_OBJECT_ID.fetch_add(1, Ordering::Relaxed),
+            name: String::new(),
+            kind,
+            position: vec3::new_zero(),
+            scale: vec3::new_one(),
+            rotation: quat::new_identity(),
+            matrix: mat4::new_identity(),
+            matrix_world: mat4::new_identity(),
+            matrix_auto_update: true,
+            visible: true,
+            children: Vec::new(),
+            animations: Vec::new(),
+        }
+    }
+
+    pub fn group() -> Object3D {
+        Object3D::new(ObjectKind::Group)
+    }
+
+    /// Unique among all nodes, e.g. to refer to the bones of a skeleton.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn with_name(mut self, name: &str) -> Object3D {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn add<O: Into<Object3D>>(&mut self, child: O) {
+        self.children.push(child.into());
+    }
+
+    /// Detaches the first direct child called `name`.
+    pub fn remove(&mut self, name: &str) -> Option<Object3D> {
+        let index = self.children.iter().position(|child| child.name == name)?;
+        Some(self.children.remove(index))
+    }
+
+    pub fn mesh(&self) -> Option<&Mesh> {
+        match &self.kind {
+            ObjectKind::Mesh(mesh) => Some(mesh),
+            ObjectKind::SkinnedMesh(skinned) => Some(&skinned.mesh),
+            _ => None,
+        }
+    }
+
+    pub fn mesh_mut(&mut self) -> Option<&mut Mesh> {
+        match &mut self.kind {
+            ObjectKind::Mesh(mesh) => Some(mesh),
+            ObjectKind::SkinnedMesh(skinned) => Some(&mut skinned.mesh),
+            _ => None,
+        }
+    }
+
+    pub fn camera(&self) -> Option<&Camera> {
+        match &self.kind {
+            ObjectKind::Camera(camera) => Some(camera),
+            _ => None,
+        }
+    }
+
+    pub fn update_matrix(&mut self) {
+        mat4::compose(&mut self.matrix, &self.position, &self.scale, &self.rotation);
+    }
+
+    /// Recomputes the world matrices of this node and its subtree, top-down.
+    pub fn update_matrix_world(&mut self, parent_matrix_world: &[f32; 16]) {
+        if self.matrix_auto_update {
+            self.update_matrix();
+        }
+        mat4::mul(&mut self.matrix_world, parent_matrix_world, &self.matrix);
+
+        if let ObjectKind::Camera(camera) = &mut self.kind {
+            mat4::inv(&mut camera.matrix, &self.matrix_world);
+        }
+
+        let matrix_world = self.matrix_world;
+        for child in self.children.iter_mut() {
+            child.update_matrix_world(&matrix_world);
+        }
+    }
+
+    /// Poses the skeletons of the subtree from the current world matrices of their
+    /// bones, run after `update_matrix_world`. Bones may live anywhere in the subtree.
+    pub fn update_skeletons(&mut self) {
+        let mut matrices_world = HashMap::new();
+        self.traverse(&mut |object| {
+            matrices_world.insert(object.id, object.matrix_world);
+        });
+        self.traverse_mut(&mut |object| {
+            if let ObjectKind::SkinnedMesh(skinned) = &mut object.kind {
+                skinned
+                    .skeleton
+                    .update(&matrices_world, &object.matrix_world);
+            }
+        });
+    }
+
+    /// Visits this node and every descendant, parents before children.
+    pub fn traverse<F: FnMut(&Object3D)>(&self, f: &mut F) {
+        f(self);
+        for child in self.children.iter() {
+            child.traverse(f);
+        }
+    }
+
+    pub fn traverse_mut<F: FnMut(&mut Object3D)>(&mut self, f: &mut F) {
+        f(self);
+        for child in self.children.iter_mut() {
+            child.traverse_mut(f);
+        }
+    }
+
+    pub fn get_object_by_name(&self, name: &str) -> Option<&Object3D> {
+        if self.name == name {
+            return Some(self);
+        }
+        self.children
+            .iter()
+            .filter_map(|child| child.get_object_by_name(name))
+            .next()
+    }
+
+    pub fn get_object_by_name_mut(&mut self, name: &str) -> Option<&mut Object3D> {
+        if self.name == name {
+            return Some(self);
+        }
+        self.children
+            .iter_mut()
+            .filter_map(|child| child.get_object_by_name_mut(name))
+            .next()
+    }
+
+    /// Gathers the visible drawables of the subtree with their world matrices.
+    pub fn collect_renderables<'a>(
+        &'a mut self,
+        meshes: &mut Vec<(&'a [f32; 16], &'a mut Mesh, Option<&'a mut Skeleton>)>,
+        instanced_meshes: &mut Vec<(&'a [f32; 16], &'a mut InstancedMesh)>,
+    ) {
+        if !self.visible {
+            return;
+        }
+        match &mut self.kind {
+            ObjectKind::Mesh(mesh) => meshes.push((&self.matrix_world, mesh, None)),
+            ObjectKind::SkinnedMesh(skinned) => meshes.push((
+                &self.matrix_world,
+                &mut skinned.mesh,
+                Some(&mut skinned.skeleton),
+            )),
+            ObjectKind::InstancedMesh(mesh) => instanced_meshes.push((&self.matrix_world, mesh)),
+            _ => {}
+        }
+        for child in self.children.iter_mut() {
+            child.collect_renderables(meshes, instanced_meshes);
+        }
+    }
+
+    /// Visible lights of the subtree, moved into world space.
+    pub fn collect_lights(&self, lights: &mut Vec<Light>) {
+        if !self.visible {
+            return;
+        }
+        if let ObjectKind::Light(light) = &self.kind {
+            lights.push(light.transformed(&self.matrix_world));
+        }
+        for child in self.children.iter() {
+            child.collect_lights(lights);
+        }
+    }
+}
+
+impl From<Mesh> for Object3D {
+    fn from(mesh: Mesh) -> Self {
+        Object3D::new(ObjectKind::Mesh(mesh))
+    }
+}
+
+impl From<InstancedMesh> for Object3D {
+    fn from(mesh: InstancedMesh) -> Self {
+        Object3D::new(ObjectKind::InstancedMesh(mesh))
+    }
+}
+
+impl From<SkinnedMesh> for Object3D {
+    fn from(mesh: SkinnedMesh) -> Self {
+        Object3D::new(ObjectKind::SkinnedMesh(mesh))
+    }
+}
+
+impl From<Light> for Object3D {
+    fn from(light: Light) -> Self {
+        Object3D::new(ObjectKind::Light(light))
+    }
+}
+
+impl From<Camera> for Object3D {
+    fn from(camera: Camera) -> Self {
+        Object3D::new(ObjectKind::Camera(camera))
+    }
+}
@@ -0,0 +1,84 @@
+extern crate wasm_bindgen;
+extern crate web_sys;
+use std::collections::HashMap;
+use web_sys::{WebGlProgram, WebGlRenderingContext, WebGlUniformLocation};
+
+use crate::error::Result;
+use crate::utils::{compile_shader, link_program};
+
+/// A linked shader program together with the locations of every active
+/// attribute and uniform, looked up once at link time.
+pub struct ProgramInfo {
+    pub program: WebGlProgram,
+    pub attributes: HashMap<String, u32>,
+    pub uniforms: HashMap<String, WebGlUniformLocation>,
+}
+
+impl ProgramInfo {
+    pub fn new(
+        gl: &WebGlRenderingContext,
+        vert_code: &str,
+        frag_code: &str,
+    ) -> Result<ProgramInfo> {
+        let vert_shader = compile_shader(gl, WebGlRenderingContext::VERTEX_SHADER, vert_code)?;
+        let frag_shader =
+            match compile_shader(gl, WebGlRenderingContext::FRAGMENT_SHADER, frag_code) {
+                Ok(shader) => shader,
+                Err(err) => {
+                    gl.delete_shader(Some(&vert_shader));
+                    return Err(err);
+                }
+            };
+        let program = link_program(gl, &vert_shader, &frag_shader)?;
+
+        Ok(ProgramInfo::from_program(gl, program))
+    }
+
+    pub fn from_program(gl: &WebGlRenderingContext, program: WebGlProgram) -> ProgramInfo {
+        let mut attributes = HashMap::new();
+        let attribute_count = gl
+            .get_program_parameter(&program, WebGlRenderingContext::ACTIVE_ATTRIBUTES)
+            .as_f64()
+            .unwrap_or(0.0) as u32;
+        for index in 0..attribute_count {
+            if let Some(info) = gl.get_active_attrib(&program, index) {
+                let location = gl.get_attrib_location(&program, &info.name());
+                if location >= 0 {
+                    attributes.insert(info.name(), location as u32);
+                }
+            }
+        }
+
+        let mut uniforms = HashMap::new();
+        let uniform_count = gl
+            .get_program_parameter(&program, WebGlRenderingContext::ACTIVE_UNIFORMS)
+            .as_f64()
+            .unwrap_or(0.0) as u32;
+        for index in 0..uniform_count {
+            if let Some(info) = gl.get_active_uniform(&program, index) {
+                // arrays are reported as "name[0]", register them under the bare name too
+                let name = info.name();
+                if let Some(location) = gl.get_uniform_location(&program, &name) {
+                    if name.ends_with("[0]") {
+                        uniforms.insert(name[..name.len() - 3].to_string(), location.clone());
+                    }
+                    uniforms.insert(name, location);
+                }
+            }
+        }
+
+        ProgramInfo {
+            program,
+            attributes,
+            uniforms,
+        }
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<u32> {
+        self.attributes.get(name).cloned()
+    }
+
+    pub fn uniform(&self, name: &str) -> Option<&WebGlUniformLocation> {
+        self.uniforms.get(name)
+    }
+}
@@ -1,12 +1,64 @@
-
+use crate::instanced_mesh::InstancedMesh;
+use crate::light::Light;
 use crate::mesh::Mesh;
+use crate::object3d::Object3D;
+extern crate gmath;
+use gmath::mat4;
 
+/// Root of the scene graph.
 pub struct Scene {
-    pub objects: Vec<Mesh>,
+    pub root: Object3D,
+}
+
+impl Default for Scene {
+    fn default() -> Scene {
+        Scene::new()
+    }
 }
 
 impl Scene {
+    pub fn new() -> Scene {
+        Scene {
+            root: Object3D::group(),
+        }
+    }
+
+    pub fn add<O: Into<Object3D>>(&mut self, object: O) {
+        self.root.add(object);
+    }
     pub fn add_object(&mut self, object: Mesh) {
-        self.objects.push(object);
+        self.add(object);
+    }
+    pub fn add_instanced_object(&mut self, object: InstancedMesh) {
+        self.add(object);
+    }
+    pub fn add_light<L: Into<Light>>(&mut self, light: L) {
+        self.add(light.into());
+    }
+
+    /// Detaches the first top-level object called `name`.
+    pub fn remove(&mut self, name: &str) -> Option<Object3D> {
+        self.root.remove(name)
+    }
+
+    pub fn get_object_by_name(&self, name: &str) -> Option<&Object3D> {
+        self.root.get_object_by_name(name)
+    }
+
+    pub fn get_object_by_name_mut(&mut self, name: &str) -> Option<&mut Object3D> {
+        self.root.get_object_by_name_mut(name)
     }
-}
\ No newline at end of file
+
+    /// Updates the world matrices, then the skeletons posed by them.
+    pub fn update_matrix_world(&mut self) {
+        self.root.update_matrix_world(&mat4::new_identity());
+        self.root.update_skeletons();
+    }
+
+    /// Every visible light, in world space.
+    pub fn lights(&self) -> Vec<Light> {
+        let mut lights = Vec::new();
+        self.root.collect_lights(&mut lights);
+        lights
+    }
+}
@@ -0,0 +1,610 @@
+use crate::light::LightCounts;
+use crate::material::{MaterialMaps, MaterialType};
+
+/// Everything that changes the generated shader source. The renderer links
+/// one program per distinct key.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProgramKey {
+    pub instanced: bool,
+    pub lights: LightCounts,
+    pub material: MaterialType,
+    pub maps: MaterialMaps,
+    pub skinning: Skinning,
+    /// morph targets blended on the GPU, each takes one attribute slot (two with normals)
+    pub morph_targets: usize,
+    pub morph_normals: bool,
+}
+
+/// Where a skinned mesh's vertex shader reads the bone matrices from.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Skinning {
+    None,
+    /// a `mat4` uniform array of this many bones
+    Uniforms(usize),
+    /// a float texture, when the bones don't fit in the vertex uniforms
+    Texture,
+}
+
+/// Texture unit each sampler is bound to.
+pub const MAP_UNIT: u32 = 0;
+pub const METALLIC_ROUGHNESS_MAP_UNIT: u32 = 1;
+pub const EMISSIVE_MAP_UNIT: u32 = 2;
+pub const AO_MAP_UNIT: u32 = 3;
+pub const BONE_TEXTURE_UNIT: u32 = 4;
+pub const BUMP_MAP_UNIT: u32 = 5;
+pub const NORMAL_MAP_UNIT: u32 = 6;
+
+/// Vertex uniform vectors left unused when deciding whether bones fit in uniforms.
+pub const VERTEX_UNIFORM_MARGIN: usize = 8;
+
+/// Vertex uniform vectors a mesh program declares besides its bone matrices:
+/// the projection, view and model matrices, the normal matrix, the point size
+/// and, each in a vector of its own, the morph target influences. Lights and
+/// material uniforms belong to the fragment shader's separate budget.
+pub fn vertex_uniform_vectors(morph_targets: usize) -> usize {
+    3 * 4 + 3 + 1 + morph_targets
+}
+
+const LIGHTS_PARS: &str = r#"
+uniform vec3 ambientLightColor;
+
+#if NUM_DIR_LIGHTS > 0
+struct DirectionalLight {
+  vec3 direction;
+  vec3 color;
+};
+uniform DirectionalLight directionalLights[NUM_DIR_LIGHTS];
+#endif
+
+#if NUM_POINT_LIGHTS > 0
+struct PointLight {
+  vec3 position;
+  vec3 color;
+  float distance;
+  float decay;
+};
+uniform PointLight pointLights[NUM_POINT_LIGHTS];
+#endif
+
+#if NUM_SPOT_LIGHTS > 0
+struct SpotLight {
+  vec3 position;
+  vec3 direction;
+  vec3 color;
+  float distance;
+  float decay;
+  float coneCos;
+  float penumbraCos;
+};
+uniform SpotLight spotLights[NUM_SPOT_LIGHTS];
+#endif
+
+struct ReflectedLight {
+  vec3 diffuse;
+  vec3 specular;
+};
+
+float distanceAttenuation(float lightDistance, float cutoffDistance, float decay) {
+  if (cutoffDistance > 0.0 && decay > 0.0) {
+    return pow(clamp(1.0 - lightDistance / cutoffDistance, 0.0, 1.0), decay);
+  }
+  return 1.0;
+}
+"#;
+
+const MAPS_PARS: &str = r#"
+#ifdef USE_UV
+varying vec2 vUv;
+#endif
+#ifdef USE_MAP
+uniform sampler2D map;
+#endif
+#ifdef USE_METALLIC_ROUGHNESS_MAP
+uniform sampler2D metallicRoughnessMap;
+#endif
+#ifdef USE_EMISSIVE_MAP
+uniform sampler2D emissiveMap;
+#endif
+#ifdef USE_AO_MAP
+uniform sampler2D aoMap;
+uniform float aoMapIntensity;
+#endif
+#ifdef USE_BUMPMAP
+uniform sampler2D bumpMap;
+uniform float bumpScale;
+
+// "Bump Mapping Unparametrized Surfaces on the GPU", Morten S. Mikkelsen:
+// the height gradient in screen space, from forward differences of the map
+vec2 dHdxy() {
+  vec2 dSTdx = dFdx(vUv);
+  vec2 dSTdy = dFdy(vUv);
+  float height = bumpScale * texture2D(bumpMap, vUv).x;
+  float dBx = bumpScale * texture2D(bumpMap, vUv + dSTdx).x - height;
+  float dBy = bumpScale * texture2D(bumpMap, vUv + dSTdy).x - height;
+  return vec2(dBx, dBy);
+}
+
+vec3 perturbNormal(vec3 surfacePosition, vec3 surfaceNormal, vec2 gradient) {
+  vec3 sigmaX = dFdx(surfacePosition);
+  vec3 sigmaY = dFdy(surfacePosition);
+  vec3 r1 = cross(sigmaY, surfaceNormal);
+  vec3 r2 = cross(surfaceNormal, sigmaX);
+  float det = dot(sigmaX, r1) * (float(gl_FrontFacing) * 2.0 - 1.0);
+  vec3 grad = sign(det) * (gradient.x * r1 + gradient.y * r2);
+  return normalize(abs(det) * surfaceNormal - grad);
+}
+#endif
+#ifdef USE_NORMALMAP
+uniform sampler2D normalMap;
+uniform vec2 normalScale;
+
+// "Followup: Normal Mapping Without Precomputed Tangents", Christian Schüler:
+// the tangent frame from screen space derivatives of the position and uv
+// the frame from a flipped back face normal is flipped again by `faceDirection`
+vec3 perturbNormal2Arb(vec3 surfacePosition, vec3 surfaceNormal, vec3 mapNormal, float faceDirection) {
+  vec3 q0 = dFdx(surfacePosition);
+  vec3 q1 = dFdy(surfacePosition);
+  vec2 st0 = dFdx(vUv);
+  vec2 st1 = dFdy(vUv);
+  vec3 q1perp = cross(q1, surfaceNormal);
+  vec3 q0perp = cross(surfaceNormal, q0);
+  vec3 T = q1perp * st0.x + q0perp * st1.x;
+  vec3 B = q1perp * st0.y + q0perp * st1.y;
+  float det = max(dot(T, T), dot(B, B));
+  float scale = det == 0.0 ? 0.0 : faceDirection * inversesqrt(det);
+  return normalize(T * (mapNormal.x * scale) + B * (mapNormal.y * scale) + surfaceNormal * mapNormal.z);
+}
+#endif
+"#;
+
+// glTF color textures are sRGB encoded, lighting is computed in linear space
+// and written back as sRGB.
+const COLORSPACE_PARS: &str = r#"
+vec4 sRGBToLinear(vec4 value) {
+  vec3 low = value.rgb * 0.0773993808;
+  vec3 high = pow(value.rgb * 0.9478672986 + vec3(0.0521327014), vec3(2.4));
+  return vec4(mix(high, low, vec3(lessThanEqual(value.rgb, vec3(0.04045)))), value.a);
+}
+
+vec4 linearToSRGB(vec4 value) {
+  vec3 low = value.rgb * 12.92;
+  vec3 high = pow(value.rgb, vec3(0.41666)) * 1.055 - vec3(0.055);
+  return vec4(mix(high, low, vec3(lessThanEqual(value.rgb, vec3(0.0031308)))), value.a);
+}
+"#;
+
+const BLINN_PHONG_PARS: &str = r#"
+uniform vec3 ambient;
+uniform bool hasAmbient;
+uniform vec3 specular;
+uniform float shininess;
+
+struct SurfaceMaterial {
+  vec3 diffuseColor;
+  vec3 ambientColor;
+  vec3 specularColor;
+  float shininess;
+};
+
+SurfaceMaterial surfaceMaterial(vec4 diffuseColor) {
+  vec3 ambientColor = hasAmbient ? ambient : diffuseColor.rgb;
+  return SurfaceMaterial(diffuseColor.rgb, ambientColor, specular, shininess);
+}
+
+// Blinn-Phong contribution of one light, all vectors normalized and in view space
+void addLight(vec3 lightDir, vec3 radiance, vec3 normal, vec3 viewDir, SurfaceMaterial material, inout ReflectedLight reflected) {
+  float nDotL = max(dot(normal, lightDir), 0.0);
+  if (nDotL <= 0.0) {
+    return;
+  }
+  vec3 halfDir = normalize(lightDir + viewDir);
+  float nDotH = max(dot(normal, halfDir), 0.0);
+  reflected.diffuse += radiance * nDotL * material.diffuseColor;
+  reflected.specular += radiance * nDotL * pow(nDotH, material.shininess) * material.specularColor;
+}
+"#;
+
+// glTF metallic-roughness BRDF: Lambert diffuse, GGX distribution, height-correlated
+// Smith visibility and Schlick fresnel. Light colors are treated like the Phong path,
+// i.e. irradiance is scaled by PI, so both materials look alike under the same lights.
+const PHYSICAL_PARS: &str = r#"
+#define PI 3.141592653589793
+#define RECIPROCAL_PI 0.3183098861837907
+
+uniform float metallic;
+uniform float roughness;
+
+struct SurfaceMaterial {
+  vec3 diffuseColor;
+  vec3 ambientColor;
+  vec3 specularColor;
+  float roughness;
+};
+
+SurfaceMaterial surfaceMaterial(vec4 diffuseColor) {
+  float metallicFactor = metallic;
+  float roughnessFactor = roughness;
+#ifdef USE_METALLIC_ROUGHNESS_MAP
+  vec4 metallicRoughness = texture2D(metallicRoughnessMap, vUv);
+  roughnessFactor *= metallicRoughness.g;
+  metallicFactor *= metallicRoughness.b;
+#endif
+  // dielectrics reflect about 4% at normal incidence
+  vec3 specularColor = mix(vec3(0.04), diffuseColor.rgb, metallicFactor);
+  // very low roughness makes the highlight of point lights vanish
+  float clampedRoughness = clamp(roughnessFactor, 0.0525, 1.0);
+  vec3 albedo = diffuseColor.rgb * (1.0 - metallicFactor);
+  return SurfaceMaterial(albedo, albedo, specularColor, clampedRoughness);
+}
+
+vec3 F_Schlick(vec3 f0, float dotVH) {
+  float fresnel = exp2((-5.55473 * dotVH - 6.98316) * dotVH);
+  return (1.0 - f0) * fresnel + f0;
+}
+
+float V_GGX_SmithCorrelated(float alpha, float dotNL, float dotNV) {
+  float a2 = alpha * alpha;
+  float gv = dotNL * sqrt(a2 + (1.0 - a2) * dotNV * dotNV);
+  float gl = dotNV * sqrt(a2 + (1.0 - a2) * dotNL * dotNL);
+  return 0.5 / max(gv + gl, 1e-6);
+}
+
+float D_GGX(float alpha, float dotNH) {
+  float a2 = alpha * alpha;
+  float denom = dotNH * dotNH * (a2 - 1.0) + 1.0;
+  return RECIPROCAL_PI * a2 / (denom * denom);
+}
+
+void addLight(vec3 lightDir, vec3 radiance, vec3 normal, vec3 viewDir, SurfaceMaterial material, inout ReflectedLight reflected) {
+  float dotNL = clamp(dot(normal, lightDir), 0.0, 1.0);
+  if (dotNL <= 0.0) {
+    return;
+  }
+  vec3 halfDir = normalize(lightDir + viewDir);
+  float dotNV = clamp(dot(normal, viewDir), 0.0, 1.0);
+  float dotNH = clamp(dot(normal, halfDir), 0.0, 1.0);
+  float dotVH = clamp(dot(viewDir, halfDir), 0.0, 1.0);
+  float alpha = material.roughness * material.roughness;
+
+  vec3 irradiance = radiance * dotNL * PI;
+  vec3 F = F_Schlick(material.specularColor, dotVH);
+  float V = V_GGX_SmithCorrelated(alpha, dotNL, dotNV);
+  float D = D_GGX(alpha, dotNH);
+
+  reflected.diffuse += irradiance * RECIPROCAL_PI * material.diffuseColor;
+  reflected.specular += irradiance * F * (V * D);
+}
+"#;
+
+// Sums every light through the `addLight` of whichever material chunk precedes it.
+const LIGHTS_FRAGMENT: &str = r#"
+ReflectedLight computeLighting(vec3 position, vec3 normal, vec3 viewDir, SurfaceMaterial material) {
+  vec3 ambientReflection = ambientLightColor * material.ambientColor;
+#ifdef USE_AO_MAP
+  ambientReflection *= (texture2D(aoMap, vUv).r - 1.0) * aoMapIntensity + 1.0;
+#endif
+  ReflectedLight reflected = ReflectedLight(ambientReflection, vec3(0.0));
+
+#if NUM_DIR_LIGHTS > 0
+  for (int i = 0; i < NUM_DIR_LIGHTS; i++) {
+    addLight(-directionalLights[i].direction, directionalLights[i].color, normal, viewDir, material, reflected);
+  }
+#endif
+
+#if NUM_POINT_LIGHTS > 0
+  for (int i = 0; i < NUM_POINT_LIGHTS; i++) {
+    vec3 toLight = pointLights[i].position - position;
+    float lightDistance = length(toLight);
+    float attenuation = distanceAttenuation(lightDistance, pointLights[i].distance, pointLights[i].decay);
+    addLight(toLight / lightDistance, pointLights[i].color * attenuation, normal, viewDir, material, reflected);
+  }
+#endif
+
+#if NUM_SPOT_LIGHTS > 0
+  for (int i = 0; i < NUM_SPOT_LIGHTS; i++) {
+    vec3 toLight = spotLights[i].position - position;
+    float lightDistance = length(toLight);
+    vec3 lightDir = toLight / lightDistance;
+    float angleCos = dot(lightDir, -spotLights[i].direction);
+    float spotEffect = smoothstep(spotLights[i].coneCos, spotLights[i].penumbraCos, angleCos);
+    float attenuation = distanceAttenuation(lightDistance, spotLights[i].distance, spotLights[i].decay);
+    addLight(lightDir, spotLights[i].color * spotEffect * attenuation, normal, viewDir, material, reflected);
+  }
+#endif
+
+  return reflected;
+}
+"#;
+
+// Bone matrices either come from a uniform array or, four rgba texels per bone
+// (one per column), from a square float texture.
+const SKINNING_PARS: &str = r#"
+#ifdef USE_SKINNING
+attribute vec4 skinIndex;
+attribute vec4 skinWeight;
+#ifdef BONE_TEXTURE
+uniform highp sampler2D boneTexture;
+uniform float boneTextureSize;
+
+mat4 getBoneMatrix(float i) {
+  float j = i * 4.0;
+  float x = mod(j, boneTextureSize);
+  float y = floor(j / boneTextureSize);
+  float texel = 1.0 / boneTextureSize;
+  float v = texel * (y + 0.5);
+  vec4 column0 = texture2D(boneTexture, vec2(texel * (x + 0.5), v));
+  vec4 column1 = texture2D(boneTexture, vec2(texel * (x + 1.5), v));
+  vec4 column2 = texture2D(boneTexture, vec2(texel * (x + 2.5), v));
+  vec4 column3 = texture2D(boneTexture, vec2(texel * (x + 3.5), v));
+  return mat4(column0, column1, column2, column3);
+}
+#else
+uniform mat4 boneMatrices[MAX_BONES];
+
+mat4 getBoneMatrix(float i) {
+  return boneMatrices[int(i)];
+}
+#endif
+#endif
+"#;
+
+/// Attribute and uniform declarations for `key.morph_targets` targets, plus the
+/// functions adding them to the base position and normal.
+fn morph_pars(key: &ProgramKey) -> String {
+    let count = key.morph_targets;
+    if count == 0 {
+        return String::new();
+    }
+    let mut pars = format!("uniform float morphTargetInfluences[{}];\n", count);
+    for i in 0..count {
+        pars.push_str(&format!("attribute vec3 morphTarget{};\n", i));
+        if key.morph_normals {
+            pars.push_str(&format!("attribute vec3 morphNormal{};\n", i));
+        }
+    }
+
+    pars.push_str("vec3 morphPosition(vec3 position) {\n");
+    for i in 0..count {
+        pars.push_str(&format!(
+            "  position += morphTarget{0} * morphTargetInfluences[{0}];\n",
+            i
+        ));
+    }
+    pars.push_str("  return position;\n}\n");
+
+    if key.morph_normals {
+        pars.push_str("vec3 morphNormal(vec3 normal) {\n");
+        for i in 0..count {
+            pars.push_str(&format!(
+                "  normal += morphNormal{0} * morphTargetInfluences[{0}];\n",
+                i
+            ));
+        }
+        pars.push_str("  return normal;\n}\n");
+    }
+    pars
+}
+
+const MESH_VERT: &str = r#"
+attribute vec3 position;
+attribute vec4 vertexColor;  //顶点颜色 (COLOR_0)
+attribute vec3 normal;
+#ifdef USE_UV
+attribute vec2 uv;
+varying vec2 vUv;
+#endif
+#ifdef USE_INSTANCING
+attribute mat4 instanceMatrix;
+attribute vec4 instanceColor;
+#endif
+uniform mat4 Pmatrix;
+uniform mat4 Vmatrix;
+uniform mat4 Mmatrix;
+// inverse transpose of the model-view matrix's upper 3x3
+uniform mat3 normalMatrix;
+// only read when drawing points
+uniform float pointSize;
+
+varying vec3 vViewPosition;
+varying vec3 vNormal;
+varying vec4 vColor;
+void main(void) {
+  vec4 objectPosition = vec4(position, 1.);
+  vec3 objectNormal = normal;
+#ifdef USE_MORPHTARGETS
+  objectPosition.xyz = morphPosition(objectPosition.xyz);
+#ifdef USE_MORPHNORMALS
+  objectNormal = morphNormal(objectNormal);
+#endif
+#endif
+#ifdef USE_SKINNING
+  mat4 skinMatrix = skinWeight.x * getBoneMatrix(skinIndex.x)
+    + skinWeight.y * getBoneMatrix(skinIndex.y)
+    + skinWeight.z * getBoneMatrix(skinIndex.z)
+    + skinWeight.w * getBoneMatrix(skinIndex.w);
+  objectPosition = skinMatrix * objectPosition;
+  objectNormal = mat3(skinMatrix[0].xyz, skinMatrix[1].xyz, skinMatrix[2].xyz) * objectNormal;
+#endif
+#ifdef USE_UV
+  vUv = uv;
+#endif
+#ifdef USE_INSTANCING
+  // instances are expected to be scaled uniformly, so their rotation part
+  // can be applied to normals directly
+  mat4 modelMatrix = Mmatrix * instanceMatrix;
+  objectNormal = mat3(instanceMatrix[0].xyz, instanceMatrix[1].xyz, instanceMatrix[2].xyz) * objectNormal;
+  vColor = instanceColor * vertexColor;
+#else
+  mat4 modelMatrix = Mmatrix;
+  vColor = vertexColor;
+#endif
+
+  vec4 viewPosition = Vmatrix * modelMatrix * objectPosition;
+  gl_Position = Pmatrix * viewPosition;
+
+  vViewPosition = viewPosition.xyz;
+  vNormal = normalMatrix * objectNormal;
+  gl_PointSize = pointSize;
+}
+"#;
+
+const MESH_FRAG: &str = r#"
+uniform vec4 color;  //表面基底色
+#ifdef STANDARD
+uniform vec3 emissive;
+uniform float alphaCutoff;
+uniform bool transparent;
+uniform bool doubleSided;
+#endif
+
+varying vec3 vViewPosition;
+varying vec3 vNormal;
+varying vec4 vColor;
+void main(void) {
+  vec4 diffuseColor = color * vColor;
+#ifdef USE_MAP
+#ifdef STANDARD
+  diffuseColor *= sRGBToLinear(texture2D(map, vUv));
+#else
+  diffuseColor *= texture2D(map, vUv);
+#endif
+#endif
+  vec3 normal = normalize(vNormal);
+  vec3 viewDir = normalize(-vViewPosition);
+  vec3 totalEmissive = vec3(0.0);
+
+#ifdef STANDARD
+  if (diffuseColor.a < alphaCutoff) {
+    discard;
+  }
+  // OPAQUE and MASK ignore alpha once the cutoff is applied
+  if (!transparent) {
+    diffuseColor.a = 1.0;
+  }
+  float faceDirection = doubleSided && !gl_FrontFacing ? -1.0 : 1.0;
+  normal *= faceDirection;
+#ifdef USE_NORMALMAP
+  vec3 mapNormal = texture2D(normalMap, vUv).xyz * 2.0 - 1.0;
+  mapNormal.xy *= normalScale;
+  normal = perturbNormal2Arb(vViewPosition, normal, mapNormal, faceDirection);
+#endif
+  totalEmissive = emissive;
+#ifdef USE_EMISSIVE_MAP
+  totalEmissive *= sRGBToLinear(texture2D(emissiveMap, vUv)).rgb;
+#endif
+#endif
+#ifdef USE_BUMPMAP
+  normal = perturbNormal(vViewPosition, normal, dHdxy());
+#endif
+
+#ifdef BASIC
+  gl_FragColor = diffuseColor;
+#else
+  ReflectedLight reflected = computeLighting(vViewPosition, normal, viewDir, surfaceMaterial(diffuseColor));
+  vec3 outgoing = reflected.diffuse + reflected.specular + totalEmissive;
+  gl_FragColor = vec4(outgoing, diffuseColor.a);
+#ifdef STANDARD
+  gl_FragColor = linearToSRGB(gl_FragColor);
+#endif
+#endif
+}
+"#;
+
+const PRECISION: &str = r#"
+#ifdef GL_FRAGMENT_PRECISION_HIGH
+precision highp float;
+#else
+precision mediump float;
+#endif
+"#;
+fn defines(key: &ProgramKey) -> String {
+    let mut defines = String::new();
+    if key.instanced {
+        defines.push_str("#define USE_INSTANCING\n");
+    }
+    match key.skinning {
+        Skinning::None => {}
+        Skinning::Uniforms(bones) => {
+            defines.push_str("#define USE_SKINNING\n");
+            defines.push_str(&format!("#define MAX_BONES {}\n", bones));
+        }
+        Skinning::Texture => {
+            defines.push_str("#define USE_SKINNING\n");
+            defines.push_str("#define BONE_TEXTURE\n");
+        }
+    }
+    if key.morph_targets > 0 {
+        defines.push_str("#define USE_MORPHTARGETS\n");
+        if key.morph_normals {
+            defines.push_str("#define USE_MORPHNORMALS\n");
+        }
+    }
+    match key.material {
+        MaterialType::Basic => defines.push_str("#define BASIC\n"),
+        MaterialType::Phong => {}
+        MaterialType::Standard => defines.push_str("#define STANDARD\n"),
+    }
+    if key.maps.any() {
+        defines.push_str("#define USE_UV\n");
+    }
+    if key.maps.map {
+        defines.push_str("#define USE_MAP\n");
+    }
+    if key.maps.metallic_roughness_map {
+        defines.push_str("#define USE_METALLIC_ROUGHNESS_MAP\n");
+    }
+    if key.maps.emissive_map {
+        defines.push_str("#define USE_EMISSIVE_MAP\n");
+    }
+    if key.maps.occlusion_map {
+        defines.push_str("#define USE_AO_MAP\n");
+    }
+    if key.maps.bump_map {
+        defines.push_str("#define USE_BUMPMAP\n");
+    }
+    if key.maps.normal_map {
+        defines.push_str("#define USE_NORMALMAP\n");
+    }
+    defines.push_str(&format!("#define NUM_DIR_LIGHTS {}\n", key.lights.directional));
+    defines.push_str(&format!("#define NUM_POINT_LIGHTS {}\n", key.lights.point));
+    defines.push_str(&format!("#define NUM_SPOT_LIGHTS {}\n", key.lights.spot));
+    defines
+}
+
+pub fn mesh_vertex_shader(key: &ProgramKey) -> String {
+    format!(
+        "{}{}{}{}",
+        defines(key),
+        morph_pars(key),
+        SKINNING_PARS,
+        MESH_VERT
+    )
+}
+
+pub fn mesh_fragment_shader(key: &ProgramKey) -> String {
+    // unlit materials leave out the lighting functions
+    let (material_pars, lights_fragment) = match key.material {
+        MaterialType::Basic => ("", ""),
+        MaterialType::Phong => (BLINN_PHONG_PARS, LIGHTS_FRAGMENT),
+        MaterialType::Standard => (PHYSICAL_PARS, LIGHTS_FRAGMENT),
+    };
+    // derivatives are core in WebGL 2, the renderer only sets bump and normal
+    // maps when either that or OES_standard_derivatives is available
+    let extensions = if key.maps.bump_map || key.maps.normal_map {
+        "#extension GL_OES_standard_derivatives : enable\n"
+    } else {
+        ""
+    };
+    format!(
+        "{}{}{}{}{}{}{}{}{}",
+        extensions,
+        PRECISION,
+        defines(key),
+        MAPS_PARS,
+        COLORSPACE_PARS,
+        LIGHTS_PARS,
+        material_pars,
+        lights_fragment,
+        MESH_FRAG
+    )
+}
@@ -0,0 +1,77 @@
+extern crate gmath;
+use gmath::mat4;
+use std::collections::HashMap;
+use web_sys::WebGlTexture;
+
+/// A joint of a skeleton. Bones are ordinary scene nodes, animating a bone means
+/// moving its node.
+#[derive(Clone)]
+pub struct Bone {
+    /// `Object3D::id` of the node that moves the joint
+    pub node: usize,
+    /// transform from mesh space to the joint's space at bind time
+    pub inverse_bind_matrix: [f32; 16],
+}
+
+/// The bones deforming a skinned mesh, `skinIndex` attributes index into `bones`.
+#[allow(non_snake_case)]
+pub struct Skeleton {
+    pub bones: Vec<Bone>,
+    /// one matrix per bone, moving bind-pose vertices to their posed position in
+    /// mesh space
+    pub bone_matrices: Vec<f32>,
+    /// `bone_matrices` as a square float texture and its width, for contexts with
+    /// too few uniforms
+    pub __webGLBoneTexture: Option<(u32, WebGlTexture)>,
+}
+
+/// The copy uploads its own bone texture.
+impl Clone for Skeleton {
+    fn clone(&self) -> Self {
+        Skeleton::new(self.bones.clone())
+    }
+}
+
+impl Skeleton {
+    pub fn new(bones: Vec<Bone>) -> Skeleton {
+        let mut bone_matrices = Vec::with_capacity(bones.len() * 16);
+        for _ in 0..bones.len() {
+            bone_matrices.extend_from_slice(&mat4::new_identity::<f32>());
+        }
+        Skeleton {
+            bones,
+            bone_matrices,
+            __webGLBoneTexture: None,
+        }
+    }
+
+    /// Recomputes the bone matrices from the world matrices of the bone nodes.
+    /// Bones whose node isn't in `matrices_world` keep their bind pose.
+    pub fn update(
+        &mut self,
+        matrices_world: &HashMap<usize, [f32; 16]>,
+        mesh_matrix_world: &[f32; 16],
+    ) {
+        let mut mesh_inverse = mat4::new_identity();
+        mat4::inv(&mut mesh_inverse, mesh_matrix_world);
+
+        for (bone, out) in self.bones.iter().zip(self.bone_matrices.chunks_mut(16)) {
+            let bone_matrix_world = match matrices_world.get(&bone.node) {
+                Some(matrix) => matrix,
+                None => {
+                    out.copy_from_slice(&mat4::new_identity::<f32>());
+                    continue;
+                }
+            };
+            let mut bone_matrix = mat4::new_identity();
+            mat4::mul(
+                &mut bone_matrix,
+                bone_matrix_world,
+                &bone.inverse_bind_matrix,
+            );
+            let mut matrix = mat4::new_identity();
+            mat4::mul(&mut matrix, &mesh_inverse, &bone_matrix);
+            out.copy_from_slice(&matrix);
+        }
+    }
+}
@@ -0,0 +1,16 @@
+use crate::mesh::Mesh;
+use crate::skeleton::Skeleton;
+
+/// A mesh deformed by a skeleton. Its geometry carries `skinIndex`/`skinWeight`
+/// attributes, up to four bones per vertex, and is blended on the GPU.
+#[derive(Clone)]
+pub struct SkinnedMesh {
+    pub mesh: Mesh,
+    pub skeleton: Skeleton,
+}
+
+impl SkinnedMesh {
+    pub fn new(mesh: Mesh, skeleton: Skeleton) -> SkinnedMesh {
+        SkinnedMesh { mesh, skeleton }
+    }
+}
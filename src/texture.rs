@@ -0,0 +1,173 @@
+use crate::error::{Error, Result};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use web_sys::{HtmlImageElement, WebGlRenderingContext};
+
+static NEXT_TEXTURE_ID: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Wrapping {
+    Repeat,
+    ClampToEdge,
+    MirroredRepeat,
+}
+
+impl Wrapping {
+    pub fn to_gl(self) -> u32 {
+        match self {
+            Wrapping::Repeat => WebGlRenderingContext::REPEAT,
+            Wrapping::ClampToEdge => WebGlRenderingContext::CLAMP_TO_EDGE,
+            Wrapping::MirroredRepeat => WebGlRenderingContext::MIRRORED_REPEAT,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Filter {
+    Nearest,
+    Linear,
+    NearestMipmapNearest,
+    LinearMipmapNearest,
+    NearestMipmapLinear,
+    LinearMipmapLinear,
+}
+
+impl Filter {
+    pub fn to_gl(self) -> u32 {
+        match self {
+            Filter::Nearest => WebGlRenderingContext::NEAREST,
+            Filter::Linear => WebGlRenderingContext::LINEAR,
+            Filter::NearestMipmapNearest => WebGlRenderingContext::NEAREST_MIPMAP_NEAREST,
+            Filter::LinearMipmapNearest => WebGlRenderingContext::LINEAR_MIPMAP_NEAREST,
+            Filter::NearestMipmapLinear => WebGlRenderingContext::NEAREST_MIPMAP_LINEAR,
+            Filter::LinearMipmapLinear => WebGlRenderingContext::LINEAR_MIPMAP_LINEAR,
+        }
+    }
+
+    pub fn uses_mipmaps(self) -> bool {
+        !matches!(self, Filter::Nearest | Filter::Linear)
+    }
+
+    /// The closest filter that reads only the base level.
+    pub fn without_mipmaps(self) -> Filter {
+        match self {
+            Filter::Nearest | Filter::NearestMipmapNearest | Filter::NearestMipmapLinear => {
+                Filter::Nearest
+            }
+            _ => Filter::Linear,
+        }
+    }
+}
+
+/// Pixel layout of raw texture data, one byte per channel.
+#[derive(Clone, Copy, PartialEq)]
+pub enum TextureFormat {
+    Rgba,
+    Rgb,
+    LuminanceAlpha,
+    Luminance,
+    Alpha,
+}
+
+impl TextureFormat {
+    pub fn to_gl(self) -> u32 {
+        match self {
+            TextureFormat::Rgba => WebGlRenderingContext::RGBA,
+            TextureFormat::Rgb => WebGlRenderingContext::RGB,
+            TextureFormat::LuminanceAlpha => WebGlRenderingContext::LUMINANCE_ALPHA,
+            TextureFormat::Luminance => WebGlRenderingContext::LUMINANCE,
+            TextureFormat::Alpha => WebGlRenderingContext::ALPHA,
+        }
+    }
+}
+
+pub enum TextureSource {
+    /// decoded by the browser, uploaded once the image has finished loading
+    Image(HtmlImageElement),
+    Data {
+        width: u32,
+        height: u32,
+        format: TextureFormat,
+        pixels: Vec<u8>,
+    },
+}
+
+/// An image and the sampler state used to read it. Textures are shared between
+/// materials through `Rc`; the renderer keeps one GPU copy per texture.
+pub struct Texture {
+    id: usize,
+    version: Cell<u32>,
+    pub source: TextureSource,
+    pub wrap_s: Wrapping,
+    pub wrap_t: Wrapping,
+    pub mag_filter: Filter,
+    pub min_filter: Filter,
+    pub generate_mipmaps: bool,
+    /// flip the image vertically on upload, so that v = 0 is its bottom row
+    pub flip_y: bool,
+    pub premultiply_alpha: bool,
+}
+
+impl Texture {
+    pub fn new(source: TextureSource) -> Texture {
+        Texture {
+            id: NEXT_TEXTURE_ID.fetch_add(1, Ordering::Relaxed),
+            version: Cell::new(0),
+            source,
+            wrap_s: Wrapping::Repeat,
+            wrap_t: Wrapping::Repeat,
+            mag_filter: Filter::Linear,
+            min_filter: Filter::LinearMipmapLinear,
+            generate_mipmaps: true,
+            flip_y: true,
+            premultiply_alpha: false,
+        }
+    }
+
+    /// Starts loading the image at `url`, the texture is uploaded once it arrives.
+    pub fn from_url(url: &str) -> Result<Texture> {
+        let image =
+            HtmlImageElement::new().map_err(|_| Error::Allocation(String::from("image")))?;
+        // allow sampling images served from other origins that send CORS headers
+        image.set_cross_origin(Some("anonymous"));
+        image.set_src(url);
+        Ok(Texture::new(TextureSource::Image(image)))
+    }
+
+    pub fn from_data(width: u32, height: u32, format: TextureFormat, pixels: Vec<u8>) -> Texture {
+        Texture::new(TextureSource::Data {
+            width,
+            height,
+            format,
+            pixels,
+        })
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version.get()
+    }
+
+    /// Asks the renderer to upload the source again, e.g. after drawing into it.
+    pub fn set_needs_update(&self) {
+        self.version.set(self.version.get() + 1);
+    }
+
+    /// Whether the source has pixels to upload yet.
+    pub fn is_ready(&self) -> bool {
+        match &self.source {
+            TextureSource::Image(image) => image.complete() && image.natural_width() > 0,
+            TextureSource::Data { .. } => true,
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        match &self.source {
+            TextureSource::Image(image) => (image.natural_width(), image.natural_height()),
+            TextureSource::Data { width, height, .. } => (*width, *height),
+        }
+    }
+}
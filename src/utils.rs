@@ -4,15 +4,19 @@ extern crate js_sys;
 extern crate num_traits;
 extern crate wasm_bindgen;
 extern crate web_sys;
-use wasm_bindgen::{JsCast};
+use wasm_bindgen::{JsCast, JsValue};
 use web_sys::{Request, RequestInit, RequestMode, Response,WebGlRenderingContext};
-use wasm_bindgen_futures::{JsFuture,future_to_promise};
+use wasm_bindgen_futures::JsFuture;
 use futures::{future, Future};
 use js_sys::Promise;
 
 use wasm_bindgen::prelude::Closure;
 
 use web_sys::{WebGlProgram, WebGlShader};
+
+use crate::error::{Error, Result};
+use crate::loaders::resolver::{ResourceError, ResourceFuture};
+
 pub fn set_panic_hook() {
     // When the `console_error_panic_hook` feature is enabled, we can call the
     // `set_panic_hook` function at least once during initialization, and then
@@ -36,10 +40,10 @@ pub fn compile_shader(
     context: &WebGlRenderingContext,
     shader_type: u32,
     source: &str,
-) -> Result<WebGlShader, String> {
+) -> Result<WebGlShader> {
     let shader = context
         .create_shader(shader_type)
-        .ok_or_else(|| String::from("Unable to create shader object"))?;
+        .ok_or_else(|| Error::Allocation(String::from("shader object")))?;
     context.shader_source(&shader, source);
     context.compile_shader(&shader);
 
@@ -50,25 +54,34 @@ pub fn compile_shader(
     {
         Ok(shader)
     } else {
-        Err(context
+        let log = context
             .get_shader_info_log(&shader)
-            .unwrap_or_else(|| String::from("Unknown error creating shader")))
+            .unwrap_or_else(|| String::from("Unknown error creating shader"));
+        context.delete_shader(Some(&shader));
+        Err(Error::Shader(log))
     }
 }
 
+/// Links the two shaders into a program and deletes them, a linked program
+/// doesn't need its shaders anymore and a failed one is discarded.
 pub fn link_program(
     context: &WebGlRenderingContext,
     vert_shader: &WebGlShader,
     frag_shader: &WebGlShader,
-) -> Result<WebGlProgram, String> {
+) -> Result<WebGlProgram> {
     let program = context
         .create_program()
-        .ok_or_else(|| String::from("Unable to create shader object"))?;
+        .ok_or_else(|| Error::Allocation(String::from("program object")))?;
 
     context.attach_shader(&program, vert_shader);
     context.attach_shader(&program, frag_shader);
     context.link_program(&program);
 
+    for shader in &[vert_shader, frag_shader] {
+        context.detach_shader(&program, shader);
+        context.delete_shader(Some(shader));
+    }
+
     if context
         .get_program_parameter(&program, WebGlRenderingContext::LINK_STATUS)
         .as_bool()
@@ -76,9 +89,11 @@ pub fn link_program(
     {
         Ok(program)
     } else {
-        Err(context
+        let log = context
             .get_program_info_log(&program)
-            .unwrap_or_else(|| String::from("Unknown error creating program object")))
+            .unwrap_or_else(|| String::from("Unknown error creating program object"));
+        context.delete_program(Some(&program));
+        Err(Error::Link(log))
     }
 }
 
@@ -86,7 +101,7 @@ pub fn window() -> web_sys::Window {
     web_sys::window().expect("no global `window` exists")
 }
 
-pub fn request_animation_frame(f: &Closure<FnMut(f32)>) {
+pub fn request_animation_frame(f: &Closure<dyn FnMut(f32)>) {
     window()
         .request_animation_frame(f.as_ref().unchecked_ref())
         .expect("should register `requestAnimationFrame` OK");
@@ -99,62 +114,77 @@ pub fn log_num(num: f64) {
 
 fn log_num_arr(&num_arr: &[f32; 16], name: &str) {
     web_sys::console::log_1(&name.into());
-    let mut i: i32 = 0;
-    for x in num_arr.iter() {
-        let mut item_str = String::from(x.to_string());
+    for (i, x) in num_arr.iter().enumerate() {
+        let mut item_str = x.to_string();
         item_str = i.to_string() + ":" + &item_str;
         web_sys::console::log_1(&item_str.into());
-        i = i + 1;
     }
 }
 
-pub fn get_string_from_xhr(url: &str) -> Promise  {
-
-    let mut opts = RequestInit::new();
-    opts.method("GET");
-    opts.mode(RequestMode::Cors);
-    let request = Request::new_with_str_and_init(url, &opts).unwrap();
-
-    request
-        .headers()
-        .set("Accept", "application/vnd.github.v3+json")
-        .unwrap();
-
-    let window = web_sys::window().unwrap();
-    let request_promise = window.fetch_with_request(&request);
-
-    let future = JsFuture::from(request_promise)
-        .and_then(|resp_value| {
-            // `resp_value` is a `Response` object.
-            assert!(resp_value.is_instance_of::<Response>());
-            let resp: Response = resp_value.dyn_into().unwrap();
-            resp.text()
-        })
-        .and_then(|js_value_str: Promise| {
-            // Convert this other `Promise` into a rust `Future`.
-            JsFuture::from(js_value_str)
+/// Fetches `url` as text.
+pub fn get_string_from_xhr(url: &str) -> Box<dyn Future<Item = String, Error = ResourceError>> {
+    let text_url = url.to_string();
+    let future = fetch(url, Response::text).and_then(move |text| {
+        text.as_string().ok_or_else(|| ResourceError {
+            url: text_url,
+            reason: String::from("response body isn't text"),
         })
-        .and_then(|js_value_str| {
-            // web_sys::console::log_1(&js_value_str);
-
-            //** ob parse *//
-            //let meshs = loaders::obj::load_obj_string(&js_value_str.as_string().unwrap()).unwrap();
-            // start_render(meshs);
-            // cb(meshs);
-
-            /*
-            /**gltf parse */
-            let meshs = loaders::gltf::load_gltf_string(&js_value_str.as_string().unwrap()).unwrap();
-            start_render(meshs);
-            */
-
-            // let mut cl = move |resolve: js_sys::Function, reject: js_sys::Function| {};
-            // let promise_cb: &mut dyn std::ops::FnMut(js_sys::Function, js_sys::Function) = &mut cl;
-            // JsFuture::from(Promise::new(promise_cb))
+    });
+    Box::new(future)
+}
 
-            JsFuture::from(Promise::resolve(&js_value_str))
-        });
+/// Fetches `url` as binary data.
+pub fn get_array_buffer_from_xhr(url: &str) -> ResourceFuture {
+    let future = fetch(url, Response::array_buffer)
+        .map(|array_buffer| js_sys::Uint8Array::new(&array_buffer).to_vec());
+    Box::new(future)
+}
 
-    future_to_promise(future)
+/// GETs `url` and reads the body with `read`. Every failure, from an invalid
+/// URL to an HTTP error status, fails the future instead of panicking.
+fn fetch(
+    url: &str,
+    read: fn(&Response) -> std::result::Result<Promise, JsValue>,
+) -> Box<dyn Future<Item = JsValue, Error = ResourceError>> {
+    let error = |reason: &str| ResourceError {
+        url: url.to_string(),
+        reason: reason.to_string(),
+    };
+    let opts = RequestInit::new();
+    opts.set_method("GET");
+    opts.set_mode(RequestMode::Cors);
+    let request = match Request::new_with_str_and_init(url, &opts) {
+        Ok(request) => request,
+        Err(_) => return Box::new(future::err(error("invalid request"))),
+    };
+    let window = match web_sys::window() {
+        Some(window) => window,
+        None => return Box::new(future::err(error("no global `window` to fetch from"))),
+    };
+
+    let url = url.to_string();
+    let future = JsFuture::from(window.fetch_with_request(&request))
+        .map_err(request_failure)
+        .and_then(move |response| {
+            let response: Response = response
+                .dyn_into()
+                .map_err(|_| String::from("fetch didn't resolve to a Response"))?;
+            // fetch only rejects on network errors, a 404 still resolves
+            if !response.ok() {
+                return Err(format!("HTTP {}", response.status()));
+            }
+            read(&response).map_err(request_failure)
+        })
+        .and_then(|body| JsFuture::from(body).map_err(request_failure))
+        .map_err(move |reason| ResourceError { url, reason });
+    Box::new(future)
 }
 
+fn request_failure(err: JsValue) -> String {
+    match err.dyn_ref::<js_sys::Error>() {
+        Some(err) => err.message().into(),
+        None => err
+            .as_string()
+            .unwrap_or_else(|| String::from("request failed")),
+    }
+}
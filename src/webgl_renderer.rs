@@ -5,20 +5,38 @@ extern crate num_traits;
 extern crate wasm_bindgen;
 extern crate web_sys;
 use crate::camera::Camera;
+use crate::error::{Error, Result};
+use crate::light::LightUniforms;
+use crate::material::{Material, MaterialMaps};
+use crate::geometry::{self, Attribute, AttributeData, Geometry, Indices, MAX_U16_VERTICES};
+use crate::instanced_mesh::InstancedMesh;
+use crate::mesh::{DrawMode, Mesh};
 use crate::scene::Scene;
-use gmath::mat4;
+use crate::skeleton::Skeleton;
+use crate::texture::{Texture, TextureSource, Wrapping};
+use gmath::{mat3, mat4};
 use js_sys::WebAssembly;
-use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
-use web_sys::{WebGlRenderingContext, WebGlUniformLocation};
+use web_sys::{
+    AngleInstancedArrays, OesVertexArrayObject, WebGl2RenderingContext, WebGlBuffer,
+    WebGlRenderingContext, WebGlTexture, WebGlVertexArrayObject,
+};
 
-use crate::utils::{compile_shader, link_program, set_panic_hook,log_num};
+use crate::program_info::ProgramInfo;
+use crate::shaders::{
+    mesh_fragment_shader, mesh_vertex_shader, vertex_uniform_vectors, ProgramKey, Skinning,
+    AO_MAP_UNIT, BONE_TEXTURE_UNIT, BUMP_MAP_UNIT, EMISSIVE_MAP_UNIT, MAP_UNIT,
+    METALLIC_ROUGHNESS_MAP_UNIT, NORMAL_MAP_UNIT, VERTEX_UNIFORM_MARGIN,
+};
+use std::rc::Rc;
+use std::collections::HashMap;
+use crate::utils::set_panic_hook;
 
 #[macro_export]
 macro_rules! float_32_array {
     ($arr:expr) => {{
         let memory_buffer = wasm_bindgen::memory()
-            .dyn_into::<WebAssembly::Memory>()?
+            .unchecked_into::<WebAssembly::Memory>()
             .buffer();
         let arr_location = $arr.as_ptr() as u32 / 4;
         let array = js_sys::Float32Array::new(&memory_buffer)
@@ -27,10 +45,58 @@ macro_rules! float_32_array {
     }};
 }
 #[macro_export]
+macro_rules! uint_32_array {
+    ($arr:expr) => {{
+        let memory_buffer = wasm_bindgen::memory()
+            .unchecked_into::<WebAssembly::Memory>()
+            .buffer();
+        let arr_location = $arr.as_ptr() as u32 / 4;
+        let array = js_sys::Uint32Array::new(&memory_buffer)
+            .subarray(arr_location, arr_location + $arr.len() as u32);
+        array
+    }};
+}
+#[macro_export]
+macro_rules! uint_8_array {
+    ($arr:expr) => {{
+        let memory_buffer = wasm_bindgen::memory()
+            .unchecked_into::<WebAssembly::Memory>()
+            .buffer();
+        let arr_location = $arr.as_ptr() as u32;
+        let array = js_sys::Uint8Array::new(&memory_buffer)
+            .subarray(arr_location, arr_location + $arr.len() as u32);
+        array
+    }};
+}
+#[macro_export]
+macro_rules! int_8_array {
+    ($arr:expr) => {{
+        let memory_buffer = wasm_bindgen::memory()
+            .unchecked_into::<WebAssembly::Memory>()
+            .buffer();
+        let arr_location = $arr.as_ptr() as u32;
+        let array = js_sys::Int8Array::new(&memory_buffer)
+            .subarray(arr_location, arr_location + $arr.len() as u32);
+        array
+    }};
+}
+#[macro_export]
+macro_rules! int_16_array {
+    ($arr:expr) => {{
+        let memory_buffer = wasm_bindgen::memory()
+            .unchecked_into::<WebAssembly::Memory>()
+            .buffer();
+        let arr_location = $arr.as_ptr() as u32 / 2;
+        let array = js_sys::Int16Array::new(&memory_buffer)
+            .subarray(arr_location, arr_location + $arr.len() as u32);
+        array
+    }};
+}
+#[macro_export]
 macro_rules! uint_16_array {
     ($arr:expr) => {{
         let memory_buffer = wasm_bindgen::memory()
-            .dyn_into::<WebAssembly::Memory>()?
+            .unchecked_into::<WebAssembly::Memory>()
             .buffer();
         let arr_location = $arr.as_ptr() as u32 / 2;
         let array = js_sys::Uint16Array::new(&memory_buffer)
@@ -39,23 +105,145 @@ macro_rules! uint_16_array {
     }};
 }
 
+/// GPU copy of one geometry attribute and the layout to read it with.
+pub struct AttributeBuffer {
+    pub buffer: WebGlBuffer,
+    pub item_size: i32,
+    /// `FLOAT`, `UNSIGNED_BYTE`, ...
+    pub component_type: u32,
+    pub normalized: bool,
+}
+
+/// GPU copies of one drawable chunk of a geometry. A geometry is uploaded as
+/// several chunks when it needs u32 indices and the context can't draw them.
+pub struct GeometryBuffers {
+    pub attributes: HashMap<String, AttributeBuffer>,
+    /// offsets of every morph target, by the name of the attribute they move
+    pub morph_attributes: HashMap<String, Vec<AttributeBuffer>>,
+    /// positions and normals morphed on the CPU, used when the active targets
+    /// don't fit in the attribute slots
+    pub morphed_attributes: HashMap<String, WebGlBuffer>,
+    /// influences `morphed_attributes` were blended with, they are only
+    /// uploaded again when the influences change
+    pub morphed_influences: Option<Vec<f32>>,
+    /// positions, normals and their targets of a chunk split off a morphed
+    /// geometry, so the CPU fallback doesn't split the geometry every frame
+    pub morph_source: Option<Geometry>,
+    pub face: WebGlBuffer,
+    /// `UNSIGNED_SHORT` or `UNSIGNED_INT`
+    pub index_type: u32,
+    pub count: i32,
+    /// attribute setup recorded the first time the chunk is drawn, together with the
+    /// program it was recorded for, since attribute locations differ between programs
+    pub vao: Option<(ProgramKey, WebGlVertexArrayObject)>,
+}
+
+/// Where vertex array objects come from on the current context.
+enum VertexArrays {
+    /// WebGL2 core vertex arrays
+    Native(WebGl2RenderingContext),
+    /// WebGL1 `OES_vertex_array_object` extension
+    Oes(OesVertexArrayObject),
+    /// no vertex arrays, attributes are rebound for every draw
+    Unsupported,
+}
+
+/// The maps of `material` a context can sample, bump and normal maps need derivatives.
+fn material_maps(material: &Material, standard_derivatives: bool) -> MaterialMaps {
+    let mut maps = material.maps();
+    maps.bump_map &= standard_derivatives;
+    maps.normal_map &= standard_derivatives;
+    maps
+}
+
+/// Inverse transpose of the upper 3x3 of a column-major matrix, used to carry
+/// normals through non-uniform scales.
+fn normal_matrix(m: &[f32; 16]) -> [f32; 9] {
+    let (a00, a01, a02) = (m[0], m[1], m[2]);
+    let (a10, a11, a12) = (m[4], m[5], m[6]);
+    let (a20, a21, a22) = (m[8], m[9], m[10]);
+
+    let b01 = a22 * a11 - a12 * a21;
+    let b11 = -a22 * a10 + a12 * a20;
+    let b21 = a21 * a10 - a11 * a20;
+
+    let det = a00 * b01 + a01 * b11 + a02 * b21;
+    if det == 0.0 {
+        return mat3::new_identity();
+    }
+    let inv_det = 1.0 / det;
+
+    // the inverse, written out already transposed
+    [
+        b01 * inv_det,
+        b11 * inv_det,
+        b21 * inv_det,
+        (-a22 * a01 + a02 * a21) * inv_det,
+        (a22 * a00 - a02 * a20) * inv_det,
+        (-a21 * a00 + a01 * a20) * inv_det,
+        (a12 * a01 - a02 * a11) * inv_det,
+        (-a12 * a00 + a02 * a10) * inv_det,
+        (a11 * a00 - a01 * a10) * inv_det,
+    ]
+}
+
+/// How the morph targets of a mesh are drawn this frame.
+enum Morphing {
+    /// no targets, or none active and no slots for them
+    None,
+    /// blended in the vertex shader, the targets bound to the slots, most influential first
+    Gpu(Vec<usize>),
+    /// more active targets than slots, blended on the CPU
+    Cpu,
+}
+
+/// How instanced draw calls are issued on the current context.
+enum Instancing {
+    /// WebGL2 core `vertexAttribDivisor`/`drawElementsInstanced`
+    Native(WebGl2RenderingContext),
+    /// WebGL1 `ANGLE_instanced_arrays` extension
+    Angle(AngleInstancedArrays),
+    /// no instancing, draw every instance on its own
+    Unsupported,
+}
+
 pub struct WebGLRenderer {
     pub dom_element: web_sys::HtmlCanvasElement,
     _gl: web_sys::WebGlRenderingContext,
-    _program: web_sys::WebGlProgram,
+    _programs: HashMap<ProgramKey, ProgramInfo>,
+    _instancing: Instancing,
+    _vertex_arrays: VertexArrays,
+    _uint_indices: bool,
+    /// uploaded textures by `Texture::id`, with the version they were uploaded at
+    _textures: HashMap<usize, (u32, WebGlTexture)>,
+    /// opaque white, bound in place of textures that haven't loaded yet
+    _placeholder_texture: WebGlTexture,
+    /// `MAX_VERTEX_UNIFORM_VECTORS`, shared by the bone matrices and the other
+    /// vertex uniforms of a program
+    _max_vertex_uniforms: usize,
+    /// whether vertex shaders can sample float textures, needed for bone textures
+    _float_textures: bool,
+    _max_vertex_attribs: usize,
+    /// whether fragment shaders have `dFdx`/`dFdy`, needed for bump maps
+    _standard_derivatives: bool,
     pub auto_clear: bool,
 }
 
 #[allow(non_snake_case)]
 impl WebGLRenderer {
-    pub fn new() -> WebGLRenderer {
-        fn initGL(canvas: &web_sys::HtmlCanvasElement) -> web_sys::WebGlRenderingContext {
-            let gl = canvas
-                .get_context("webgl")
-                .unwrap()
-                .unwrap()
-                .dyn_into::<WebGlRenderingContext>()
-                .unwrap();
+    pub fn new() -> Result<WebGLRenderer> {
+        fn initGL(canvas: &web_sys::HtmlCanvasElement) -> Result<web_sys::WebGlRenderingContext> {
+            // Prefer WebGL2. Its context implements every WebGL1 method we call, so it is
+            // driven through the WebGL1 bindings and only downcast for WebGL2-only calls.
+            let gl = match canvas.get_context("webgl2") {
+                Ok(Some(context)) => context.unchecked_into::<WebGlRenderingContext>(),
+                _ => canvas
+                    .get_context("webgl")
+                    .ok()
+                    .and_then(|context| context)
+                    .and_then(|context| context.dyn_into::<WebGlRenderingContext>().ok())
+                    .ok_or_else(|| Error::Context(String::from("WebGL is not available")))?,
+            };
 
             // Clear the canvas
             gl.clear_color(1.0, 1.0, 1.0, 1.0);
@@ -74,72 +262,128 @@ impl WebGLRenderer {
 
             // gl.clear_color(0.0, 0.0, 0.0, 0.0);
 
-            gl
-        }
-
-        fn initProgram(_gl: &web_sys::WebGlRenderingContext) -> web_sys::WebGlProgram {
-            /*=========================Shaders========================*/
-
-            // vertex shader source code
-            let vertCode = r#"
-               attribute vec3 position;
-               attribute vec4 color;  //表面基底色
-               attribute vec3 normal;
-               uniform mat4 Pmatrix;
-               uniform mat4 Vmatrix;
-               uniform mat4 Mmatrix;
-               
-               varying vec3 vColor;
-               void main(void) {
-                 vec3 lightColor = vec3(1.0, 1.0, 1.0);
-                 vec3 lightDirection = vec3(0.5, 3.0, 4.0);  //归一化的世界坐标（入射光方向）
-                 vec3 ambientLight = vec3(0.2, 0.2, 0.2);
-
-                 
-                 gl_Position = Pmatrix*Vmatrix*Mmatrix*vec4(position, 1.);
-
-                 vec3 normal1 = normalize(vec3(normal));              //对法向量进行归一化
-                 float nDotL = max(dot(lightDirection, normal1), 0.0);//计算光线方向和法向量的点积
-                 vec3 diffuse = lightColor * vec3(color) * nDotL;     //计算漫反射光的颜色
-                 vec3 ambient = ambientLight * vec3(color);
-                 vColor = diffuse + ambient;  //vColor = normal;
-               }
-            "#;
-
-            // Create a vertex shader object
-            let vertShader =
-                compile_shader(_gl, WebGlRenderingContext::VERTEX_SHADER, vertCode).unwrap();
-
-            // fragment shader source code
-            let fragCode = r#"
-               precision mediump float;
-               varying vec3 vColor;
-               void main(void) {
-                 gl_FragColor = vec4(vColor, 1.);
-            }"#;
-            // Create fragment shader object
-            let fragShader =
-                compile_shader(_gl, WebGlRenderingContext::FRAGMENT_SHADER, fragCode).unwrap();
-            // Link both programs
-            let _program = link_program(_gl, &vertShader, &fragShader).unwrap();
-            // Use the combined shader program object
-            _gl.use_program(Some(&_program));
-            _program
-        }
-
-        let document = web_sys::window().unwrap().document().unwrap();
-        let canvas = document.get_element_by_id("canvas").unwrap();
-        let _canvas = canvas.dyn_into::<web_sys::HtmlCanvasElement>().unwrap();
-
-        let gl = initGL(&_canvas);
-        let _program = initProgram(&gl);
-
-        WebGLRenderer {
+            Ok(gl)
+        }
+
+        fn initInstancing(_gl: &web_sys::WebGlRenderingContext) -> Instancing {
+            if let Some(gl2) = _gl.dyn_ref::<WebGl2RenderingContext>() {
+                return Instancing::Native(gl2.clone());
+            }
+            match _gl.get_extension("ANGLE_instanced_arrays") {
+                Ok(Some(ext)) => Instancing::Angle(ext.unchecked_into::<AngleInstancedArrays>()),
+                _ => Instancing::Unsupported,
+            }
+        }
+
+        fn initVertexArrays(_gl: &web_sys::WebGlRenderingContext) -> VertexArrays {
+            if let Some(gl2) = _gl.dyn_ref::<WebGl2RenderingContext>() {
+                return VertexArrays::Native(gl2.clone());
+            }
+            match _gl.get_extension("OES_vertex_array_object") {
+                Ok(Some(ext)) => VertexArrays::Oes(ext.unchecked_into::<OesVertexArrayObject>()),
+                _ => VertexArrays::Unsupported,
+            }
+        }
+
+        fn initUintIndices(_gl: &web_sys::WebGlRenderingContext) -> bool {
+            _gl.is_instance_of::<WebGl2RenderingContext>()
+                || _gl
+                    .get_extension("OES_element_index_uint")
+                    .map(|ext| ext.is_some())
+                    .unwrap_or(false)
+        }
+
+        fn initPlaceholderTexture(_gl: &web_sys::WebGlRenderingContext) -> Result<WebGlTexture> {
+            let texture = _gl
+                .create_texture()
+                .ok_or_else(|| Error::Allocation(String::from("placeholder texture")))?;
+            _gl.bind_texture(WebGlRenderingContext::TEXTURE_2D, Some(&texture));
+            _gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+                WebGlRenderingContext::TEXTURE_2D,
+                0,
+                WebGlRenderingContext::RGBA as i32,
+                1,
+                1,
+                0,
+                WebGlRenderingContext::RGBA,
+                WebGlRenderingContext::UNSIGNED_BYTE,
+                Some(&[255, 255, 255, 255]),
+            )
+            .map_err(|_| Error::Allocation(String::from("placeholder texture")))?;
+            Ok(texture)
+        }
+
+        fn initMaxVertexUniforms(_gl: &web_sys::WebGlRenderingContext) -> usize {
+            _gl.get_parameter(WebGlRenderingContext::MAX_VERTEX_UNIFORM_VECTORS)
+                .ok()
+                .and_then(|value| value.as_f64())
+                .unwrap_or(128.0) as usize
+        }
+
+        fn initFloatTextures(_gl: &web_sys::WebGlRenderingContext) -> bool {
+            let vertex_texture_units = _gl
+                .get_parameter(WebGlRenderingContext::MAX_VERTEX_TEXTURE_IMAGE_UNITS)
+                .ok()
+                .and_then(|value| value.as_f64())
+                .unwrap_or(0.0);
+            if vertex_texture_units < 1.0 {
+                return false;
+            }
+            _gl.is_instance_of::<WebGl2RenderingContext>()
+                || _gl
+                    .get_extension("OES_texture_float")
+                    .map(|ext| ext.is_some())
+                    .unwrap_or(false)
+        }
+
+        fn initMaxVertexAttribs(_gl: &web_sys::WebGlRenderingContext) -> usize {
+            _gl.get_parameter(WebGlRenderingContext::MAX_VERTEX_ATTRIBS)
+                .ok()
+                .and_then(|value| value.as_f64())
+                .unwrap_or(8.0) as usize
+        }
+
+        fn initStandardDerivatives(_gl: &web_sys::WebGlRenderingContext) -> bool {
+            _gl.is_instance_of::<WebGl2RenderingContext>()
+                || _gl
+                    .get_extension("OES_standard_derivatives")
+                    .map(|ext| ext.is_some())
+                    .unwrap_or(false)
+        }
+
+        let document = web_sys::window()
+            .and_then(|window| window.document())
+            .ok_or_else(|| Error::Context(String::from("no document")))?;
+        let _canvas = document
+            .get_element_by_id("canvas")
+            .and_then(|canvas| canvas.dyn_into::<web_sys::HtmlCanvasElement>().ok())
+            .ok_or_else(|| Error::Context(String::from("no <canvas id=\"canvas\"> element")))?;
+
+        let gl = initGL(&_canvas)?;
+        let _instancing = initInstancing(&gl);
+        let _vertex_arrays = initVertexArrays(&gl);
+        let _uint_indices = initUintIndices(&gl);
+        let _placeholder_texture = initPlaceholderTexture(&gl)?;
+        let _max_vertex_uniforms = initMaxVertexUniforms(&gl);
+        let _float_textures = initFloatTextures(&gl);
+        let _max_vertex_attribs = initMaxVertexAttribs(&gl);
+        let _standard_derivatives = initStandardDerivatives(&gl);
+
+        Ok(WebGLRenderer {
             dom_element: _canvas,
             _gl: gl,
-            _program: _program,
+            _programs: HashMap::new(),
+            _instancing,
+            _vertex_arrays,
+            _uint_indices,
+            _textures: HashMap::new(),
+            _placeholder_texture,
+            _max_vertex_uniforms,
+            _float_textures,
+            _max_vertex_attribs,
+            _standard_derivatives,
             auto_clear: true,
-        }
+        })
     }
 
     pub fn set_size(&self, width: u32, height: u32) {
@@ -160,247 +404,1167 @@ impl WebGLRenderer {
             WebGlRenderingContext::COLOR_BUFFER_BIT | WebGlRenderingContext::DEPTH_BUFFER_BIT,
         );
     }
-    pub fn render(&self, scene: &mut Scene, camera: &mut Camera) -> Result<(), JsValue> {
-        set_panic_hook();
-
-        if self.auto_clear == true {
-            self.clear();
-        }
-
-        for object in &mut scene.objects {
-            if object.__webGLVertexBuffer.is_none() {
-                /*==========Defining and storing the geometry=======*/
+    fn create_array_buffer(&self, data: &[f32], usage: u32) -> Result<WebGlBuffer> {
+        let buffer = self
+            ._gl
+            .create_buffer()
+            .ok_or_else(|| Error::Allocation(String::from("buffer")))?;
+        self._gl
+            .bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, Some(&buffer));
+        let array = float_32_array!(data);
+        self._gl.buffer_data_with_array_buffer_view(
+            WebGlRenderingContext::ARRAY_BUFFER,
+            &array,
+            usage,
+        );
+        Ok(buffer)
+    }
 
-                // Create an empty buffer object to store the vertex buffer
-                object.__webGLVertexBuffer = Some(
-                    self._gl
-                        .create_buffer()
-                        .ok_or("failed to create buffer")
-                        .unwrap(),
-                );
+    /// Re-uploads `data` into `buffer`, creating the buffer on first use, so vertex
+    /// arrays that reference it stay valid.
+    fn update_array_buffer(
+        &self,
+        buffer: Option<WebGlBuffer>,
+        data: &[f32],
+    ) -> Result<WebGlBuffer> {
+        let buffer = match buffer {
+            Some(buffer) => buffer,
+            None => return self.create_array_buffer(data, WebGlRenderingContext::DYNAMIC_DRAW),
+        };
+        self._gl
+            .bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, Some(&buffer));
+        let array = float_32_array!(data);
+        self._gl.buffer_data_with_array_buffer_view(
+            WebGlRenderingContext::ARRAY_BUFFER,
+            &array,
+            WebGlRenderingContext::DYNAMIC_DRAW,
+        );
+        Ok(buffer)
+    }
 
-                //Bind appropriate array buffer to it
-                self._gl.bind_buffer(
+    fn create_attribute_buffer(&self, attribute: &Attribute) -> Result<AttributeBuffer> {
+        let buffer = match &attribute.data {
+            AttributeData::F32(data) => {
+                self.create_array_buffer(data, WebGlRenderingContext::STATIC_DRAW)?
+            }
+            data => {
+                let array: js_sys::Object = match data {
+                    AttributeData::U8(data) => uint_8_array!(data).into(),
+                    AttributeData::U16(data) => uint_16_array!(data).into(),
+                    AttributeData::I8(data) => int_8_array!(data).into(),
+                    AttributeData::I16(data) => int_16_array!(data).into(),
+                    AttributeData::F32(_) => unreachable!(),
+                };
+                let buffer = self
+                    ._gl
+                    .create_buffer()
+                    .ok_or_else(|| Error::Allocation(String::from("buffer")))?;
+                self._gl
+                    .bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, Some(&buffer));
+                self._gl.buffer_data_with_array_buffer_view(
                     WebGlRenderingContext::ARRAY_BUFFER,
-                    object.__webGLVertexBuffer.as_ref(),
+                    &array,
+                    WebGlRenderingContext::STATIC_DRAW,
                 );
+                buffer
+            }
+        };
+        Ok(AttributeBuffer {
+            buffer,
+            item_size: attribute.item_size as i32,
+            component_type: attribute.data.component_type(),
+            normalized: attribute.normalized,
+        })
+    }
 
-                let vertices = float_32_array!(&object.geometry.vertices);
-                // web_sys::console::log_1(&vertices);
-
-                // Pass the vertex data to the buffer
+    fn create_index_buffer(&self, indices: &Indices) -> Result<(WebGlBuffer, u32)> {
+        let buffer = self
+            ._gl
+            .create_buffer()
+            .ok_or_else(|| Error::Allocation(String::from("buffer")))?;
+        self._gl
+            .bind_buffer(WebGlRenderingContext::ELEMENT_ARRAY_BUFFER, Some(&buffer));
+        let index_type = match indices {
+            Indices::U16(data) => {
+                let array = uint_16_array!(data);
                 self._gl.buffer_data_with_array_buffer_view(
-                    WebGlRenderingContext::ARRAY_BUFFER,
-                    &vertices,
+                    WebGlRenderingContext::ELEMENT_ARRAY_BUFFER,
+                    &array,
                     WebGlRenderingContext::STATIC_DRAW,
                 );
-
-                //normal start
-                object.__webGLNormalBuffer = Some(
-                    self._gl
-                        .create_buffer()
-                        .ok_or("failed to create buffer")
-                        .unwrap(),
+                WebGlRenderingContext::UNSIGNED_SHORT
+            }
+            Indices::U32(data) => {
+                let array = uint_32_array!(data);
+                self._gl.buffer_data_with_array_buffer_view(
+                    WebGlRenderingContext::ELEMENT_ARRAY_BUFFER,
+                    &array,
+                    WebGlRenderingContext::STATIC_DRAW,
                 );
+                WebGlRenderingContext::UNSIGNED_INT
+            }
+        };
+        Ok((buffer, index_type))
+    }
 
-                self._gl.bind_buffer(
-                    WebGlRenderingContext::ARRAY_BUFFER,
-                    object.__webGLNormalBuffer.as_ref(),
-                );
+    /// Whether `geometry` uses u32 indices that the context can't draw.
+    fn needs_split(&self, geometry: &Geometry) -> bool {
+        match geometry.indices {
+            Indices::U32(_) => !self._uint_indices,
+            Indices::U16(_) => false,
+        }
+    }
 
-                let normals = float_32_array!(&object.geometry.normals);
+    /// Uploads `geometry`, splitting it into u16 chunks first when it uses
+    /// u32 indices that the context can't draw.
+    fn create_geometry_buffers(
+        &self,
+        geometry: &Geometry,
+        mode: DrawMode,
+    ) -> Result<Vec<GeometryBuffers>> {
+        let split;
+        let chunks = if self.needs_split(geometry) {
+            split = geometry.split(MAX_U16_VERTICES, mode.primitive_size())?;
+            &split[..]
+        } else {
+            std::slice::from_ref(geometry)
+        };
 
-                self._gl.buffer_data_with_array_buffer_view(
-                    WebGlRenderingContext::ARRAY_BUFFER,
-                    &normals,
-                    WebGlRenderingContext::STATIC_DRAW,
-                );
+        let mut buffers = Vec::new();
+        for chunk in chunks {
+            let mut attributes = HashMap::new();
+            for (name, attribute) in chunk.attributes.iter() {
+                attributes.insert(name.clone(), self.create_attribute_buffer(attribute)?);
+            }
+            let mut morph_attributes = HashMap::new();
+            for (name, targets) in chunk.morph_attributes.iter() {
+                let targets = targets
+                    .iter()
+                    .map(|target| self.create_attribute_buffer(target))
+                    .collect::<Result<Vec<_>>>()?;
+                morph_attributes.insert(name.clone(), targets);
+            }
+            let (face, index_type) = self.create_index_buffer(&chunk.indices)?;
+            let morph_source = if chunks.len() > 1 && chunk.morph_target_count() > 0 {
+                let mut source = Geometry::new(Indices::U16(Vec::new()));
+                for &name in &[geometry::POSITION, geometry::NORMAL] {
+                    if let Some(attribute) = chunk.attribute(name) {
+                        source.set_attribute(name, attribute.clone());
+                    }
+                    if let Some(targets) = chunk.morph_attributes.get(name) {
+                        source.morph_attributes.insert(name.to_string(), targets.clone());
+                    }
+                }
+                Some(source)
+            } else {
+                None
+            };
 
-                //normal end
+            buffers.push(GeometryBuffers {
+                attributes,
+                morph_attributes,
+                morphed_attributes: HashMap::new(),
+                morphed_influences: None,
+                morph_source,
+                face,
+                index_type,
+                count: chunk.indices.len() as i32,
+                vao: None,
+            });
+        }
+        Ok(buffers)
+    }
 
-                // Create an empty buffer object to store the vertex buffer
-                object.__webGLColorBuffer = Some(
-                    self._gl
-                        .create_buffer()
-                        .ok_or("failed to create buffer")
-                        .unwrap(),
-                );
-                //Bind appropriate array buffer to it
-                self._gl.bind_buffer(
-                    WebGlRenderingContext::ARRAY_BUFFER,
-                    object.__webGLColorBuffer.as_ref(),
-                );
+    fn bind_attribute(
+        &self,
+        program_info: &ProgramInfo,
+        name: &str,
+        buffer: Option<&WebGlBuffer>,
+        size: i32,
+    ) -> Result<()> {
+        let location = program_info
+            .attribute(name)
+            .ok_or_else(|| Error::Link(format!("cannot get {}", name)))?;
+        self._gl
+            .bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, buffer);
+        self._gl.vertex_attrib_pointer_with_i32(
+            location,
+            size,
+            WebGlRenderingContext::FLOAT,
+            false,
+            0,
+            0,
+        );
+        self._gl.enable_vertex_attrib_array(location);
+        Ok(())
+    }
 
-                let len = &object.geometry.indices.len();
+    pub fn is_webgl2(&self) -> bool {
+        self._gl.is_instance_of::<WebGl2RenderingContext>()
+    }
 
-                let mut colors: Vec<f32> = Vec::new();
+    fn bind_vertex_array(&self, vao: Option<&WebGlVertexArrayObject>) {
+        match &self._vertex_arrays {
+            VertexArrays::Native(gl2) => gl2.bind_vertex_array(vao),
+            VertexArrays::Oes(ext) => ext.bind_vertex_array_oes(vao),
+            VertexArrays::Unsupported => {}
+        }
+    }
 
-                for x in 0..*len {
-                    colors.push(object.material.color[0]);
-                    colors.push(object.material.color[1]);
-                    colors.push(object.material.color[2]);
-                    colors.push(object.material.color[3]);
-                }
-                let colors = float_32_array!(&colors);
-                // web_sys::console::log_1(&colors);
+    /// Makes the chunk's attributes current for the program `key`. With vertex arrays
+    /// available `setup` only runs the first time, recording its bindings into the
+    /// chunk's vao; otherwise it runs for every draw.
+    fn bind_geometry<F>(
+        &self,
+        buffers: &mut GeometryBuffers,
+        key: ProgramKey,
+        setup: F,
+    ) -> Result<()>
+    where
+        F: Fn(&GeometryBuffers) -> Result<()>,
+    {
+        match &buffers.vao {
+            Some((vao_key, vao)) if *vao_key == key => {
+                self.bind_vertex_array(Some(vao));
+                return Ok(());
+            }
+            Some((_, vao)) => self.delete_vertex_array(vao),
+            None => {}
+        }
 
-                // Pass the vertex data to the buffer
-                self._gl.buffer_data_with_array_buffer_view(
-                    WebGlRenderingContext::ARRAY_BUFFER,
-                    &colors,
-                    WebGlRenderingContext::STATIC_DRAW,
-                );
+        let vao = match &self._vertex_arrays {
+            VertexArrays::Native(gl2) => gl2.create_vertex_array(),
+            VertexArrays::Oes(ext) => ext.create_vertex_array_oes(),
+            VertexArrays::Unsupported => None,
+        };
+        self.bind_vertex_array(vao.as_ref());
+        setup(buffers)?;
+        buffers.vao = vao.map(|vao| (key, vao));
+        Ok(())
+    }
+
+    fn delete_vertex_array(&self, vao: &WebGlVertexArrayObject) {
+        match &self._vertex_arrays {
+            VertexArrays::Native(gl2) => gl2.delete_vertex_array(Some(vao)),
+            VertexArrays::Oes(ext) => ext.delete_vertex_array_oes(Some(vao)),
+            VertexArrays::Unsupported => {}
+        }
+    }
+
+    /// Binds every chunk attribute the program declares, by name. Declared attributes
+    /// the chunk lacks are disabled, so the shader reads their constant value instead.
+    fn bind_attributes(
+        &self,
+        program_info: &ProgramInfo,
+        buffers: &GeometryBuffers,
+    ) -> Result<()> {
+        for (name, &location) in program_info.attributes.iter() {
+            match buffers.attributes.get(name) {
+                Some(attribute) => self.bind_attribute_buffer(location, attribute),
+                None => self._gl.disable_vertex_attrib_array(location),
+            }
+        }
+        Ok(())
+    }
+
+    fn bind_attribute_buffer(&self, location: u32, attribute: &AttributeBuffer) {
+        self._gl.bind_buffer(
+            WebGlRenderingContext::ARRAY_BUFFER,
+            Some(&attribute.buffer),
+        );
+        self._gl.vertex_attrib_pointer_with_i32(
+            location,
+            attribute.item_size,
+            attribute.component_type,
+            attribute.normalized,
+            0,
+            0,
+        );
+        self._gl.enable_vertex_attrib_array(location);
+    }
+
+    /// Attribute slots for GPU morph targets and whether they include normals.
+    fn morph_slots(&self, geometry: &Geometry) -> (usize, bool) {
+        let targets = geometry.morph_target_count();
+        if targets == 0 {
+            return (0, false);
+        }
+        let normals = geometry.morph_attributes.contains_key(geometry::NORMAL);
+        // leave room for position, normal, vertexColor, uv, skinIndex and skinWeight
+        let free = self._max_vertex_attribs.saturating_sub(6);
+        let (per_target, max_targets) = if normals { (2, 4) } else { (1, 8) };
+        (targets.min(max_targets).min(free / per_target), normals)
+    }
+
+    /// Binds the active targets to the slots when they fit, the most influential first.
+    fn morphing(&self, mesh: &Mesh) -> Morphing {
+        let (slots, _) = self.morph_slots(&mesh.geometry);
+        let mut active: Vec<usize> = (0..mesh.geometry.morph_target_count())
+            .filter(|&i| mesh.morph_target_influences.get(i).is_some_and(|&w| w != 0.0))
+            .collect();
+        active.sort_by(|&a, &b| {
+            let (a, b) = (mesh.morph_target_influences[a], mesh.morph_target_influences[b]);
+            b.abs().partial_cmp(&a.abs()).unwrap_or(std::cmp::Ordering::Equal)
+        });
+        if active.len() > slots {
+            Morphing::Cpu
+        } else if slots > 0 {
+            Morphing::Gpu(active)
+        } else {
+            Morphing::None
+        }
+    }
+
+    /// Uploads the positions and normals of `mesh` with every target applied,
+    /// unless the buffers already hold them for the current influences.
+    fn upload_morphed_attributes(&self, mesh: &mut Mesh) -> Result<()> {
+        let influences = &mesh.morph_target_influences;
+        for buffers in mesh.__webGLBuffers.iter_mut() {
+            if buffers.morphed_influences.as_ref() == Some(influences) {
+                continue;
+            }
+            let source = buffers.morph_source.as_ref().unwrap_or(&mesh.geometry);
+            for &name in &[geometry::POSITION, geometry::NORMAL] {
+                if let Some(Attribute {
+                    data: AttributeData::F32(data),
+                    ..
+                }) = source.morphed_attribute(name, influences)
+                {
+                    let buffer = buffers.morphed_attributes.remove(name);
+                    buffers
+                        .morphed_attributes
+                        .insert(name.to_string(), self.update_array_buffer(buffer, &data)?);
+                }
+            }
+            buffers.morphed_influences = Some(influences.clone());
+        }
+        Ok(())
+    }
 
-                // Create an empty buffer object to store Index buffer
-                object.__webGLFaceBuffer = Some(
+    /// Points position and normal at the CPU-morphed or the base data and the morph
+    /// slots at the chosen targets. Runs after the chunk's vao is bound, since the
+    /// bindings change from frame to frame.
+    fn bind_morph_attributes(
+        &self,
+        program_info: &ProgramInfo,
+        buffers: &GeometryBuffers,
+        morphing: &Morphing,
+        slots: usize,
+    ) {
+        for &name in &[geometry::POSITION, geometry::NORMAL] {
+            let location = match program_info.attribute(name) {
+                Some(location) => location,
+                None => continue,
+            };
+            match (morphing, buffers.morphed_attributes.get(name)) {
+                (Morphing::Cpu, Some(buffer)) => {
                     self._gl
-                        .create_buffer()
-                        .ok_or("failed to create buffer")
-                        .unwrap(),
-                );
-                // Bind appropriate array buffer to it
-                self._gl.bind_buffer(
-                    WebGlRenderingContext::ELEMENT_ARRAY_BUFFER,
-                    object.__webGLFaceBuffer.as_ref(),
-                );
+                        .bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, Some(buffer));
+                    self._gl.vertex_attrib_pointer_with_i32(
+                        location,
+                        3,
+                        WebGlRenderingContext::FLOAT,
+                        false,
+                        0,
+                        0,
+                    );
+                }
+                _ => {
+                    if let Some(attribute) = buffers.attributes.get(name) {
+                        self.bind_attribute_buffer(location, attribute);
+                    }
+                }
+            }
+        }
+
+        let chosen: &[usize] = match morphing {
+            Morphing::Gpu(chosen) => chosen,
+            _ => &[],
+        };
+        for slot in 0..slots {
+            for &(prefix, name) in &[
+                ("morphTarget", geometry::POSITION),
+                ("morphNormal", geometry::NORMAL),
+            ] {
+                let location = match program_info.attribute(&format!("{}{}", prefix, slot)) {
+                    Some(location) => location,
+                    None => continue,
+                };
+                let target = chosen
+                    .get(slot)
+                    .and_then(|&target| buffers.morph_attributes.get(name)?.get(target));
+                match target {
+                    Some(target) => self.bind_attribute_buffer(location, target),
+                    None => self._gl.disable_vertex_attrib_array(location),
+                }
+            }
+        }
+    }
 
-                let indices = uint_16_array!(&object.geometry.indices);
-                // web_sys::console::log_1(&indices);
+    fn set_default_vertex_color(&self, program_info: &ProgramInfo) {
+        if let Some(location) = program_info.attribute("vertexColor") {
+            self._gl.vertex_attrib4f(location, 1.0, 1.0, 1.0, 1.0);
+        }
+    }
 
-                // Pass the vertex data to the buffer
-                self._gl.buffer_data_with_array_buffer_view(
-                    WebGlRenderingContext::ELEMENT_ARRAY_BUFFER,
-                    &indices,
-                    WebGlRenderingContext::STATIC_DRAW,
+    fn vertex_attrib_divisor(&self, location: u32, divisor: u32) {
+        match &self._instancing {
+            Instancing::Native(gl2) => gl2.vertex_attrib_divisor(location, divisor),
+            Instancing::Angle(ext) => ext.vertex_attrib_divisor_angle(location, divisor),
+            Instancing::Unsupported => {}
+        }
+    }
+
+    /// Uploads the camera matrices, they are shared by every object drawn with `program_info`.
+    fn set_camera_uniforms(&self, program_info: &ProgramInfo, camera: &Camera) -> Result<()> {
+        let view_matrix: [f32; 16] = camera.matrix;
+
+        let proj_matrix: [f32; 16] = camera.projection_matrix;
+
+        // let proj_matrix_js = float_32_array!(&proj_matrix);
+        // web_sys::console::log_1(&proj_matrix_js);
+
+        // let view_matrix_js = float_32_array!(&view_matrix);
+        // web_sys::console::log_1(&view_matrix_js);
+
+        let Pmatrix = program_info
+            .uniform("Pmatrix")
+            .ok_or_else(|| Error::Link(String::from("cannot get Pmatrix")))?;
+        let Vmatrix = program_info
+            .uniform("Vmatrix")
+            .ok_or_else(|| Error::Link(String::from("cannot get Vmatrix")))?;
+
+        self._gl
+            .uniform_matrix4fv_with_f32_array(Some(Pmatrix), false, &proj_matrix);
+        self._gl
+            .uniform_matrix4fv_with_f32_array(Some(Vmatrix), false, &view_matrix);
+        Ok(())
+    }
+
+    /// Links the program for `key` the first time it is needed.
+    fn prepare_program(&mut self, key: ProgramKey) -> Result<()> {
+        if !self._programs.contains_key(&key) {
+            let program_info = ProgramInfo::new(
+                &self._gl,
+                &mesh_vertex_shader(&key),
+                &mesh_fragment_shader(&key),
+            )?;
+            self._programs.insert(key, program_info);
+        }
+        Ok(())
+    }
+
+    fn set_light_uniforms(&self, program_info: &ProgramInfo, lights: &LightUniforms) {
+        let gl = &self._gl;
+        gl.uniform3fv_with_f32_array(program_info.uniform("ambientLightColor"), &lights.ambient);
+
+        for (i, light) in lights.directional.iter().enumerate() {
+            let name = |field: &str| format!("directionalLights[{}].{}", i, field);
+            gl.uniform3fv_with_f32_array(program_info.uniform(&name("direction")), &light.direction);
+            gl.uniform3fv_with_f32_array(program_info.uniform(&name("color")), &light.color);
+        }
+        for (i, light) in lights.point.iter().enumerate() {
+            let name = |field: &str| format!("pointLights[{}].{}", i, field);
+            gl.uniform3fv_with_f32_array(program_info.uniform(&name("position")), &light.position);
+            gl.uniform3fv_with_f32_array(program_info.uniform(&name("color")), &light.color);
+            gl.uniform1f(program_info.uniform(&name("distance")), light.distance);
+            gl.uniform1f(program_info.uniform(&name("decay")), light.decay);
+        }
+        for (i, light) in lights.spot.iter().enumerate() {
+            let name = |field: &str| format!("spotLights[{}].{}", i, field);
+            gl.uniform3fv_with_f32_array(program_info.uniform(&name("position")), &light.position);
+            gl.uniform3fv_with_f32_array(program_info.uniform(&name("direction")), &light.direction);
+            gl.uniform3fv_with_f32_array(program_info.uniform(&name("color")), &light.color);
+            gl.uniform1f(program_info.uniform(&name("distance")), light.distance);
+            gl.uniform1f(program_info.uniform(&name("decay")), light.decay);
+            let cone_cos = light.angle.cos();
+            // smoothstep is undefined unless its first edge is below the second,
+            // which a penumbra of zero would make equal
+            let penumbra_cos = (light.angle * (1.0 - light.penumbra)).cos().max(cone_cos + 1e-4);
+            gl.uniform1f(program_info.uniform(&name("coneCos")), cone_cos);
+            gl.uniform1f(program_info.uniform(&name("penumbraCos")), penumbra_cos);
+        }
+    }
+
+    /// Uploads `texture` the first time it is used and whenever its version changes.
+    /// Images that are still loading are skipped and retried on the next frame.
+    fn upload_texture(&mut self, texture: &Texture) -> Result<()> {
+        let uploaded = self._textures.get(&texture.id());
+        if uploaded.map(|(version, _)| *version) == Some(texture.version()) || !texture.is_ready() {
+            return Ok(());
+        }
+        let gl_texture = match uploaded {
+            Some((_, gl_texture)) => gl_texture.clone(),
+            None => self
+                ._gl
+                .create_texture()
+                .ok_or_else(|| Error::Allocation(String::from("texture")))?,
+        };
+
+        let gl = &self._gl;
+        let target = WebGlRenderingContext::TEXTURE_2D;
+        gl.active_texture(WebGlRenderingContext::TEXTURE0);
+        gl.bind_texture(target, Some(&gl_texture));
+        gl.pixel_storei(WebGlRenderingContext::UNPACK_FLIP_Y_WEBGL, texture.flip_y as i32);
+        gl.pixel_storei(
+            WebGlRenderingContext::UNPACK_PREMULTIPLY_ALPHA_WEBGL,
+            texture.premultiply_alpha as i32,
+        );
+        match &texture.source {
+            TextureSource::Image(image) => gl.tex_image_2d_with_u32_and_u32_and_image(
+                target,
+                0,
+                WebGlRenderingContext::RGBA as i32,
+                WebGlRenderingContext::RGBA,
+                WebGlRenderingContext::UNSIGNED_BYTE,
+                image,
+            )
+            // e.g. a cross-origin image without CORS headers
+            .map_err(|_| Error::Allocation(String::from("texture image")))?,
+            TextureSource::Data {
+                width,
+                height,
+                format,
+                pixels,
+            } => {
+                // rows of rgb data aren't 4-byte aligned
+                gl.pixel_storei(WebGlRenderingContext::UNPACK_ALIGNMENT, 1);
+                gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+                    target,
+                    0,
+                    format.to_gl() as i32,
+                    *width as i32,
+                    *height as i32,
+                    0,
+                    format.to_gl(),
+                    WebGlRenderingContext::UNSIGNED_BYTE,
+                    Some(pixels),
+                )
+                .map_err(|_| Error::Allocation(String::from("texture data")))?;
+                gl.pixel_storei(WebGlRenderingContext::UNPACK_ALIGNMENT, 4);
+            }
+        }
+
+        // WebGL1 can only repeat and mipmap power-of-two textures
+        let (width, height) = texture.size();
+        let power_of_two = width.is_power_of_two() && height.is_power_of_two();
+        let full_support = power_of_two || self.is_webgl2();
+        let (wrap_s, wrap_t, min_filter) = if full_support {
+            (texture.wrap_s, texture.wrap_t, texture.min_filter)
+        } else {
+            let wrap = Wrapping::ClampToEdge;
+            (wrap, wrap, texture.min_filter.without_mipmaps())
+        };
+        let min_filter = if texture.generate_mipmaps {
+            min_filter
+        } else {
+            min_filter.without_mipmaps()
+        };
+        gl.tex_parameteri(target, WebGlRenderingContext::TEXTURE_WRAP_S, wrap_s.to_gl() as i32);
+        gl.tex_parameteri(target, WebGlRenderingContext::TEXTURE_WRAP_T, wrap_t.to_gl() as i32);
+        gl.tex_parameteri(
+            target,
+            WebGlRenderingContext::TEXTURE_MAG_FILTER,
+            texture.mag_filter.to_gl() as i32,
+        );
+        gl.tex_parameteri(target, WebGlRenderingContext::TEXTURE_MIN_FILTER, min_filter.to_gl() as i32);
+        if min_filter.uses_mipmaps() {
+            gl.generate_mipmap(target);
+        }
+
+        self._textures
+            .insert(texture.id(), (texture.version(), gl_texture));
+        Ok(())
+    }
+
+    /// Frees the GPU copy of `texture`, it is uploaded again if used later.
+    pub fn dispose_texture(&mut self, texture: &Texture) {
+        if let Some((_, gl_texture)) = self._textures.remove(&texture.id()) {
+            self._gl.delete_texture(Some(&gl_texture));
+        }
+    }
+
+    fn upload_material_textures(&mut self, material: &Material) -> Result<()> {
+        for texture in material.textures() {
+            self.upload_texture(texture)?;
+        }
+        Ok(())
+    }
+
+    /// Binds `texture` to `unit` and points the sampler uniform `name` at it.
+    fn bind_texture(
+        &self,
+        program_info: &ProgramInfo,
+        name: &str,
+        unit: u32,
+        texture: &Option<Rc<Texture>>,
+    ) {
+        let texture = match texture {
+            Some(texture) => texture,
+            None => return,
+        };
+        let gl_texture = self
+            ._textures
+            .get(&texture.id())
+            .map(|(_, gl_texture)| gl_texture)
+            .unwrap_or(&self._placeholder_texture);
+        self._gl
+            .active_texture(WebGlRenderingContext::TEXTURE0 + unit);
+        self._gl
+            .bind_texture(WebGlRenderingContext::TEXTURE_2D, Some(gl_texture));
+        self._gl.uniform1i(program_info.uniform(name), unit as i32);
+    }
+
+    /// Makes the program current and uploads the uniforms shared by every object drawn
+    /// with it in this frame.
+    fn use_program(
+        &self,
+        program_info: &ProgramInfo,
+        camera: &Camera,
+        lights: &LightUniforms,
+    ) -> Result<()> {
+        self._gl.use_program(Some(&program_info.program));
+        self.set_camera_uniforms(program_info, camera)?;
+        self.set_light_uniforms(program_info, lights);
+        self.set_default_vertex_color(program_info);
+        Ok(())
+    }
+
+    /// Uploads the per-object transform and material parameters. Material values are
+    /// uniforms, so edits show up on the next frame.
+    fn set_object_uniforms(
+        &self,
+        program_info: &ProgramInfo,
+        camera: &Camera,
+        model_matrix: &[f32; 16],
+        material: &Material,
+    ) {
+        let mut model_view_matrix = mat4::new_identity();
+        mat4::mul(&mut model_view_matrix, &camera.matrix, model_matrix);
+
+        self._gl.uniform_matrix4fv_with_f32_array(
+            program_info.uniform("Mmatrix"),
+            false,
+            model_matrix,
+        );
+        self._gl.uniform_matrix3fv_with_f32_array(
+            program_info.uniform("normalMatrix"),
+            false,
+            &normal_matrix(&model_view_matrix),
+        );
+        self.set_material_uniforms(program_info, material);
+    }
+
+    fn set_material_uniforms(&self, program_info: &ProgramInfo, material: &Material) {
+        let gl = &self._gl;
+        gl.uniform4fv_with_f32_array(program_info.uniform("color"), &material.color());
+        match material {
+            Material::Basic(material) => {
+                gl.uniform1f(program_info.uniform("pointSize"), material.size);
+                self.bind_texture(program_info, "map", MAP_UNIT, &material.map);
+                gl.disable(WebGlRenderingContext::CULL_FACE);
+            }
+            Material::Phong(material) => {
+                gl.uniform1f(program_info.uniform("pointSize"), 1.0);
+                gl.uniform1i(program_info.uniform("hasAmbient"), material.ambient.is_some() as i32);
+                gl.uniform3fv_with_f32_array(
+                    program_info.uniform("ambient"),
+                    &material.ambient.unwrap_or_default(),
                 );
+                gl.uniform3fv_with_f32_array(program_info.uniform("specular"), &material.specular);
+                gl.uniform1f(program_info.uniform("shininess"), material.shininess);
+                gl.uniform1f(program_info.uniform("bumpScale"), material.bump_scale);
+                self.bind_texture(program_info, "map", MAP_UNIT, &material.map);
+                self.bind_texture(program_info, "bumpMap", BUMP_MAP_UNIT, &material.bump_map);
+                gl.disable(WebGlRenderingContext::CULL_FACE);
             }
+            Material::Standard(material) => {
+                gl.uniform1f(program_info.uniform("pointSize"), 1.0);
+                gl.uniform1f(program_info.uniform("metallic"), material.metallic);
+                gl.uniform1f(program_info.uniform("roughness"), material.roughness);
+                gl.uniform3fv_with_f32_array(program_info.uniform("emissive"), &material.emissive);
+                gl.uniform1f(
+                    program_info.uniform("alphaCutoff"),
+                    material.alpha_cutoff.unwrap_or(0.0),
+                );
+                gl.uniform1i(program_info.uniform("transparent"), material.transparent as i32);
+                gl.uniform1i(program_info.uniform("doubleSided"), material.double_sided as i32);
+                gl.uniform1f(program_info.uniform("aoMapIntensity"), material.occlusion_strength);
+                self.bind_texture(program_info, "map", MAP_UNIT, &material.map);
+                self.bind_texture(
+                    program_info,
+                    "metallicRoughnessMap",
+                    METALLIC_ROUGHNESS_MAP_UNIT,
+                    &material.metallic_roughness_map,
+                );
+                self.bind_texture(program_info, "emissiveMap", EMISSIVE_MAP_UNIT, &material.emissive_map);
+                self.bind_texture(program_info, "aoMap", AO_MAP_UNIT, &material.occlusion_map);
+                // glTF normals point up the image, i.e. against v, the derivative
+                // tangent frame's bitangent follows v
+                gl.uniform2f(
+                    program_info.uniform("normalScale"),
+                    material.normal_scale,
+                    -material.normal_scale,
+                );
+                self.bind_texture(program_info, "normalMap", NORMAL_MAP_UNIT, &material.normal_map);
+                if material.double_sided {
+                    gl.disable(WebGlRenderingContext::CULL_FACE);
+                } else {
+                    gl.enable(WebGlRenderingContext::CULL_FACE);
+                }
+            }
+        }
+    }
 
-            /*======== Associating shaders to buffer objects ========*/
-            //顶点 start
-            // Bind vertex buffer object
-            self._gl.bind_buffer(
-                WebGlRenderingContext::ARRAY_BUFFER,
-                object.__webGLVertexBuffer.as_ref(),
-            );
+    /// Picks uniforms for the bone matrices when they fit next to the other
+    /// vertex uniforms of a program with `morph_targets`, a bone texture otherwise.
+    fn skinning(&self, skeleton: Option<&Skeleton>, morph_targets: usize) -> Result<Skinning> {
+        let bones = match skeleton {
+            Some(skeleton) => skeleton.bones.len(),
+            None => return Ok(Skinning::None),
+        };
+        // some drivers take a few vectors for themselves, don't fill the last ones
+        let max_bones = self
+            ._max_vertex_uniforms
+            .saturating_sub(vertex_uniform_vectors(morph_targets) + VERTEX_UNIFORM_MARGIN)
+            / 4;
+        if bones <= max_bones {
+            Ok(Skinning::Uniforms(bones.max(1)))
+        } else if self._float_textures {
+            Ok(Skinning::Texture)
+        } else {
+            Err(Error::Unsupported(format!(
+                "{} bones exceed the {} supported without float textures",
+                bones, max_bones
+            )))
+        }
+    }
 
-            // Get the attribute location
-            let position = self._gl.get_attrib_location(&self._program, "position") as u32;
+    /// Writes the bone matrices to the square float texture of `skeleton`, created
+    /// the first time, four texels per bone.
+    fn upload_bone_texture(&self, skeleton: &mut Skeleton) -> Result<()> {
+        let texels = (skeleton.bones.len() * 4) as f32;
+        let size = texels.sqrt().ceil().max(1.0) as u32;
+        let size = size.next_power_of_two();
+        let gl_texture = match skeleton.__webGLBoneTexture.take() {
+            Some((uploaded_size, gl_texture)) if uploaded_size == size => gl_texture,
+            Some((_, gl_texture)) => {
+                self._gl.delete_texture(Some(&gl_texture));
+                self.create_bone_texture()?
+            }
+            None => self.create_bone_texture()?,
+        };
 
-            // Point an attribute to the currently bound VBO
-            self._gl.vertex_attrib_pointer_with_i32(
-                position,
-                3,
-                WebGlRenderingContext::FLOAT,
-                false,
+        let mut data = skeleton.bone_matrices.clone();
+        data.resize((size * size * 4) as usize, 0.0);
+        let array = float_32_array!(data);
+        // WebGL2 wants a sized format for float data
+        let internal_format = if self.is_webgl2() {
+            WebGl2RenderingContext::RGBA32F
+        } else {
+            WebGlRenderingContext::RGBA
+        };
+        self._gl
+            .active_texture(WebGlRenderingContext::TEXTURE0 + BONE_TEXTURE_UNIT);
+        self._gl
+            .bind_texture(WebGlRenderingContext::TEXTURE_2D, Some(&gl_texture));
+        self._gl.pixel_storei(WebGlRenderingContext::UNPACK_FLIP_Y_WEBGL, 0);
+        self._gl
+            .tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_array_buffer_view(
+                WebGlRenderingContext::TEXTURE_2D,
                 0,
+                internal_format as i32,
+                size as i32,
+                size as i32,
                 0,
-            );
+                WebGlRenderingContext::RGBA,
+                WebGlRenderingContext::FLOAT,
+                Some(&array),
+            )
+            .map_err(|_| Error::Allocation(String::from("bone texture data")))?;
+        skeleton.__webGLBoneTexture = Some((size, gl_texture));
+        Ok(())
+    }
 
-            // Enable the attribute
-            self._gl.enable_vertex_attrib_array(position);
-            //顶点 end
+    fn create_bone_texture(&self) -> Result<WebGlTexture> {
+        let gl_texture = self
+            ._gl
+            .create_texture()
+            .ok_or_else(|| Error::Allocation(String::from("bone texture")))?;
+        let target = WebGlRenderingContext::TEXTURE_2D;
+        self._gl.bind_texture(target, Some(&gl_texture));
+        // float textures can't be filtered without another extension
+        for &parameter in &[
+            WebGlRenderingContext::TEXTURE_MIN_FILTER,
+            WebGlRenderingContext::TEXTURE_MAG_FILTER,
+        ] {
+            self._gl
+                .tex_parameteri(target, parameter, WebGlRenderingContext::NEAREST as i32);
+        }
+        for &parameter in &[
+            WebGlRenderingContext::TEXTURE_WRAP_S,
+            WebGlRenderingContext::TEXTURE_WRAP_T,
+        ] {
+            self._gl
+                .tex_parameteri(target, parameter, WebGlRenderingContext::CLAMP_TO_EDGE as i32);
+        }
+        Ok(gl_texture)
+    }
 
-            //法向量 start
-            self._gl.bind_buffer(
-                WebGlRenderingContext::ARRAY_BUFFER,
-                object.__webGLNormalBuffer.as_ref(),
-            );
+    /// Uploads this frame's pose of `skeleton` the way `skinning` reads it.
+    fn set_skeleton_uniforms(
+        &self,
+        program_info: &ProgramInfo,
+        skinning: Skinning,
+        skeleton: &mut Skeleton,
+    ) -> Result<()> {
+        match skinning {
+            Skinning::None => {}
+            Skinning::Uniforms(_) => {
+                if !skeleton.bone_matrices.is_empty() {
+                    self._gl.uniform_matrix4fv_with_f32_array(
+                        program_info.uniform("boneMatrices"),
+                        false,
+                        &skeleton.bone_matrices,
+                    );
+                }
+            }
+            Skinning::Texture => {
+                self.upload_bone_texture(skeleton)?;
+                let size = skeleton
+                    .__webGLBoneTexture
+                    .as_ref()
+                    .map(|(size, _)| *size)
+                    .unwrap_or(1);
+                self._gl
+                    .uniform1i(program_info.uniform("boneTexture"), BONE_TEXTURE_UNIT as i32);
+                self._gl
+                    .uniform1f(program_info.uniform("boneTextureSize"), size as f32);
+            }
+        }
+        Ok(())
+    }
 
-            let normal = self._gl.get_attrib_location(&self._program, "normal") as u32;
+    pub fn render(&mut self, scene: &mut Scene, camera: &mut Camera) -> Result<()> {
+        set_panic_hook();
 
-            self._gl.vertex_attrib_pointer_with_i32(
-                normal,
-                3,
-                WebGlRenderingContext::FLOAT,
-                false,
-                0,
-                0,
-            );
+        if self.auto_clear {
+            self.clear();
+        }
 
-            self._gl.enable_vertex_attrib_array(normal);
-            //法向量 end
+        scene.update_matrix_world();
+        let lights = LightUniforms::from_lights(&scene.lights(), &camera.matrix);
 
-            //颜色 start
-            // bind the color buffer
-            self._gl.bind_buffer(
-                WebGlRenderingContext::ARRAY_BUFFER,
-                object.__webGLColorBuffer.as_ref(),
-            );
+        let mut meshes = Vec::new();
+        let mut instanced_meshes = Vec::new();
+        scene
+            .root
+            .collect_renderables(&mut meshes, &mut instanced_meshes);
 
-            // get the attribute location
-            let color = self._gl.get_attrib_location(&self._program, "color") as u32;
+        self.render_meshes(&mut meshes, camera, &lights)?;
+        self.render_instanced_meshes(&mut instanced_meshes, camera, &lights)?;
 
-            // point attribute to the volor buffer object
-            self._gl.vertex_attrib_pointer_with_i32(
-                color,
-                4,
-                WebGlRenderingContext::FLOAT,
-                false,
-                0,
-                0,
-            );
+        Ok(())
+    }
 
-            // enable the color attribute
-            self._gl.enable_vertex_attrib_array(color);
-            //颜色 end
+    fn render_meshes(
+        &mut self,
+        objects: &mut Vec<(&[f32; 16], &mut Mesh, Option<&mut Skeleton>)>,
+        camera: &Camera,
+        lights: &LightUniforms,
+    ) -> Result<()> {
+        let light_counts = lights.counts();
+        let mut plans = Vec::with_capacity(objects.len());
+        for (_, object, skeleton) in objects.iter() {
+            let morphing = self.morphing(object);
+            let (morph_targets, morph_normals) = match morphing {
+                Morphing::Gpu(_) => self.morph_slots(&object.geometry),
+                _ => (0, false),
+            };
+            let skeleton = skeleton.as_ref().map(|skeleton| &**skeleton);
+            let skinning = self.skinning(skeleton, morph_targets)?;
+            let key = ProgramKey {
+                instanced: false,
+                lights: light_counts,
+                material: object.material.material_type(),
+                maps: material_maps(&object.material, self._standard_derivatives),
+                skinning,
+                morph_targets,
+                morph_normals,
+            };
+            self.prepare_program(key)?;
+            self.upload_material_textures(&object.material)?;
+            plans.push((key, morphing));
+        }
+
+        let mut current_key = None;
+        for ((matrix_world, object, skeleton), (key, morphing)) in
+            objects.iter_mut().zip(plans.iter())
+        {
+            let key = *key;
+            let program_info = &self._programs[&key];
+            if current_key != Some(key) {
+                self.use_program(program_info, camera, lights)?;
+                current_key = Some(key);
+            }
+
+            if object.__webGLBuffers.is_empty() {
+                /*==========Defining and storing the geometry=======*/
+                object.__webGLBuffers =
+                    self.create_geometry_buffers(&object.geometry, object.mode)?;
+            }
 
             /*========================= MATRIX ========================= */
-            mat4::compose(
-                &mut object.matrix,
-                &object.position,
-                &object.scale,
-                &object.rotation,
-            );
+            self.set_object_uniforms(program_info, camera, matrix_world, &object.material);
+            if let Some(skeleton) = skeleton {
+                self.set_skeleton_uniforms(program_info, key.skinning, skeleton)?;
+            }
+            let morphed = object.geometry.morph_target_count() > 0;
+            match morphing {
+                Morphing::Gpu(chosen) => {
+                    let influences: Vec<f32> = (0..key.morph_targets)
+                        .map(|slot| {
+                            chosen
+                                .get(slot)
+                                .map_or(0.0, |&target| object.morph_target_influences[target])
+                        })
+                        .collect();
+                    self._gl.uniform1fv_with_f32_array(
+                        program_info.uniform("morphTargetInfluences"),
+                        &influences,
+                    );
+                }
+                Morphing::Cpu => self.upload_morphed_attributes(object)?,
+                Morphing::None => {}
+            }
 
-            let mov_matrix: [f32; 16] = object.matrix;
+            for buffers in object.__webGLBuffers.iter_mut() {
+                /*======== Associating shaders to buffer objects ========*/
+                self.bind_geometry(buffers, key, |buffers| {
+                    //顶点, 法向量, 颜色, 纹理坐标...
+                    self.bind_attributes(program_info, buffers)?;
 
-            let view_matrix: [f32; 16] = camera.matrix;
+                    self._gl.bind_buffer(
+                        WebGlRenderingContext::ELEMENT_ARRAY_BUFFER,
+                        Some(&buffers.face),
+                    );
+                    Ok(())
+                })?;
+                if morphed {
+                    self.bind_morph_attributes(program_info, buffers, morphing, key.morph_targets);
+                }
 
-            let proj_matrix: [f32; 16] = camera.projection_matrix;
+                /*============= Drawing the primitive ===============*/
+                // web_sys::console::log_1(&"count:".into());
+                // log_num(buffers.count as f64);
 
-            // let proj_matrix_js = float_32_array!(&proj_matrix);
-            // web_sys::console::log_1(&proj_matrix_js);
+                self._gl.draw_elements_with_i32(
+                    object.mode.to_gl(),
+                    buffers.count,
+                    buffers.index_type,
+                    0,
+                );
+            }
+        }
+        self.bind_vertex_array(None);
 
-            // let view_matrix_js = float_32_array!(&view_matrix);
-            // web_sys::console::log_1(&view_matrix_js);
+        Ok(())
+    }
 
-            let Pmatrix: WebGlUniformLocation = self
-                ._gl
-                .get_uniform_location(&self._program, "Pmatrix")
-                .ok_or_else(|| String::from("cannot get Pmatrix"))
-                .unwrap();
-            let Vmatrix = self
-                ._gl
-                .get_uniform_location(&self._program, "Vmatrix")
-                .ok_or_else(|| String::from("cannot get Vmatrix"))
-                .unwrap();
-            let Mmatrix = self
-                ._gl
-                .get_uniform_location(&self._program, "Mmatrix")
-                .ok_or_else(|| String::from("cannot get Mmatrix"))
-                .unwrap();
+    fn render_instanced_meshes(
+        &mut self,
+        objects: &mut Vec<(&[f32; 16], &mut InstancedMesh)>,
+        camera: &Camera,
+        lights: &LightUniforms,
+    ) -> Result<()> {
+        if objects.is_empty() {
+            return Ok(());
+        }
 
-            /*============= Drawing the primitive ===============*/
+        let light_counts = lights.counts();
+        let standard_derivatives = self._standard_derivatives;
+        let program_key = |material: &Material| ProgramKey {
+            instanced: true,
+            lights: light_counts,
+            material: material.material_type(),
+            maps: material_maps(material, standard_derivatives),
+            skinning: Skinning::None,
+            morph_targets: 0,
+            morph_normals: false,
+        };
+        for (_, object) in objects.iter() {
+            self.prepare_program(program_key(&object.material))?;
+            self.upload_material_textures(&object.material)?;
+        }
 
-            self._gl
-                .uniform_matrix4fv_with_f32_array(Some(&Pmatrix), false, &proj_matrix);
-            self._gl
-                .uniform_matrix4fv_with_f32_array(Some(&Vmatrix), false, &view_matrix);
-            self._gl
-                .uniform_matrix4fv_with_f32_array(Some(&Mmatrix), false, &mov_matrix);
-            // Draw the triangle
-            let count = object.geometry.indices.len();
-            // web_sys::console::log_1(&"count:".into());
-            // log_num(count as f64);
-
-            self._gl.draw_elements_with_i32(
-                WebGlRenderingContext::TRIANGLES,
-                count as i32,
-                WebGlRenderingContext::UNSIGNED_SHORT,
-                0,
-            );
+        let instanced = !matches!(self._instancing, Instancing::Unsupported);
+
+        // (key, instanceMatrix location, instanceColor location) of the current program
+        let mut current: Option<(ProgramKey, u32, u32)> = None;
+        for (matrix_world, object) in objects.iter_mut() {
+            let key = program_key(&object.material);
+            let program_info = &self._programs[&key];
+            let (instance_matrix, instance_color) = match current {
+                Some((current_key, instance_matrix, instance_color)) if current_key == key => {
+                    (instance_matrix, instance_color)
+                }
+                _ => {
+                    if let Some((_, instance_matrix, instance_color)) = current {
+                        self.reset_instance_attributes(instance_matrix, instance_color);
+                    }
+                    self.use_program(program_info, camera, lights)?;
+
+                    // a mat4 attribute takes four consecutive locations, one per column
+                    let instance_matrix = program_info
+                        .attribute("instanceMatrix")
+                        .ok_or_else(|| Error::Link(String::from("cannot get instanceMatrix")))?;
+                    let instance_color = program_info
+                        .attribute("instanceColor")
+                        .ok_or_else(|| Error::Link(String::from("cannot get instanceColor")))?;
+                    current = Some((key, instance_matrix, instance_color));
+                    (instance_matrix, instance_color)
+                }
+            };
+
+            if object.__webGLBuffers.is_empty() {
+                object.__webGLBuffers =
+                    self.create_geometry_buffers(&object.geometry, DrawMode::Triangles)?;
+            }
+
+            if object.needs_update {
+                let had_colors = object.__webGLInstanceColorBuffer.is_some();
+                object.__webGLInstanceMatrixBuffer = Some(self.update_array_buffer(
+                    object.__webGLInstanceMatrixBuffer.take(),
+                    &object.instance_matrices,
+                )?);
+                object.__webGLInstanceColorBuffer = match &object.instance_colors {
+                    Some(colors) => Some(self.update_array_buffer(
+                        object.__webGLInstanceColorBuffer.take(),
+                        colors,
+                    )?),
+                    None => None,
+                };
+                // the recorded vaos enable a different set of arrays now
+                if had_colors != object.__webGLInstanceColorBuffer.is_some() {
+                    for buffers in object.__webGLBuffers.iter_mut() {
+                        buffers.vao = None;
+                    }
+                }
+                object.needs_update = false;
+            }
+
+            self.set_object_uniforms(program_info, camera, matrix_world, &object.material);
+
+            let instance_count = object.count() as i32;
+            let instance_matrix_buffer = object.__webGLInstanceMatrixBuffer.as_ref();
+            let instance_color_buffer = object.__webGLInstanceColorBuffer.as_ref();
+
+            let setup = |buffers: &GeometryBuffers| -> Result<()> {
+                self.bind_attributes(program_info, buffers)?;
+                self._gl.bind_buffer(
+                    WebGlRenderingContext::ELEMENT_ARRAY_BUFFER,
+                    Some(&buffers.face),
+                );
+
+                if !instanced {
+                    // per-instance values are fed as constant attributes instead
+                    for location in instance_matrix..instance_matrix + 4 {
+                        self._gl.disable_vertex_attrib_array(location);
+                    }
+                    self._gl.disable_vertex_attrib_array(instance_color);
+                    return Ok(());
+                }
+
+                self._gl
+                    .bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, instance_matrix_buffer);
+                for column in 0..4 {
+                    let location = instance_matrix + column;
+                    self._gl.vertex_attrib_pointer_with_i32(
+                        location,
+                        4,
+                        WebGlRenderingContext::FLOAT,
+                        false,
+                        64,
+                        column as i32 * 16,
+                    );
+                    self._gl.enable_vertex_attrib_array(location);
+                    self.vertex_attrib_divisor(location, 1);
+                }
+
+                match instance_color_buffer {
+                    Some(buffer) => {
+                        self.bind_attribute(program_info, "instanceColor", Some(buffer), 4)?;
+                        self.vertex_attrib_divisor(instance_color, 1);
+                    }
+                    None => self._gl.disable_vertex_attrib_array(instance_color),
+                }
+                Ok(())
+            };
+
+            // used whenever the instanceColor array is disabled
+            self._gl.vertex_attrib4f(instance_color, 1.0, 1.0, 1.0, 1.0);
+
+            for buffers in object.__webGLBuffers.iter_mut() {
+                self.bind_geometry(buffers, key, setup)?;
+
+                match &self._instancing {
+                    Instancing::Native(gl2) => gl2.draw_elements_instanced_with_i32(
+                        WebGlRenderingContext::TRIANGLES,
+                        buffers.count,
+                        buffers.index_type,
+                        0,
+                        instance_count,
+                    ),
+                    Instancing::Angle(ext) => ext.draw_elements_instanced_angle_with_i32(
+                        WebGlRenderingContext::TRIANGLES,
+                        buffers.count,
+                        buffers.index_type,
+                        0,
+                        instance_count,
+                    ),
+                    Instancing::Unsupported => {
+                        // one draw per instance
+                        for (index, matrix) in object.instance_matrices.chunks(16).enumerate() {
+                            for column in 0..4 {
+                                let offset = column * 4;
+                                self._gl.vertex_attrib4fv_with_f32_array(
+                                    instance_matrix + column as u32,
+                                    &matrix[offset..offset + 4],
+                                );
+                            }
+                            if let Some(colors) = &object.instance_colors {
+                                self._gl.vertex_attrib4fv_with_f32_array(
+                                    instance_color,
+                                    &colors[index * 4..index * 4 + 4],
+                                );
+                            }
+                            self._gl.draw_elements_with_i32(
+                                WebGlRenderingContext::TRIANGLES,
+                                buffers.count,
+                                buffers.index_type,
+                                0,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        self.bind_vertex_array(None);
+        if let Some((_, instance_matrix, instance_color)) = current {
+            self.reset_instance_attributes(instance_matrix, instance_color);
         }
 
         Ok(())
     }
+
+    /// Leaves no instanced arrays behind for programs that don't declare them. Only
+    /// needed without vertex arrays, otherwise the state lives in the vaos.
+    fn reset_instance_attributes(&self, instance_matrix: u32, instance_color: u32) {
+        if let VertexArrays::Unsupported = self._vertex_arrays {
+            for location in (instance_matrix..instance_matrix + 4).chain(Some(instance_color)) {
+                self.vertex_attrib_divisor(location, 0);
+                self._gl.disable_vertex_attrib_array(location);
+            }
+        }
+    }
 }